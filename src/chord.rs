@@ -0,0 +1,171 @@
+//! This module defines chord symbols, such as `Cmaj7`, `Am` or `G7/B`, which
+//! can stand in the pitch column for all the notes of the chord at once. How
+//! the notes are laid out is up to the voicing set by the latest `VOICING`
+//! directive: the octave that the root sits in, and how the rest are spread
+//! above it. An `ARP` directive can also stagger the notes of later chords,
+//! as if they were strummed, rather than striking them all at once.
+
+use crate::error::SyntaxErrorType;
+use crate::meter::{parse_ticks, Meter};
+use crate::note::{KeySignature, Note};
+
+#[derive(Clone, Copy)]
+pub enum VoicingStyle {
+    // every note as close above the root as it can be
+    Close,
+    // the third (the second note up) raised an octave, for a wider sound
+    Open,
+    // the second note from the top dropped an octave
+    Drop2,
+}
+
+#[derive(Clone, Copy)]
+pub struct Voicing {
+    pub style: VoicingStyle,
+    pub octave: i32,
+}
+
+// how the notes of a chord are staggered: each starts `ticks` after the one
+// before it, lowest first unless `down`, so that no ticks at all strikes them
+// together
+#[derive(Clone, Copy, Default)]
+pub struct Arpeggio {
+    pub ticks: f64,
+    pub down: bool,
+}
+
+// every chord quality, as written after the root, with its notes given in
+// semitones above the root
+pub const QUALITIES: [(&str, &[i32]); 27] = [
+    ("", &[0, 4, 7]),
+    ("maj", &[0, 4, 7]),
+    ("m", &[0, 3, 7]),
+    ("min", &[0, 3, 7]),
+    ("dim", &[0, 3, 6]),
+    ("aug", &[0, 4, 8]),
+    ("+", &[0, 4, 8]),
+    ("sus2", &[0, 2, 7]),
+    ("sus4", &[0, 5, 7]),
+    ("sus", &[0, 5, 7]),
+    ("5", &[0, 7]),
+    ("6", &[0, 4, 7, 9]),
+    ("m6", &[0, 3, 7, 9]),
+    ("7", &[0, 4, 7, 10]),
+    ("dom7", &[0, 4, 7, 10]),
+    ("maj7", &[0, 4, 7, 11]),
+    ("M7", &[0, 4, 7, 11]),
+    ("m7", &[0, 3, 7, 10]),
+    ("min7", &[0, 3, 7, 10]),
+    ("mM7", &[0, 3, 7, 11]),
+    ("dim7", &[0, 3, 6, 9]),
+    ("m7b5", &[0, 3, 6, 10]),
+    ("aug7", &[0, 4, 8, 10]),
+    ("9", &[0, 4, 7, 10, 14]),
+    ("maj9", &[0, 4, 7, 11, 14]),
+    ("m9", &[0, 3, 7, 10, 14]),
+    ("add9", &[0, 4, 7, 14]),
+];
+
+impl Default for Voicing {
+    fn default() -> Self {
+        Self {
+            style: VoicingStyle::Close,
+            octave: 4,
+        }
+    }
+}
+
+impl Voicing {
+    // parse the arguments of a `VOICING <close|open|drop2> <octave>` directive
+    pub fn new(args: &[&str]) -> Result<Self, SyntaxErrorType> {
+        let bad = || SyntaxErrorType::BadChord(format!("VOICING {}", args.join(" ")));
+        let (style, octave) = match args[..] {
+            [style, octave] => (style, octave.parse::<i8>().map_err(|_| bad())? as i32),
+            _ => return Err(bad()),
+        };
+        let style = match style {
+            "close" => VoicingStyle::Close,
+            "open" => VoicingStyle::Open,
+            "drop2" => VoicingStyle::Drop2,
+            _ => return Err(bad()),
+        };
+        Ok(Self { style, octave })
+    }
+}
+
+impl Arpeggio {
+    // parse the arguments of an `ARP <ticks> [up|down]` directive, with the
+    // ticks written at the meter's resolution
+    pub fn new(args: &[&str], meter: &Meter) -> Result<Self, SyntaxErrorType> {
+        let bad = || SyntaxErrorType::BadChord(format!("ARP {}", args.join(" ")));
+        let (ticks, down) = match args[..] {
+            [ticks] | [ticks, "up"] => (ticks, false),
+            [ticks, "down"] => (ticks, true),
+            _ => return Err(bad()),
+        };
+        let ticks = meter.ticks(parse_ticks(ticks).ok_or_else(bad)?);
+        Ok(Self { ticks, down })
+    }
+
+    // how many ticks after the chord's start the note at `index` (lowest
+    // first) of its `count` notes starts
+    pub fn offset(&self, index: usize, count: usize) -> f64 {
+        let place = if self.down { count - 1 - index } else { index };
+        place as f64 * self.ticks
+    }
+}
+
+// the notes of a chord symbol, lowest first, with the root in the voicing's
+// octave, unless the symbol ends in `:<octave>` to say otherwise; a bass note
+// after a slash (as in `G7/B`) goes below the rest of the chord
+pub fn chord_notes(symbol: &str, voicing: &Voicing) -> Result<Vec<Note>, SyntaxErrorType> {
+    let bad = || SyntaxErrorType::BadChord(symbol.to_string());
+    let (chord, octave) = match symbol.split_once(':') {
+        Some((chord, octave)) => (chord, octave.parse::<i8>().map_err(|_| bad())? as i32),
+        None => (symbol, voicing.octave),
+    };
+    let (chord, bass) = match chord.split_once('/') {
+        Some((chord, bass)) => (chord, Some(pitch_class(bass).ok_or_else(bad)?)),
+        None => (chord, None),
+    };
+    // the root is a letter, and perhaps a sharp or a flat; the split has to
+    // fall after a whole character, even when the first isn't a letter at all
+    let first = chord.chars().next().map_or(0, char::len_utf8);
+    let split = chord
+        .char_indices()
+        .nth(1)
+        .filter(|&(_, ch)| ch == '#' || ch == 'b')
+        .map_or(first, |(i, _)| i + 1);
+    let (root, quality) = chord.split_at(split);
+    let root = pitch_class(root).ok_or_else(bad)? + 12 * octave;
+    let intervals = QUALITIES
+        .iter()
+        .find(|(name, _)| *name == quality)
+        .ok_or_else(bad)?
+        .1;
+
+    let mut semitones = intervals.iter().map(|i| root + i).collect::<Vec<_>>();
+    let len = semitones.len();
+    match voicing.style {
+        VoicingStyle::Close => {}
+        VoicingStyle::Open if len > 2 => semitones[1] += 12,
+        VoicingStyle::Drop2 if len > 2 => semitones[len - 2] -= 12,
+        _ => {}
+    }
+    semitones.sort();
+    if let Some(bass) = bass {
+        let lowest = semitones[0];
+        semitones.insert(0, lowest - 1 - (lowest - 1 - bass).rem_euclid(12));
+    }
+    semitones
+        .into_iter()
+        .map(|semitone| Note::from_semitones(semitone).ok_or_else(bad))
+        .collect()
+}
+
+// the semitones above C of a root or bass note written as a letter and an
+// optional accidental, which may take it below C (as for `Cb`) or above B
+fn pitch_class(name: &str) -> Option<i32> {
+    let note = Note::from_name(name, 0, &KeySignature::default()).ok()?;
+    Some(note.semitones())
+}
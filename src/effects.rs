@@ -0,0 +1,519 @@
+//! This module defines the effects that can be run over the whole of a piece
+//! once it has been synthesized, in the order the piece lists them. Each one
+//! works on the interleaved buffer of samples, and may lengthen it to make
+//! room for a tail (like the dying echoes of a reverb or a delay). The fades
+//! at either end of a piece come after all of them.
+
+use crate::error::SyntaxErrorType;
+use crate::filter::{Biquad, Filter, FilterKind};
+use crate::meter::PPQ;
+use crate::signal::NoteDuration;
+
+use std::f64::consts::{FRAC_1_SQRT_2, PI};
+use std::str::FromStr;
+
+#[derive(Clone, Copy)]
+pub enum Effect {
+    // `size` (0 to 1) sets how long the reverb rings, `damp` (0 to 1) how
+    // quickly its high end dies away, and `mix` (0 to 1) how much of it is
+    // heard against the dry signal
+    Reverb {
+        size: f64,
+        damp: f64,
+        mix: f64,
+    },
+    // echoes every `ticks`, each `feedback` (0 to MAX_FEEDBACK) times as loud
+    // as the one before it, heard at `mix` (0 to 1) against the dry signal
+    Delay {
+        ticks: f64,
+        feedback: f64,
+        mix: f64,
+    },
+    // a copy of the signal delayed by an amount that wobbles at `rate` Hz
+    // through `depth` milliseconds, heard at `mix` (0 to 1) against the dry
+    // signal; in stereo, the wobble in each channel is a quarter-cycle apart
+    Chorus {
+        rate: f64,
+        depth: f64,
+        mix: f64,
+    },
+    // the signal turned up by `drive` and then squashed back into full scale,
+    // smoothly or by clipping it, with `tone` (0 to 1) setting how much of
+    // the resulting high end is kept
+    Distortion {
+        clip: bool,
+        drive: f64,
+        tone: f64,
+    },
+    // anything louder than `threshold` dBFS is turned down so that it only
+    // rises 1 dB for every `ratio` dB it would have, with the gain falling
+    // over `attack` and recovering over `release` milliseconds
+    Compressor {
+        threshold: f64,
+        ratio: f64,
+        attack: f64,
+        release: f64,
+    },
+    // one band of an equalizer: a low shelf, a peak or a high shelf
+    Equalizer(Filter),
+}
+
+// how long a fade at the start or end of a piece lasts: a plain number is in
+// seconds, and anything else is read as a note duration, keeping time with
+// the piece
+#[derive(Clone, Copy, PartialEq)]
+pub enum FadeLength {
+    Seconds(f64),
+    Ticks(f64),
+}
+
+pub const DEFAULT_REVERB: Effect = Effect::Reverb {
+    size: 0.5,
+    damp: 0.5,
+    mix: 0.25,
+};
+
+// the delay lengths (in samples at 44.1kHz) of Freeverb's comb and allpass
+// filters, and how much longer they are in the right channel than the left
+const COMBS: [usize; 8] = [1116, 1188, 1277, 1356, 1422, 1491, 1557, 1617];
+const ALLPASSES: [usize; 4] = [556, 441, 341, 225];
+const STEREO_SPREAD: usize = 23;
+
+// the shortest delay (in milliseconds) that the chorus wobbles up from, and the
+// widest it can wobble by
+const CHORUS_DELAY: f64 = 10.0;
+const MAX_CHORUS_DEPTH: f64 = 20.0;
+
+// the most that a delay's echoes can feed back; any closer to 1 and they take
+// so long to die away that the room left for them runs into gigabytes
+pub const MAX_FEEDBACK: f64 = 0.95;
+
+// the range that the tone of a distortion sweeps its lowpass cutoff (in Hz)
+// across, from darkest to brightest
+const TONE_RANGE: (f64, f64) = (200.0, 20000.0);
+
+impl Effect {
+    pub fn reverb(args: &[&str]) -> Result<Self, SyntaxErrorType> {
+        let bad = || SyntaxErrorType::BadEffect(format!("REVERB {}", args.join(" ")));
+        match parse_values(args).ok_or_else(bad)?[..] {
+            [size, damp, mix] if [size, damp, mix].iter().all(|v| (0.0..=1.0).contains(v)) => {
+                Ok(Self::Reverb { size, damp, mix })
+            }
+            _ => Err(bad()),
+        }
+    }
+
+    // parse `<duration> <feedback> <mix>`, where the duration is written just
+    // as a note's would be (at the given resolution, in ticks per quarter
+    // note), so that the echoes keep time with the piece
+    pub fn delay(args: &[&str], ppq: u32) -> Result<Self, SyntaxErrorType> {
+        let bad = || SyntaxErrorType::BadEffect(format!("DELAY {}", args.join(" ")));
+        let (duration, values) = args.split_first().ok_or_else(bad)?;
+        let ticks = NoteDuration::new(duration, ppq).map_err(|_| bad())?.ticks;
+        match parse_values(values).ok_or_else(bad)?[..] {
+            [feedback, mix]
+                if ticks > 0.0
+                    && (0.0..=MAX_FEEDBACK).contains(&feedback)
+                    && (0.0..=1.0).contains(&mix) =>
+            {
+                Ok(Self::Delay {
+                    ticks,
+                    feedback,
+                    mix,
+                })
+            }
+            _ => Err(bad()),
+        }
+    }
+
+    pub fn chorus(args: &[&str]) -> Result<Self, SyntaxErrorType> {
+        let bad = || SyntaxErrorType::BadEffect(format!("CHORUS {}", args.join(" ")));
+        match parse_values(args).ok_or_else(bad)?[..] {
+            [rate, depth, mix]
+                if rate > 0.0
+                    && (0.0..=MAX_CHORUS_DEPTH).contains(&depth)
+                    && (0.0..=1.0).contains(&mix) =>
+            {
+                Ok(Self::Chorus { rate, depth, mix })
+            }
+            _ => Err(bad()),
+        }
+    }
+
+    // parse `<TANH|CLIP> <drive> <tone>`, where the drive is at least 1
+    pub fn distortion(args: &[&str]) -> Result<Self, SyntaxErrorType> {
+        let bad = || SyntaxErrorType::BadEffect(format!("DISTORTION {}", args.join(" ")));
+        let (shape, values) = args.split_first().ok_or_else(bad)?;
+        let clip = match shape.to_uppercase().as_str() {
+            "TANH" => false,
+            "CLIP" => true,
+            _ => return Err(bad()),
+        };
+        match parse_values(values).ok_or_else(bad)?[..] {
+            [drive, tone] if drive >= 1.0 && (0.0..=1.0).contains(&tone) => {
+                Ok(Self::Distortion { clip, drive, tone })
+            }
+            _ => Err(bad()),
+        }
+    }
+
+    // parse `<threshold> <ratio> <attack> <release>`
+    pub fn compressor(args: &[&str]) -> Result<Self, SyntaxErrorType> {
+        let bad = || SyntaxErrorType::BadEffect(format!("COMPRESS {}", args.join(" ")));
+        match parse_values(args).ok_or_else(bad)?[..] {
+            [threshold, ratio, attack, release]
+                if threshold <= 0.0 && ratio >= 1.0 && attack >= 0.0 && release >= 0.0 =>
+            {
+                Ok(Self::Compressor {
+                    threshold,
+                    ratio,
+                    attack,
+                    release,
+                })
+            }
+            _ => Err(bad()),
+        }
+    }
+
+    // parse `<LOW|HIGH> <freq> <gain>` for a shelf, or `MID <freq> <gain> <q>`
+    // for a peak, with the gain in dB
+    pub fn equalizer(args: &[&str]) -> Result<Self, SyntaxErrorType> {
+        let bad = || SyntaxErrorType::BadEffect(format!("EQ {}", args.join(" ")));
+        let (band, values) = args.split_first().ok_or_else(bad)?;
+        let (kind, cutoff, resonance) = match (
+            band.to_uppercase().as_str(),
+            &parse_values(values).ok_or_else(bad)?[..],
+        ) {
+            ("LOW", &[cutoff, gain]) => (FilterKind::LowShelf(gain), cutoff, FRAC_1_SQRT_2),
+            ("HIGH", &[cutoff, gain]) => (FilterKind::HighShelf(gain), cutoff, FRAC_1_SQRT_2),
+            ("MID", &[cutoff, gain, q]) => (FilterKind::Peak(gain), cutoff, q),
+            _ => return Err(bad()),
+        };
+        if cutoff <= 0.0 || resonance <= 0.0 {
+            return Err(bad());
+        }
+        Ok(Self::Equalizer(Filter {
+            kind,
+            cutoff,
+            resonance,
+        }))
+    }
+
+    // parse a chain of effects written as `<effect> -> <effect> -> ...`, where
+    // each effect is written just as its directive would be, or by name alone
+    // (e.g. `DELAY`) to take some moderate settings
+    pub fn chain(args: &[&str], ppq: u32) -> Result<Vec<Self>, SyntaxErrorType> {
+        if args.is_empty() {
+            return Ok(vec![]);
+        }
+        args.join(" ")
+            .split("->")
+            .map(|part| {
+                let words = part.split_whitespace().collect::<Vec<_>>();
+                let (name, args) = words
+                    .split_first()
+                    .ok_or_else(|| SyntaxErrorType::BadEffect(args.join(" ")))?;
+                Self::parse(&name.to_uppercase(), args, ppq)
+            })
+            .collect()
+    }
+
+    fn parse(name: &str, args: &[&str], ppq: u32) -> Result<Self, SyntaxErrorType> {
+        let defaults: &[&str] = match name {
+            "REVERB" if args.is_empty() => return Ok(DEFAULT_REVERB),
+            "DELAY" => &["E", "0.4", "0.3"],
+            "CHORUS" => &["0.8", "5", "0.5"],
+            "DISTORTION" => &["TANH", "4", "0.5"],
+            "COMPRESS" => &["-18", "4", "10", "150"],
+            _ => &[],
+        };
+        let args = if args.is_empty() { defaults } else { args };
+        match name {
+            "REVERB" => Self::reverb(args),
+            "DELAY" => Self::delay(args, ppq),
+            "CHORUS" => Self::chorus(args),
+            "DISTORTION" => Self::distortion(args),
+            "COMPRESS" => Self::compressor(args),
+            "EQ" => Self::equalizer(args),
+            _ => Err(SyntaxErrorType::BadEffect(name.to_string())),
+        }
+    }
+
+    pub fn is_reverb(&self) -> bool {
+        matches!(self, Self::Reverb { .. })
+    }
+
+    pub fn is_compressor(&self) -> bool {
+        matches!(self, Self::Compressor { .. })
+    }
+
+    // how many frames the effect lengthens a buffer by to make room for its
+    // tail, as `apply()` will
+    pub fn tail(&self, rate: u32, samples_per_tick: f64) -> usize {
+        match *self {
+            Self::Reverb { size, .. } => reverb_tail(rate, size),
+            Self::Delay {
+                ticks, feedback, ..
+            } => delay_length(ticks, samples_per_tick).saturating_mul(delay_echoes(feedback)),
+            _ => 0,
+        }
+    }
+
+    // `samples_per_tick` turns the tempo-synced times of some effects into
+    // sample counts
+    pub fn apply(&self, samples: &mut Vec<f64>, channels: usize, rate: u32, samples_per_tick: f64) {
+        match *self {
+            Self::Reverb { size, damp, mix } => reverb(samples, channels, rate, size, damp, mix),
+            Self::Delay {
+                ticks,
+                feedback,
+                mix,
+            } => {
+                let length = delay_length(ticks, samples_per_tick);
+                delay(samples, channels, length, feedback, mix)
+            }
+            Self::Chorus {
+                rate: lfo,
+                depth,
+                mix,
+            } => chorus(samples, channels, rate, lfo, depth, mix),
+            Self::Distortion { clip, drive, tone } => {
+                distortion(samples, channels, rate, clip, drive, tone)
+            }
+            Self::Compressor {
+                threshold,
+                ratio,
+                attack,
+                release,
+            } => {
+                let ms = |time: f64| f64::exp(-1.0 / (time * rate as f64 / 1000.0).max(1.0));
+                compressor(samples, channels, threshold, ratio, ms(attack), ms(release))
+            }
+            Self::Equalizer(filter) => {
+                for channel in 0..channels {
+                    let mut biquad = Biquad::default();
+                    biquad.tune(&filter, rate);
+                    for sample in samples.iter_mut().skip(channel).step_by(channels) {
+                        *sample = biquad.process(*sample);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl FadeLength {
+    pub fn samples(&self, rate: u32, samples_per_tick: f64) -> usize {
+        match *self {
+            Self::Seconds(seconds) => (seconds * rate as f64).round() as usize,
+            Self::Ticks(ticks) => (ticks * samples_per_tick).round() as usize,
+        }
+    }
+}
+
+impl FromStr for FadeLength {
+    type Err = SyntaxErrorType;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.parse::<f64>() {
+            Ok(seconds) if seconds.is_finite() && seconds >= 0.0 => Ok(Self::Seconds(seconds)),
+            Ok(_) => Err(SyntaxErrorType::BadDuration(s.to_string())),
+            // a bare number has already been taken as seconds, so the
+            // resolution of ticks makes no difference here
+            Err(_) => NoteDuration::new(s, PPQ).map(|duration| Self::Ticks(duration.ticks)),
+        }
+    }
+}
+
+// ramp the first `fade_in` and the last `fade_out` frames of the samples
+// linearly up from and down to silence
+pub fn fade(samples: &mut [f64], channels: usize, fade_in: usize, fade_out: usize) {
+    let frames = samples.len() / channels;
+    for (n, frame) in samples.chunks_mut(channels).enumerate() {
+        let rising = if n < fade_in {
+            n as f64 / fade_in as f64
+        } else {
+            1.0
+        };
+        let remaining = frames - n - 1;
+        let falling = if remaining < fade_out {
+            remaining as f64 / fade_out as f64
+        } else {
+            1.0
+        };
+        frame.iter_mut().for_each(|s| *s *= rising.min(falling));
+    }
+}
+
+fn parse_values(args: &[&str]) -> Option<Vec<f64>> {
+    args.iter()
+        .map(|s| s.parse::<f64>().ok().filter(|v| v.is_finite()))
+        .collect()
+}
+
+// a Schroeder-style reverb, laid out as in Freeverb: eight damped feedback
+// comb filters in parallel, followed by four allpass filters in series, run
+// separately over each channel
+fn reverb(samples: &mut Vec<f64>, channels: usize, rate: u32, size: f64, damp: f64, mix: f64) {
+    let feedback = reverb_feedback(size);
+    let damp = 0.4 * damp;
+    samples.resize(samples.len() + reverb_tail(rate, size) * channels, 0.0);
+
+    for channel in 0..channels {
+        let spread = if channel % 2 == 1 { STEREO_SPREAD } else { 0 };
+        let mut combs = COMBS
+            .iter()
+            .map(|&len| (vec![0.0; scale_to(len + spread, rate)], 0, 0.0))
+            .collect::<Vec<_>>();
+        let mut allpasses = ALLPASSES
+            .iter()
+            .map(|&len| (vec![0.0; scale_to(len + spread, rate)], 0))
+            .collect::<Vec<_>>();
+
+        for sample in samples.iter_mut().skip(channel).step_by(channels) {
+            // the input is turned down, as the combs add up to a lot of gain
+            let input = *sample * 0.015;
+            let mut wet = 0.0;
+            for (buffer, position, filtered) in combs.iter_mut() {
+                let out = buffer[*position];
+                *filtered = out * (1.0 - damp) + *filtered * damp;
+                buffer[*position] = input + *filtered * feedback;
+                *position = (*position + 1) % buffer.len();
+                wet += out;
+            }
+            for (buffer, position) in allpasses.iter_mut() {
+                let delayed = buffer[*position];
+                buffer[*position] = wet + delayed * 0.5;
+                *position = (*position + 1) % buffer.len();
+                wet = delayed - wet;
+            }
+            *sample = *sample * (1.0 - mix) + wet * 3.0 * mix;
+        }
+    }
+}
+
+// how much the combs of a reverb of the given size feed back
+fn reverb_feedback(size: f64) -> f64 {
+    0.7 + 0.28 * size
+}
+
+// a length in samples at 44.1kHz, scaled to the given sample rate
+fn scale_to(len: usize, rate: u32) -> usize {
+    (len as f64 * rate as f64 / 44100.0).round().max(1.0) as usize
+}
+
+// the frames that a reverb of the given size needs after the end of the
+// signal, for its longest comb to die away to about -60dB
+fn reverb_tail(rate: u32, size: f64) -> usize {
+    let longest = scale_to(COMBS[7] + STEREO_SPREAD, rate);
+    (longest as f64 * (0.001f64).ln() / reverb_feedback(size).ln()).ceil() as usize
+}
+
+// the length in samples of a delay of the given ticks
+fn delay_length(ticks: f64, samples_per_tick: f64) -> usize {
+    (ticks * samples_per_tick).round().max(1.0) as usize
+}
+
+// how many echoes a delay with the given feedback needs room for after the
+// end of the signal, for them to die away to about -60dB
+fn delay_echoes(feedback: f64) -> usize {
+    if feedback > 0.0 {
+        ((0.001f64).ln() / feedback.ln()).ceil() as usize
+    } else {
+        1
+    }
+}
+
+// a feedback delay line, one per channel, `length` samples long
+fn delay(samples: &mut Vec<f64>, channels: usize, length: usize, feedback: f64, mix: f64) {
+    samples.resize(
+        samples.len() + length * delay_echoes(feedback) * channels,
+        0.0,
+    );
+
+    for channel in 0..channels {
+        let mut buffer = vec![0.0; length];
+        let mut position = 0;
+        for sample in samples.iter_mut().skip(channel).step_by(channels) {
+            let delayed = buffer[position];
+            buffer[position] = *sample + delayed * feedback;
+            position = (position + 1) % length;
+            *sample += delayed * mix;
+        }
+    }
+}
+
+// a delay line per channel, read at a point that an LFO sweeps back and forth,
+// interpolating linearly between samples
+fn chorus(samples: &mut [f64], channels: usize, rate: u32, lfo: f64, depth: f64, mix: f64) {
+    let ms = rate as f64 / 1000.0;
+    let length = ((CHORUS_DELAY + depth) * ms).ceil() as usize + 2;
+    for channel in 0..channels {
+        let offset = channel as f64 * PI / 2.0;
+        let mut buffer = vec![0.0; length];
+        let mut position = 0;
+        for (n, sample) in samples
+            .iter_mut()
+            .skip(channel)
+            .step_by(channels)
+            .enumerate()
+        {
+            buffer[position] = *sample;
+            let sweep = (1.0 + f64::sin(2.0 * PI * lfo * n as f64 / rate as f64 + offset)) / 2.0;
+            let lag = (CHORUS_DELAY + depth * sweep) * ms;
+            let back = position as f64 - lag + length as f64;
+            let (i, frac) = (back.floor() as usize % length, back.fract());
+            let delayed = buffer[i] + (buffer[(i + 1) % length] - buffer[i]) * frac;
+            position = (position + 1) % length;
+            *sample = *sample * (1.0 - mix) + delayed * mix;
+        }
+    }
+}
+
+// waveshaping, then a one-pole lowpass per channel to tame the harmonics it
+// adds; either shape leaves a full-scale signal at full scale
+fn distortion(samples: &mut [f64], channels: usize, rate: u32, clip: bool, drive: f64, tone: f64) {
+    let (low, high) = TONE_RANGE;
+    let cutoff = (low * (high / low).powf(tone)).min(rate as f64 / 2.0);
+    let smoothing = f64::exp(-2.0 * PI * cutoff / rate as f64);
+    for channel in 0..channels {
+        let mut last = 0.0;
+        for sample in samples.iter_mut().skip(channel).step_by(channels) {
+            let shaped = if clip {
+                (*sample * drive).clamp(-1.0, 1.0)
+            } else {
+                f64::tanh(*sample * drive) / drive.tanh()
+            };
+            last = shaped * (1.0 - smoothing) + last * smoothing;
+            *sample = last;
+        }
+    }
+}
+
+// a feed-forward compressor, linked across channels so that it doesn't shift
+// the stereo image: the level of each frame is its loudest channel, followed
+// in dB by an envelope that rises at the attack rate and falls at the release
+// rate, and whatever of it is over the threshold sets the gain reduction
+fn compressor(
+    samples: &mut [f64],
+    channels: usize,
+    threshold: f64,
+    ratio: f64,
+    attack: f64,
+    release: f64,
+) {
+    let mut envelope = f64::NEG_INFINITY;
+    for frame in samples.chunks_mut(channels) {
+        let peak = frame.iter().fold(0.0f64, |peak, s| peak.max(s.abs()));
+        let level = 20.0 * peak.max(1e-10).log10();
+        let smoothing = if level > envelope { attack } else { release };
+        envelope = if envelope.is_finite() {
+            level + (envelope - level) * smoothing
+        } else {
+            level
+        };
+        let over = (envelope - threshold).max(0.0);
+        let gain = 10f64.powf(-over * (1.0 - 1.0 / ratio) / 20.0);
+        frame.iter_mut().for_each(|s| *s *= gain);
+    }
+}
@@ -0,0 +1,75 @@
+//! This module defines an ADSR envelope, which shapes the amplitude of a
+//! signal over its lifetime: a ramp up over the attack, a fall to the sustain
+//! level over the decay, and a fade to silence over the release. On a note's
+//! amplitude, the release rings on once the note ends; a filter envelope
+//! instead fits its release into the very end of the note.
+
+use crate::error::SyntaxErrorType;
+
+// attack, decay and release are in seconds; sustain is a level from 0 to 1
+#[derive(Clone, Copy)]
+pub struct Envelope {
+    pub attack: f64,
+    pub decay: f64,
+    pub sustain: f64,
+    pub release: f64,
+}
+
+impl Envelope {
+    pub fn new(args: &[&str]) -> Result<Self, SyntaxErrorType> {
+        let bad = || SyntaxErrorType::BadEnvelope(args.join(" "));
+        let values = args
+            .iter()
+            .map(|s| s.parse::<f64>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| bad())?;
+        match values[..] {
+            [attack, decay, sustain, release]
+                if values.iter().all(|v| v.is_finite() && *v >= 0.0) && sustain <= 1.0 =>
+            {
+                Ok(Self {
+                    attack,
+                    decay,
+                    sustain,
+                    release,
+                })
+            }
+            _ => Err(bad()),
+        }
+    }
+
+    // the level of the envelope `t` seconds into a note lasting `length`
+    // seconds in total
+    pub fn gain(&self, t: f64, length: f64) -> f64 {
+        let release_start = (length - self.release).max(0.0);
+        if t < release_start || length <= release_start {
+            self.level(t)
+        } else {
+            self.level(release_start) * (length - t) / (length - release_start)
+        }
+    }
+
+    // the level of the envelope `t` seconds into a note that's held for `held`
+    // seconds and then let go, with the release starting from wherever the
+    // envelope had got to when it was
+    pub fn ringing_gain(&self, t: f64, held: f64) -> f64 {
+        if t < held {
+            self.level(t)
+        } else if self.release > 0.0 {
+            self.level(held) * (1.0 - (t - held) / self.release).max(0.0)
+        } else {
+            0.0
+        }
+    }
+
+    // the attack/decay/sustain part of the envelope, ignoring the release
+    fn level(&self, t: f64) -> f64 {
+        if t < self.attack {
+            t / self.attack
+        } else if t < self.attack + self.decay {
+            1.0 - (1.0 - self.sustain) * (t - self.attack) / self.decay
+        } else {
+            self.sustain
+        }
+    }
+}
@@ -9,15 +9,73 @@ pub enum SyntaxErrorType {
     BadDuration(String),
     BadPitchClass(String),
     BadOctave(String),
+    BadFrequency(String),
+    BadCents(String),
+    BadTemperament(String),
     BadAmplitude(String),
     BadWaveform(String),
+    BadWavetable(String),
+    BadSample(String),
+    BadHarmonics(String),
+    BadPan(String),
+    BadChannels(String),
+    BadMetadata(String),
+    BadSpeakers(String),
+    BadSampleRate(String),
+    BadKey(String),
+    BadNotation(String),
+    BadModifier(String),
+    BadOrnament(String),
+    BadEnvelope(String),
+    BadFilter(String),
+    BadEffect(String),
+    BadInstrument(String),
+    InstrumentBody(String),
+    UnclosedInstrument(String),
+    BadLabel(String),
+    BadGoto(String),
+    BadTrack(String),
+    BadChord(String),
+    BadTime(String),
+    BadPpq(String),
+    BadSwing(String),
+    BadSwell(String),
+    BadHumanize(String),
+    BadDefault(String),
+    BadMark(String),
+    BadLoop(String),
+    BadAbc(String),
+    BadMml(String),
+    BadRepeat(String),
+    BadPattern(String),
+    BadBlock(String),
+    UnclosedBlock(String),
+    DuplicatePattern(String),
+    UndefinedPattern(String),
+    BadInclude(String),
+    BadTranspose(String),
+    IncludeCycle(String),
+    IncludeTooLong(String),
+    DuplicateLabel(String),
+    UndefinedLabel(String),
+    ForwardGoto(String),
+    DuplicateDirective(String),
+    ConflictingDirective(String),
 }
 
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum MusicError {
     SyntaxError(usize, SyntaxErrorType),
+    IncludedSyntaxError(String, usize, SyntaxErrorType),
+    StructuredSyntaxError(String, SyntaxErrorType),
+    StructureError(String, String),
     FileReadError(String),
     FileWriteError(String),
+    DecompressError(String),
+    BuildError(SyntaxErrorType),
+    TooLong(f64, f64),
+    TooManyNotes(usize, usize),
 }
 
 impl std::fmt::Display for SyntaxErrorType {
@@ -29,8 +87,76 @@ impl std::fmt::Display for SyntaxErrorType {
             Self::BadDuration(s) => write!(f, "invalid duration: \"{}\"", s),
             Self::BadPitchClass(s) => write!(f, "invalid pitch class: \"{}\"", s),
             Self::BadOctave(s) => write!(f, "invalid octave: \"{}\"", s),
+            Self::BadFrequency(s) => write!(f, "invalid frequency: \"{}\"", s),
+            Self::BadCents(s) => write!(f, "invalid offset in cents: \"{}\"", s),
+            Self::BadTemperament(s) => write!(f, "invalid temperament: \"{}\"", s),
             Self::BadAmplitude(s) => write!(f, "invalid amplitude: \"{}\"", s),
             Self::BadWaveform(s) => write!(f, "invalid waveform abbreviation: \"{}\"", s),
+            Self::BadWavetable(s) => write!(f, "could not load wavetable: \"{}\"", s),
+            Self::BadSample(s) => write!(f, "could not load sample: \"{}\"", s),
+            Self::BadHarmonics(s) => write!(f, "invalid harmonic series: \"{}\"", s),
+            Self::BadPan(s) => write!(f, "invalid pan: \"{}\"", s),
+            Self::BadChannels(s) => write!(f, "invalid channel count: \"{}\"", s),
+            Self::BadMetadata(s) => write!(f, "missing text: \"{}\"", s),
+            Self::BadSpeakers(s) => write!(f, "invalid speakers: \"{}\"", s),
+            Self::BadSampleRate(s) => write!(f, "invalid sample rate: \"{}\"", s),
+            Self::BadKey(s) => write!(f, "invalid key signature: \"{}\"", s),
+            Self::BadNotation(s) => write!(f, "invalid notation: \"{}\"", s),
+            Self::BadModifier(s) => write!(f, "invalid note modifier: \"{}\"", s),
+            Self::BadOrnament(s) => write!(f, "invalid ornament: \"{}\"", s),
+            Self::BadEnvelope(s) => write!(f, "invalid envelope: \"{}\"", s),
+            Self::BadFilter(s) => write!(f, "invalid filter: \"{}\"", s),
+            Self::BadEffect(s) => write!(f, "invalid effect: \"{}\"", s),
+            Self::BadInstrument(s) => write!(f, "invalid instrument: \"{}\"", s),
+            Self::InstrumentBody(s) => write!(
+                f,
+                "only directives that shape notes can go in an INSTRUMENT block: \"{}\"",
+                s
+            ),
+            Self::UnclosedInstrument(s) => {
+                write!(f, "INSTRUMENT block \"{}\" is never closed with END", s)
+            }
+            Self::BadLabel(s) => write!(f, "invalid label: \"{}\"", s),
+            Self::BadGoto(s) => write!(f, "invalid GOTO: \"{}\"", s),
+            Self::BadTrack(s) => write!(f, "invalid TRACK: \"{}\"", s),
+            Self::BadChord(s) => write!(f, "invalid chord: \"{}\"", s),
+            Self::BadTime(s) => write!(f, "invalid time signature: \"{}\"", s),
+            Self::BadPpq(s) => write!(f, "invalid ticks per quarter note: \"{}\"", s),
+            Self::BadSwing(s) => write!(f, "invalid swing: \"{}\"", s),
+            Self::BadSwell(s) => write!(f, "invalid crescendo or diminuendo: \"{}\"", s),
+            Self::BadHumanize(s) => write!(f, "invalid humanization: \"{}\"", s),
+            Self::BadDefault(s) => write!(f, "invalid default: \"{}\"", s),
+            Self::BadMark(s) => write!(f, "invalid marker: \"{}\"", s),
+            Self::BadLoop(s) => write!(f, "invalid loop: \"{}\"", s),
+            Self::BadAbc(s) => write!(f, "invalid ABC notation: \"{}\"", s),
+            Self::BadMml(s) => write!(f, "invalid MML: \"{}\"", s),
+            Self::BadRepeat(s) => write!(f, "invalid REPEAT block: \"{}\"", s),
+            Self::BadPattern(s) => write!(f, "invalid pattern: \"{}\"", s),
+            Self::BadBlock(s) => write!(
+                f,
+                "}} closes no REPEAT, PATTERN, TRANSPOSE or TUPLET block: \"{}\"",
+                s
+            ),
+            Self::UnclosedBlock(s) => write!(f, "{} block is never closed with }}", s),
+            Self::DuplicatePattern(s) => write!(f, "pattern defined twice: \"{}\"", s),
+            Self::UndefinedPattern(s) => write!(f, "undefined pattern: \"{}\"", s),
+            Self::BadTranspose(s) => write!(f, "invalid transposition: \"{}\"", s),
+            Self::BadInclude(s) => write!(f, "could not include file: \"{}\"", s),
+            Self::IncludeCycle(s) => write!(f, "file includes itself: \"{}\"", s),
+            Self::IncludeTooLong(s) => write!(f, "too many lines or included files: \"{}\"", s),
+            Self::DuplicateLabel(s) => write!(f, "label defined twice: \"{}\"", s),
+            Self::UndefinedLabel(s) => write!(f, "undefined label: \"{}\"", s),
+            Self::ForwardGoto(s) => write!(
+                f,
+                "GOTO refers to label \"{}\", which is only defined later",
+                s
+            ),
+            Self::DuplicateDirective(s) => write!(f, "repeated directive: \"{}\"", s),
+            Self::ConflictingDirective(s) => write!(
+                f,
+                "directive \"{}\" conflicts with the command-line value",
+                s
+            ),
         }
     }
 }
@@ -39,12 +165,33 @@ impl std::fmt::Display for MusicError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             MusicError::SyntaxError(line, e) => write!(f, "Syntax error (line {}): {}", line, e),
+            MusicError::IncludedSyntaxError(file, line, e) => {
+                write!(f, "Syntax error (\"{}\", line {}): {}", file, line, e)
+            }
+            MusicError::StructuredSyntaxError(at, e) => write!(f, "Syntax error ({}): {}", at, e),
+            MusicError::StructureError(file, e) => {
+                write!(f, "Syntax error (\"{}\"): {}", file, e.trim_end())
+            }
             MusicError::FileReadError(file) => {
                 write!(f, "I/O error: failed to read file \"{}\"", file)
             }
             MusicError::FileWriteError(file) => {
                 write!(f, "I/O error: failed to write to file \"{}\"", file)
             }
+            MusicError::DecompressError(file) => {
+                write!(f, "I/O error: failed to decompress gzip file \"{}\"", file)
+            }
+            MusicError::BuildError(e) => write!(f, "Build error: {}", e),
+            MusicError::TooLong(length, limit) => write!(
+                f,
+                "Render error: piece would last {:.1} seconds, over the limit of {} seconds",
+                length, limit
+            ),
+            MusicError::TooManyNotes(count, limit) => write!(
+                f,
+                "Render error: piece would have {} notes, over the limit of {}",
+                count, limit
+            ),
         }
     }
 }
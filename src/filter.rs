@@ -0,0 +1,176 @@
+//! This module defines the resonant low-pass and high-pass filters that a note
+//! can be run through, along with the shelves and peaks that the equalizer
+//! shapes whole mixes with. Each is a biquad, with coefficients from Robert
+//! Bristow-Johnson's "Audio EQ Cookbook", and each voice keeps its own state.
+//! A filter envelope can sweep the cutoff over the course of a note.
+
+use crate::envelope::Envelope;
+use crate::error::SyntaxErrorType;
+
+use std::f64::consts::PI;
+
+#[derive(Clone, Copy)]
+pub enum FilterKind {
+    LowPass,
+    HighPass,
+    // boost (or cut) everything below or above the cutoff, or a band around
+    // it, by the given number of dB
+    LowShelf(f64),
+    HighShelf(f64),
+    Peak(f64),
+}
+
+// a filter cuts off at `cutoff` Hz, with a resonant peak there whose sharpness
+// is given by `resonance` (the Q factor; 0.707 gives no peak at all)
+#[derive(Clone, Copy)]
+pub struct Filter {
+    pub kind: FilterKind,
+    pub cutoff: f64,
+    pub resonance: f64,
+}
+
+// a filter envelope sweeps a filter's cutoff by as much as `depth` octaves
+// (downward, if it's negative), following the shape of an ADSR envelope
+#[derive(Clone, Copy)]
+pub struct FilterEnvelope {
+    pub envelope: Envelope,
+    pub depth: f64,
+}
+
+// the running state of a biquad: its coefficients, normalized so that a0 is 1,
+// and its last two inputs and outputs
+#[derive(Clone, Copy, Default)]
+pub struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    x: [f64; 2],
+    y: [f64; 2],
+}
+
+impl Filter {
+    // parse `<LP|HP> <cutoff> <resonance>`
+    pub fn new(args: &[&str]) -> Result<Self, SyntaxErrorType> {
+        let bad = || SyntaxErrorType::BadFilter(args.join(" "));
+        let kind = match args.first() {
+            Some(&"LP") => FilterKind::LowPass,
+            Some(&"HP") => FilterKind::HighPass,
+            _ => return Err(bad()),
+        };
+        let values = args[1..]
+            .iter()
+            .map(|s| s.parse::<f64>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| bad())?;
+        match values[..] {
+            [cutoff, resonance]
+                if cutoff.is_finite()
+                    && cutoff > 0.0
+                    && resonance.is_finite()
+                    && resonance > 0.0 =>
+            {
+                Ok(Self {
+                    kind,
+                    cutoff,
+                    resonance,
+                })
+            }
+            _ => Err(bad()),
+        }
+    }
+}
+
+impl Biquad {
+    // set the coefficients for the given filter, keeping the cutoff just
+    // below Nyquist so that the filter stays stable
+    pub fn tune(&mut self, filter: &Filter, rate: u32) {
+        let cutoff = filter.cutoff.min(0.49 * rate as f64);
+        let w = 2.0 * PI * cutoff / rate as f64;
+        let alpha = w.sin() / (2.0 * filter.resonance);
+        let (b, a) = match filter.kind {
+            FilterKind::LowPass => (
+                [(1.0 - w.cos()) / 2.0, 1.0 - w.cos(), (1.0 - w.cos()) / 2.0],
+                [1.0 + alpha, -2.0 * w.cos(), 1.0 - alpha],
+            ),
+            FilterKind::HighPass => (
+                [(1.0 + w.cos()) / 2.0, -1.0 - w.cos(), (1.0 + w.cos()) / 2.0],
+                [1.0 + alpha, -2.0 * w.cos(), 1.0 - alpha],
+            ),
+            FilterKind::LowShelf(gain) => {
+                let a = 10f64.powf(gain / 40.0);
+                let root = 2.0 * a.sqrt() * alpha;
+                (
+                    [
+                        a * ((a + 1.0) - (a - 1.0) * w.cos() + root),
+                        2.0 * a * ((a - 1.0) - (a + 1.0) * w.cos()),
+                        a * ((a + 1.0) - (a - 1.0) * w.cos() - root),
+                    ],
+                    [
+                        (a + 1.0) + (a - 1.0) * w.cos() + root,
+                        -2.0 * ((a - 1.0) + (a + 1.0) * w.cos()),
+                        (a + 1.0) + (a - 1.0) * w.cos() - root,
+                    ],
+                )
+            }
+            FilterKind::Peak(gain) => {
+                let a = 10f64.powf(gain / 40.0);
+                (
+                    [1.0 + alpha * a, -2.0 * w.cos(), 1.0 - alpha * a],
+                    [1.0 + alpha / a, -2.0 * w.cos(), 1.0 - alpha / a],
+                )
+            }
+            FilterKind::HighShelf(gain) => {
+                let a = 10f64.powf(gain / 40.0);
+                let root = 2.0 * a.sqrt() * alpha;
+                (
+                    [
+                        a * ((a + 1.0) + (a - 1.0) * w.cos() + root),
+                        -2.0 * a * ((a - 1.0) + (a + 1.0) * w.cos()),
+                        a * ((a + 1.0) + (a - 1.0) * w.cos() - root),
+                    ],
+                    [
+                        (a + 1.0) - (a - 1.0) * w.cos() + root,
+                        2.0 * ((a - 1.0) - (a + 1.0) * w.cos()),
+                        (a + 1.0) - (a - 1.0) * w.cos() - root,
+                    ],
+                )
+            }
+        };
+        self.b = b.map(|b| b / a[0]);
+        self.a = [a[1] / a[0], a[2] / a[0]];
+    }
+
+    pub fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+            - self.a[0] * self.y[0]
+            - self.a[1] * self.y[1];
+        self.x = [x, self.x[0]];
+        self.y = [y, self.y[0]];
+        y
+    }
+}
+
+impl FilterEnvelope {
+    // parse `<attack> <decay> <sustain> <release> <depth>`
+    pub fn new(args: &[&str]) -> Result<Self, SyntaxErrorType> {
+        let bad = || SyntaxErrorType::BadEnvelope(args.join(" "));
+        match args {
+            [adsr @ .., depth] if adsr.len() == 4 => match depth.parse::<f64>() {
+                Ok(depth) if depth.is_finite() => Ok(Self {
+                    envelope: Envelope::new(adsr).map_err(|_| bad())?,
+                    depth,
+                }),
+                _ => Err(bad()),
+            },
+            _ => Err(bad()),
+        }
+    }
+
+    // the filter as swept by the envelope, `t` seconds into a note lasting
+    // `length` seconds in total
+    pub fn sweep(&self, filter: &Filter, t: f64, length: f64) -> Filter {
+        Filter {
+            cutoff: filter.cutoff * 2.0f64.powf(self.depth * self.envelope.gain(t, length)),
+            ..*filter
+        }
+    }
+}
@@ -0,0 +1,685 @@
+//! This module reads tunes written in ABC notation, the plain-text notation
+//! that a great many folk tunes are collected in. It understands the header
+//! fields that decide how the notes sound (`L:`, `M:`, `K:` and `Q:`, as well
+//! as `V:` for tunes in several voices, each of which becomes a track), and in
+//! the body: notes with their accidentals, octave marks and lengths, rests,
+//! chords, ties, broken rhythms, tuplets, and repeats with first and second
+//! endings. Dynamics such as `!mf!` set the amplitude of the notes after them,
+//! and `.` plays a note staccato; everything else that only matters on paper
+//! (chord symbols, grace notes, slurs, lyrics and other decorations) is
+//! skipped. Only the first tune in a file is read. Lines starting with
+//! `%%wav-maker` are passed through as lines of the score, so that a tune can
+//! set up its own waveforms and effects (e.g. `%%wav-maker DEFAULT WAVE T`).
+
+use crate::error::{MusicError, SyntaxErrorType};
+use crate::piece::{MusicalPiece, PieceOptions};
+use crate::signal::DYNAMICS;
+
+use super::{note_name, on_line, parse_lines, Fraction};
+
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+// each natural letter, in the order C D E F G A B, with its semitones above C
+const LETTERS: [(char, i32); 7] = [
+    ('C', 0),
+    ('D', 2),
+    ('E', 4),
+    ('F', 5),
+    ('G', 7),
+    ('A', 9),
+    ('B', 11),
+];
+
+// a note, a chord or a rest (which has no pitches), starting at the time it is
+// written at, before any repeats are played out; pitches are in semitones
+// above C0
+#[derive(Clone)]
+struct Event {
+    start: Fraction,
+    length: Fraction,
+    pitches: Vec<i32>,
+    ampl: f64,
+    staccato: bool,
+    line: usize,
+}
+
+// the parts of a voice that matter for playing it: its events, the bar lines
+// that change the order they're played in (at the time they are written at),
+// and lines to pass through to the score
+enum Item {
+    Event(Event),
+    RepeatStart(Fraction),
+    RepeatEnd(Fraction),
+    Ending(u32, Fraction),
+    Directive(String, usize),
+}
+
+// how far a voice has got: the time its next note starts at, whether that
+// note is tied to the last one, how the next note's length is changed by a
+// broken rhythm or a tuplet (as the ratio and the number of notes left), and
+// the accidentals written so far in the bar, by letter and octave
+struct Voice {
+    name: String,
+    items: Vec<Item>,
+    time: Fraction,
+    tied: bool,
+    broken: Option<Fraction>,
+    tuplet: Option<(Fraction, u32)>,
+    accidentals: HashMap<(usize, i32), i32>,
+}
+
+// the state of the tune being read: the unit note length (if one has been
+// given), the length of a measure, the accidental that the key gives each
+// letter, the tempo, the voices and the one being written to, and the
+// amplitude and articulation of the next note
+struct Tune {
+    unit: Option<Fraction>,
+    meter: Fraction,
+    key: [i32; 7],
+    bpm: Option<f64>,
+    voices: Vec<Voice>,
+    voice: usize,
+    ampl: f64,
+    staccato: bool,
+}
+
+impl Voice {
+    fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            items: vec![],
+            time: Fraction::ZERO,
+            tied: false,
+            broken: None,
+            tuplet: None,
+            accidentals: HashMap::new(),
+        }
+    }
+
+    // the events in the order they are played, with the time that each is
+    // played at: a repeated section is played twice, the first time through
+    // to its first ending, and the second time skipping over that ending
+    fn play(&self) -> Vec<Event> {
+        let mut events = vec![];
+        // how much later than written the events being played out are
+        let mut offset = Fraction::ZERO;
+        let (mut from, mut from_time) = (0, Fraction::ZERO);
+        let mut pass = 1;
+        let mut i = 0;
+        while i < self.items.len() {
+            match self.items[i] {
+                Item::Event(ref event) => events.push(Event {
+                    start: event.start + offset,
+                    ..event.clone()
+                }),
+                Item::RepeatStart(time) => {
+                    (from, from_time, pass) = (i + 1, time, 1);
+                }
+                Item::RepeatEnd(time) if pass == 1 => {
+                    offset = offset + (time - from_time);
+                    pass = 2;
+                    i = from;
+                    continue;
+                }
+                Item::RepeatEnd(time) => {
+                    (from, from_time, pass) = (i + 1, time, 1);
+                }
+                // an ending for another time through is skipped, up to the
+                // next ending or repeat (and past it, if it closes the ending)
+                Item::Ending(n, time) if n != pass => {
+                    let next = (i + 1..self.items.len())
+                        .find(|&j| !matches!(self.items[j], Item::Event(_) | Item::Directive(..)));
+                    let Some(j) = next else { break };
+                    match self.items[j] {
+                        Item::RepeatEnd(end) => {
+                            offset = offset - (end - time);
+                            (from, from_time) = (j + 1, end);
+                            i = j + 1;
+                        }
+                        Item::RepeatStart(end) | Item::Ending(_, end) => {
+                            offset = offset - (end - time);
+                            i = j;
+                        }
+                        _ => i = j,
+                    }
+                    continue;
+                }
+                Item::Ending(..) | Item::Directive(..) => {}
+            }
+            i += 1;
+        }
+        events
+    }
+}
+
+impl Tune {
+    fn new() -> Self {
+        Self {
+            unit: None,
+            meter: Fraction::new(1, 1),
+            key: [0; 7],
+            bpm: None,
+            voices: vec![Voice::new("")],
+            voice: 0,
+            ampl: 1.0,
+            staccato: false,
+        }
+    }
+
+    // the unit note length, which unless given is an eighth, or a sixteenth
+    // in a meter shorter than 3/4
+    fn unit(&self) -> Fraction {
+        let short = self.meter.num * 4 < self.meter.den * 3;
+        self.unit
+            .unwrap_or(Fraction::new(1, if short { 16 } else { 8 }))
+    }
+
+    // apply an information field, such as `K:G` or `L:1/8`; fields that don't
+    // change how the tune sounds are ignored
+    fn field(&mut self, name: char, value: &str) -> Result<(), SyntaxErrorType> {
+        let bad = || SyntaxErrorType::BadAbc(format!("{}:{}", name, value));
+        let value = value.trim();
+        match name {
+            'L' => self.unit = Some(parse_fraction(value).ok_or_else(bad)?),
+            'M' => {
+                self.meter = match value {
+                    "C" | "C|" | "none" | "" => Fraction::new(1, 1),
+                    _ => parse_fraction(value).ok_or_else(bad)?,
+                }
+            }
+            'K' => self.key = parse_key(value).ok_or_else(bad)?,
+            // only the tempo that the tune starts at is kept
+            'Q' if self.bpm.is_none() => self.bpm = Some(self.parse_tempo(value).ok_or_else(bad)?),
+            'V' => {
+                let name = value.split_whitespace().next().ok_or_else(bad)?;
+                self.voice = match self.voices.iter().position(|v| v.name == name) {
+                    Some(index) => index,
+                    None => {
+                        self.voices.push(Voice::new(name));
+                        self.voices.len() - 1
+                    }
+                };
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    // a tempo such as `1/4=120`, or just `120` unit notes a minute, in
+    // quarter notes a minute
+    fn parse_tempo(&self, value: &str) -> Option<f64> {
+        let (beats, bpm) = match value.split_whitespace().find(|word| word.contains('=')) {
+            Some(word) => word.split_once('=')?,
+            None => ("", value),
+        };
+        let beat = match beats {
+            "" => self.unit(),
+            _ => parse_fraction(beats)?,
+        };
+        let bpm = bpm.parse::<f64>().ok()? * beat.ticks() / 16.0;
+        (bpm.is_finite() && bpm > 0.0).then_some(bpm)
+    }
+
+    fn current(&mut self) -> &mut Voice {
+        &mut self.voices[self.voice]
+    }
+
+    // read a line of the tune's body
+    fn body(&mut self, text: &str, line: usize) -> Result<(), SyntaxErrorType> {
+        let chars = text.chars().collect::<Vec<_>>();
+        let bad = |i: usize| SyntaxErrorType::BadAbc(chars[i..].iter().collect());
+        let mut i = 0;
+        while i < chars.len() {
+            match chars[i] {
+                '%' => break,
+                ' ' | '\t' | '`' | 'y' | ')' | '\\' => i += 1,
+                '~' | 'H' | 'L' | 'M' | 'O' | 'P' | 'S' | 'T' | 'u' | 'v' => i += 1,
+                '.' => {
+                    self.staccato = true;
+                    i += 1;
+                }
+                '-' => {
+                    self.current().tied = true;
+                    i += 1;
+                }
+                // a chord symbol or annotation, a grace note or a decoration
+                '"' | '{' | '!' | '+' => {
+                    let close = match chars[i] {
+                        '{' => '}',
+                        c => c,
+                    };
+                    let end = (i + 1..chars.len())
+                        .find(|&j| chars[j] == close)
+                        .ok_or_else(|| bad(i))?;
+                    let inside = chars[i + 1..end].iter().collect::<String>();
+                    if matches!(chars[i], '!' | '+') {
+                        self.decoration(&inside);
+                    }
+                    i = end + 1;
+                }
+                '>' | '<' => {
+                    let start = i;
+                    while i < chars.len() && chars[i] == chars[start] {
+                        i += 1;
+                    }
+                    self.broken(chars[start] == '>', (i - start) as u32)
+                        .ok_or_else(|| bad(start))?;
+                }
+                '(' => match chars.get(i + 1) {
+                    Some(c) if c.is_ascii_digit() => {
+                        i = self.tuplet(&chars, i + 1).ok_or_else(|| bad(i))?
+                    }
+                    _ => i += 1,
+                },
+                '[' => match chars.get(i + 1) {
+                    Some(c) if c.is_ascii_digit() => i = self.ending(&chars, i + 1),
+                    Some('|') => i = self.bar(&chars, i + 1).ok_or_else(|| bad(i))?,
+                    Some(c) if c.is_ascii_alphabetic() && chars.get(i + 2) == Some(&':') => {
+                        let end = (i..chars.len())
+                            .find(|&j| chars[j] == ']')
+                            .ok_or_else(|| bad(i))?;
+                        let value = chars[i + 3..end].iter().collect::<String>();
+                        self.field(*c, &value)?;
+                        i = end + 1;
+                    }
+                    _ => i = self.chord(&chars, i + 1, line).ok_or_else(|| bad(i))?,
+                },
+                '|' | ':' => i = self.bar(&chars, i).ok_or_else(|| bad(i))?,
+                _ => {
+                    let (pitch, length, end) = self.note(&chars, i).ok_or_else(|| bad(i))?;
+                    self.push(pitch.into_iter().collect(), length, line);
+                    i = end;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // a dynamic marking sets the amplitude of the notes that follow it
+    fn decoration(&mut self, name: &str) {
+        if let Some(&(_, ampl)) = DYNAMICS.iter().find(|(n, _)| *n == name) {
+            self.ampl = ampl;
+        } else if name == "staccato" {
+            self.staccato = true;
+        }
+    }
+
+    // `>` lengthens the last note by half and takes as much off the next, and
+    // `<` the other way around; each extra `>` or `<` halves what moves
+    fn broken(&mut self, longer_first: bool, count: u32) -> Option<()> {
+        let short = Fraction::new(1, 1 << count.min(8));
+        let long = Fraction::new(2, 1) - short;
+        let (first, second) = if longer_first {
+            (long, short)
+        } else {
+            (short, long)
+        };
+        let voice = self.current();
+        let Some(Item::Event(last)) = voice.items.last_mut() else {
+            return None;
+        };
+        last.length = last.length * first;
+        voice.time = last.start + last.length;
+        voice.broken = Some(second);
+        Some(())
+    }
+
+    // `(p:q:r` plays the next `r` notes (or `p`, if `r` is left out) in the
+    // time of `q`, where `q` is 3 for duplets and quadruplets, 2 for triplets
+    // and sextuplets, and otherwise depends on whether the meter is compound
+    fn tuplet(&mut self, chars: &[char], i: usize) -> Option<usize> {
+        let mut numbers = vec![];
+        let mut i = i;
+        loop {
+            let start = i;
+            while i < chars.len() && chars[i].is_ascii_digit() {
+                i += 1;
+            }
+            numbers.push(
+                chars[start..i]
+                    .iter()
+                    .collect::<String>()
+                    .parse::<i64>()
+                    .ok(),
+            );
+            if chars.get(i) != Some(&':') || numbers.len() == 3 {
+                break;
+            }
+            i += 1;
+        }
+        let p = numbers[0].filter(|&p| p >= 2)?;
+        let compound = self.meter.den == 8 && self.meter.num % 3 == 0 && self.meter.num > 3;
+        let q = numbers.get(1).copied().flatten().unwrap_or(match p {
+            2 | 4 | 8 => 3,
+            3 | 6 => 2,
+            _ if compound => 3,
+            _ => 2,
+        });
+        let r = numbers.get(2).copied().flatten().unwrap_or(p);
+        if q <= 0 || r <= 0 {
+            return None;
+        }
+        self.current().tuplet = Some((Fraction::new(q, p), r as u32));
+        Some(i)
+    }
+
+    // a first or second ending, written as `[1` or `|2` (anything like `,3`
+    // after the number is ignored)
+    fn ending(&mut self, chars: &[char], i: usize) -> usize {
+        let mut end = i;
+        while end < chars.len() && chars[end].is_ascii_digit() {
+            end += 1;
+        }
+        let n = chars[i..end]
+            .iter()
+            .collect::<String>()
+            .parse()
+            .unwrap_or(1);
+        while end < chars.len() && (chars[end].is_ascii_digit() || matches!(chars[end], ',' | '-'))
+        {
+            end += 1;
+        }
+        let voice = self.current();
+        voice.items.push(Item::Ending(n, voice.time));
+        end
+    }
+
+    // a bar line, which clears the accidentals of the bar before it, and may
+    // start or end a repeat (`|:`, `:|`, `::`, and the like) or an ending
+    fn bar(&mut self, chars: &[char], i: usize) -> Option<usize> {
+        let mut end = i;
+        while end < chars.len() && matches!(chars[end], '|' | ':' | ']') {
+            end += 1;
+        }
+        let bar = chars[i..end].iter().collect::<String>();
+        if bar == ":" {
+            return None;
+        }
+        let voice = self.current();
+        voice.accidentals.clear();
+        if bar.starts_with(':') {
+            voice.items.push(Item::RepeatEnd(voice.time));
+        }
+        if bar.ends_with(':') && (bar.len() > 1 || bar == "::") {
+            voice.items.push(Item::RepeatStart(voice.time));
+        }
+        match chars.get(end) {
+            Some(c) if c.is_ascii_digit() => Some(self.ending(chars, end)),
+            _ => Some(end),
+        }
+    }
+
+    // the notes of a chord, up to its `]`, followed by the length of the whole
+    // chord, which multiplies that of its first note
+    fn chord(&mut self, chars: &[char], i: usize, line: usize) -> Option<usize> {
+        let mut pitches = vec![];
+        let mut length = None;
+        let mut i = i;
+        while chars.get(i) != Some(&']') {
+            match chars.get(i)? {
+                '-' => {
+                    self.current().tied = true;
+                    i += 1;
+                }
+                ' ' | '.' | '~' => i += 1,
+                _ => {
+                    let (pitch, note_length, end) = self.note(chars, i)?;
+                    pitches.extend(pitch);
+                    length = length.or(Some(note_length));
+                    i = end;
+                }
+            }
+        }
+        let (multiplier, end) = parse_length(chars, i + 1);
+        self.push(pitches, length? * multiplier, line);
+        Some(end)
+    }
+
+    // a note or rest, with its accidental, octave marks and length: the pitch
+    // (or `None` for a rest), the length and where the note ends
+    fn note(&mut self, chars: &[char], i: usize) -> Option<(Option<i32>, Fraction, usize)> {
+        let mut i = i;
+        let mut accidental = None;
+        while let Some(c @ ('^' | '_' | '=')) = chars.get(i) {
+            accidental = Some(
+                accidental.unwrap_or(0)
+                    + match c {
+                        '^' => 1,
+                        '_' => -1,
+                        _ => 0,
+                    },
+            );
+            i += 1;
+        }
+        let letter = *chars.get(i)?;
+        i += 1;
+        // a rest, or a rest of whole measures
+        match letter {
+            'z' | 'x' => {
+                let (multiplier, end) = parse_length(chars, i);
+                return Some((None, self.unit() * multiplier, end));
+            }
+            'Z' | 'X' => {
+                let (multiplier, end) = parse_length(chars, i);
+                return Some((None, self.meter * multiplier, end));
+            }
+            _ => {}
+        }
+        let index = LETTERS
+            .iter()
+            .position(|&(l, _)| l == letter.to_ascii_uppercase())?;
+        let mut octave = if letter.is_ascii_uppercase() { 4 } else { 5 };
+        while let Some(c @ ('\'' | ',')) = chars.get(i) {
+            octave += if *c == '\'' { 1 } else { -1 };
+            i += 1;
+        }
+        let (multiplier, end) = parse_length(chars, i);
+        // an accidental holds for the same note until the end of the bar
+        let key = self.key[index];
+        let voice = self.current();
+        let shift = match accidental {
+            Some(shift) => {
+                voice.accidentals.insert((index, octave), shift);
+                shift
+            }
+            None => voice
+                .accidentals
+                .get(&(index, octave))
+                .copied()
+                .unwrap_or(key),
+        };
+        let pitch = 12 * octave + LETTERS[index].1 + shift;
+        Some((Some(pitch), self.unit() * multiplier, end))
+    }
+
+    // add a note, chord or rest to the current voice, as squeezed by any
+    // tuplet or broken rhythm; a note tied to the last one carries it on
+    // rather than sounding again
+    fn push(&mut self, pitches: Vec<i32>, length: Fraction, line: usize) {
+        let (ampl, staccato) = (self.ampl, std::mem::take(&mut self.staccato));
+        let voice = self.current();
+        let mut length = length;
+        if let Some((ratio, left)) = voice.tuplet {
+            length = length * ratio;
+            voice.tuplet = (left > 1).then_some((ratio, left - 1));
+        }
+        if let Some(ratio) = voice.broken.take() {
+            length = length * ratio;
+        }
+        if std::mem::take(&mut voice.tied) {
+            if let Some(Item::Event(last)) = voice.items.last_mut() {
+                if last.pitches == pitches {
+                    last.length = last.length + length;
+                    voice.time = voice.time + length;
+                    return;
+                }
+            }
+        }
+        voice.items.push(Item::Event(Event {
+            start: voice.time,
+            length,
+            pitches,
+            ampl,
+            staccato,
+            line,
+        }));
+        voice.time = voice.time + length;
+    }
+}
+
+// a fraction written as `n/d`, or as a whole number
+fn parse_fraction(input: &str) -> Option<Fraction> {
+    let (num, den) = input.split_once('/').unwrap_or((input, "1"));
+    let (num, den) = (
+        num.trim().parse::<i64>().ok()?,
+        den.trim().parse::<i64>().ok()?,
+    );
+    (num > 0 && den > 0).then(|| Fraction::new(num, den))
+}
+
+// the multiplier written after a note, such as `2`, `3/2`, `/2`, `/` (which
+// halves it) or `//` (which quarters it), and where it ends
+fn parse_length(chars: &[char], i: usize) -> (Fraction, usize) {
+    let number = |i: &mut usize| {
+        let start = *i;
+        while *i < chars.len() && chars[*i].is_ascii_digit() {
+            *i += 1;
+        }
+        chars[start..*i]
+            .iter()
+            .collect::<String>()
+            .parse::<i64>()
+            .ok()
+    };
+    let mut i = i;
+    let mut length = Fraction::new(number(&mut i).unwrap_or(1).max(1), 1);
+    while chars.get(i) == Some(&'/') {
+        i += 1;
+        length = length * Fraction::new(1, number(&mut i).unwrap_or(2).max(1));
+    }
+    (length, i)
+}
+
+// the accidentals that a key such as `G`, `F#m`, `Bbmix` or `none` gives each
+// letter, in the order C D E F G A B
+fn parse_key(value: &str) -> Option<[i32; 7]> {
+    let value = value.split_whitespace().next().unwrap_or("none");
+    if matches!(value, "none" | "HP" | "Hp") {
+        return Some([0; 7]);
+    }
+    let mut chars = value.chars();
+    // place the tonic on the circle of fifths, and then the mode relative to
+    // the major key on it
+    let mut fifths = match chars.next()? {
+        'F' => -1,
+        'C' => 0,
+        'G' => 1,
+        'D' => 2,
+        'A' => 3,
+        'E' => 4,
+        'B' => 5,
+        _ => return None,
+    };
+    let mut mode = chars.as_str();
+    if let Some(rest) = mode.strip_prefix('#') {
+        fifths += 7;
+        mode = rest;
+    } else if let Some(rest) = mode.strip_prefix('b') {
+        fifths -= 7;
+        mode = rest;
+    }
+    let mode = mode.to_ascii_lowercase();
+    fifths += match mode.get(..3).unwrap_or(&mode) {
+        "" | "maj" | "ion" => 0,
+        "m" | "min" | "aeo" => -3,
+        "mix" => -1,
+        "dor" => -2,
+        "phr" => -4,
+        "lyd" => 1,
+        "loc" => -5,
+        _ => return None,
+    };
+    let mut accidentals = [0; 7];
+    let order = [3, 0, 4, 1, 5, 2, 6];
+    for &letter in order.iter().take(fifths.clamp(0, 7) as usize) {
+        accidentals[letter] = 1;
+    }
+    for &letter in order.iter().rev().take((-fifths).clamp(0, 7) as usize) {
+        accidentals[letter] = -1;
+    }
+    Some(accidentals)
+}
+
+// the lines of a score that plays the first tune in the given ABC file, each
+// with the line of the file that it comes from
+pub fn abc_to_score(input: &str) -> Result<Vec<(String, usize)>, MusicError> {
+    let mut tune = Tune::new();
+    let mut tunes = 0;
+    for (n, text) in input.lines().enumerate() {
+        let error = |e| MusicError::SyntaxError(n + 1, e);
+        if let Some(directive) = text.strip_prefix("%%wav-maker") {
+            let item = Item::Directive(directive.trim().to_string(), n + 1);
+            tune.current().items.push(item);
+            continue;
+        }
+        let mut chars = text.chars();
+        match (chars.next(), chars.next()) {
+            (Some('X'), Some(':')) => {
+                tunes += 1;
+                if tunes > 1 {
+                    break;
+                }
+            }
+            (Some(name), Some(':')) if name.is_ascii_alphabetic() => {
+                tune.field(name, chars.as_str()).map_err(error)?
+            }
+            _ => tune.body(text, n + 1).map_err(error)?,
+        }
+    }
+
+    let mut lines = vec![];
+    if let Some(bpm) = tune.bpm {
+        lines.push((format!("BPM {}", bpm), 1));
+    }
+    for voice in tune.voices.iter() {
+        if !voice.name.is_empty() {
+            lines.push((format!("TRACK {}", voice.name), 1));
+        }
+        lines.push(("DEFAULT WAVE S".to_string(), 1));
+        // directives are passed through once each, however often the music
+        // around them is repeated
+        let mut passed = HashSet::new();
+        for item in voice.items.iter() {
+            if let Item::Directive(directive, line) = item {
+                if passed.insert(line) {
+                    lines.push((directive.clone(), *line));
+                }
+            }
+        }
+        for event in voice.play() {
+            let staccato = if event.staccato { "." } else { "" };
+            let duration = format!("{}{}", event.length, staccato);
+            let start = event.start.ticks();
+            if event.pitches.is_empty() {
+                lines.push((format!("{} {} R", start, duration), event.line));
+            }
+            for &pitch in event.pitches.iter() {
+                let name = note_name(pitch);
+                let note = format!("{} {} {} {}", start, duration, name, event.ampl);
+                lines.push((note, event.line));
+            }
+        }
+    }
+    Ok(lines)
+}
+
+// parse a tune written in ABC notation, which is in the file at `path`; an
+// error is reported against the line of the tune that it comes from
+pub fn parse_abc(
+    input: &str,
+    path: &Path,
+    options: &PieceOptions,
+) -> Result<MusicalPiece, MusicError> {
+    parse_lines(&abc_to_score(input)?, path, options, on_line)
+}
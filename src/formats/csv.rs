@@ -0,0 +1,103 @@
+//! This module reads and writes pieces as a flat table of notes, one row per
+//! note with the columns `start_tick,duration_ticks,pitch,ampl,wave`, so that
+//! spreadsheets and scripts can generate or analyze a piece without knowing
+//! the syntax of a score. Each row is read as the note line with the same
+//! columns, so a pitch may be any note (or `R` for a rest) and the amplitude a
+//! dynamic; a note with no waveform is a sine wave. A first row that names
+//! the columns is skipped.
+
+use crate::error::{MusicError, SyntaxErrorType};
+use crate::note::Pitch;
+use crate::piece::{MusicalPiece, PieceOptions};
+
+use super::{note_name, on_line, parse_lines};
+
+use std::path::Path;
+
+// the row of column names that a table starts with
+const HEADER: &str = "start_tick,duration_ticks,pitch,ampl,wave";
+
+// the fields of a row, trimmed; a field may be quoted, as a waveform such as
+// `FM:2,1` has to be, and `""` within quotes stands for a quote
+fn fields(row: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut quoted = false;
+    let mut chars = row.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                fields.last_mut().unwrap().push('"');
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(String::new()),
+            c => fields.last_mut().unwrap().push(c),
+        }
+    }
+    fields
+        .iter()
+        .map(|field| field.trim().to_string())
+        .collect()
+}
+
+// the lines of a score that plays the given table, each with the line of the
+// file that it comes from; blank rows are skipped, as are empty columns at the
+// end of a row, but an empty column before one that isn't is an error
+pub fn csv_to_score(input: &str) -> Result<Vec<(String, usize)>, MusicError> {
+    let mut lines = vec![("DEFAULT WAVE S".to_string(), 1)];
+    for (n, row) in input.lines().enumerate() {
+        let mut fields = fields(row);
+        while fields.last().is_some_and(String::is_empty) {
+            fields.pop();
+        }
+        let header = n == 0 && fields.first().is_some_and(|f| f.starts_with("start"));
+        if fields.is_empty() || header {
+            continue;
+        }
+        if fields.iter().any(String::is_empty) {
+            return Err(on_line(&(n + 1), SyntaxErrorType::MissingEntry));
+        }
+        lines.push((fields.join(" "), n + 1));
+    }
+    Ok(lines)
+}
+
+// parse a table of notes, which is in the file at `path`; an error is
+// reported against the row of the file that it comes from
+pub fn parse_csv(
+    input: &str,
+    path: &Path,
+    options: &PieceOptions,
+) -> Result<MusicalPiece, MusicError> {
+    parse_lines(&csv_to_score(input)?, path, options, on_line)
+}
+
+// write a piece out as a table of the notes that it plays, in the order that
+// they start; starts and durations are in ticks as they are played (with any
+// swing), amplitudes are as they are played (after the velocity curve), and
+// tracks, tempo and everything else about how the notes sound are left out
+pub fn write_csv(piece: &MusicalPiece) -> String {
+    let mut table = format!("{}\n", HEADER);
+    for (start, end, signal) in piece.notes() {
+        let pitch = match signal.pitch {
+            Pitch::Note(note) if note.cents != 0.0 => {
+                format!("{}{:+}c", note_name(note.semitones()), note.cents)
+            }
+            Pitch::Note(note) => note_name(note.semitones()),
+            Pitch::Frequency(freq) => format!("{}Hz", freq),
+        };
+        let mut wave = piece.wave_name(&signal.wavetype);
+        if wave.contains([',', '"']) {
+            wave = format!("\"{}\"", wave.replace('"', "\"\""));
+        }
+        table.push_str(&format!(
+            "{},{},{},{},{}\n",
+            start,
+            end - start,
+            pitch,
+            signal.ampl,
+            wave
+        ));
+    }
+    table
+}
@@ -0,0 +1,421 @@
+//! This module reads and writes Standard MIDI Files. The notes of each track in the file
+//! are played in a track of their own, at the velocities they were recorded
+//! with, and the file's tempo changes make up the tempo map; everything else
+//! (program changes, controllers, pitch bends and so on) is ignored. Ticks
+//! are kept as they are in the file, by way of a `PPQ` directive. A track is
+//! played as sine waves unless it is given a waveform of its own, except on
+//! the percussion channel, where the General MIDI drums are played by the
+//! kick, snare and hi-hat waveforms.
+//!
+//! A piece is written out as a file of type 1: a first track holds the tempo
+//! map, and then the notes of each track of the piece follow in a track of
+//! their own, on a channel of their own, except that notes played by the
+//! drum waveforms go on the percussion channel as General MIDI drums.
+
+use crate::error::MusicError;
+use crate::piece::{MusicalPiece, PieceOptions};
+use crate::signal::WaveType;
+
+use super::{note_name, parse_lines};
+
+use std::collections::{HashMap, VecDeque};
+use std::path::Path;
+
+// the tempo of a file until it sets one, in microseconds per quarter note
+const DEFAULT_TEMPO: u32 = 500000;
+
+// the channel that General MIDI keeps for percussion (10, counting from 1)
+const PERCUSSION: u8 = 9;
+
+// the ticks per quarter note of a file written out, which keeps a tick of a
+// piece (a 64th note) to a whole number of them
+const WRITTEN_PPQ: u16 = 480;
+
+// how often, in ticks of the piece, a ramp in tempo is stepped through when
+// written out, as a file can only change tempo outright
+const RAMP_STEP: f64 = 4.0;
+
+// a note in a track, in ticks, with its channel, key and velocity
+struct MidiNote {
+    start: u64,
+    length: u64,
+    channel: u8,
+    key: u8,
+    velocity: u8,
+}
+
+// a track of the file, with its name (if it has one), its notes, and its
+// tempo changes, as ticks and microseconds per quarter note
+#[derive(Default)]
+struct MidiTrack {
+    name: Option<String>,
+    notes: Vec<MidiNote>,
+    tempos: Vec<(u64, u32)>,
+}
+
+// reads through the bytes of a file, or of a chunk of one; each read gives
+// `None` if it would run off the end
+struct Reader<'a> {
+    bytes: &'a [u8],
+    at: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        let bytes = self.bytes.get(self.at..self.at.checked_add(n)?)?;
+        self.at += n;
+        Some(bytes)
+    }
+
+    fn byte(&mut self) -> Option<u8> {
+        self.take(1).map(|b| b[0])
+    }
+
+    fn number(&mut self, n: usize) -> Option<u32> {
+        let bytes = self.take(n)?;
+        Some(bytes.iter().fold(0, |value, &b| value << 8 | b as u32))
+    }
+
+    // a variable-length quantity: seven bits a byte, for as long as the top
+    // bit is set (which it never is for more than four bytes)
+    fn varlen(&mut self) -> Option<u32> {
+        let mut value = 0;
+        for _ in 0..4 {
+            let b = self.byte()?;
+            value = value << 7 | (b & 0x7f) as u32;
+            if b & 0x80 == 0 {
+                return Some(value);
+            }
+        }
+        None
+    }
+
+    fn done(&self) -> bool {
+        self.at >= self.bytes.len()
+    }
+}
+
+// the General MIDI drums, as the waveform that plays each: bass drums and toms
+// are kicks, snares and claps are snares, and the rest are hi-hats
+fn drum(key: u8) -> &'static str {
+    match key {
+        35 | 36 | 41 | 43 | 45 | 47 | 48 | 50 => "BD",
+        37..=40 => "SD",
+        _ => "HH",
+    }
+}
+
+// read the events of a track chunk; a note that is still held when the track
+// ends is let go there, and one that is let go as soon as it starts is dropped
+fn read_track(bytes: &[u8]) -> Option<MidiTrack> {
+    let mut reader = Reader { bytes, at: 0 };
+    let mut track = MidiTrack::default();
+    // the start and velocity of each note being held, by channel and key,
+    // oldest first
+    let mut held: HashMap<(u8, u8), VecDeque<(u64, u8)>> = HashMap::new();
+    let mut tick = 0;
+    let mut status = 0;
+    while !reader.done() {
+        tick += reader.varlen()? as u64;
+        let first = reader.byte()?;
+        // a channel event can leave out its status byte, if it's the same as
+        // the one before (running status)
+        let data = match first {
+            0x80.. => {
+                status = first;
+                None
+            }
+            _ if (0x80..0xf0).contains(&status) => Some(first),
+            _ => return None,
+        };
+        let mut data_byte = || data.map_or_else(|| reader.byte(), Some);
+        match status {
+            0xff => {
+                let kind = reader.byte()?;
+                let length = reader.varlen()? as usize;
+                let body = reader.take(length)?;
+                match kind {
+                    0x03 if track.name.is_none() => {
+                        track.name = Some(String::from_utf8_lossy(body).to_string());
+                    }
+                    0x51 if length == 3 => {
+                        let tempo = Reader { bytes: body, at: 0 }.number(3)?;
+                        track.tempos.push((tick, tempo));
+                    }
+                    0x2f => break,
+                    _ => {}
+                }
+                status = 0;
+            }
+            0xf0 | 0xf7 => {
+                let length = reader.varlen()? as usize;
+                reader.take(length)?;
+                status = 0;
+            }
+            0x80..=0x9f => {
+                let channel = status & 0x0f;
+                let key = data_byte()?;
+                let velocity = reader.byte()?;
+                let notes = held.entry((channel, key)).or_default();
+                if status >= 0x90 && velocity > 0 {
+                    notes.push_back((tick, velocity));
+                } else if let Some((start, velocity)) = notes.pop_front() {
+                    track.notes.push(MidiNote {
+                        start,
+                        length: tick - start,
+                        channel,
+                        key,
+                        velocity,
+                    });
+                }
+            }
+            0xa0..=0xbf | 0xe0..=0xef => {
+                data_byte()?;
+                reader.byte()?;
+            }
+            0xc0..=0xdf => {
+                data_byte()?;
+            }
+            _ => return None,
+        }
+    }
+    for ((channel, key), notes) in held {
+        for (start, velocity) in notes {
+            track.notes.push(MidiNote {
+                start,
+                length: tick - start,
+                channel,
+                key,
+                velocity,
+            });
+        }
+    }
+    track.notes.retain(|note| note.length > 0);
+    track.notes.sort_by_key(|note| (note.start, note.key));
+    Some(track)
+}
+
+// the lines of a score that plays the given MIDI file, each with where in the
+// file it comes from (e.g. `track 2, note at tick 480`); `waves` gives the
+// waveform for some of the tracks, by their number (counting from 1) or name
+pub fn midi_to_score(
+    bytes: &[u8],
+    waves: &[(String, String)],
+) -> Result<Vec<(String, String)>, String> {
+    let mut reader = Reader { bytes, at: 0 };
+    if reader.take(4) != Some(b"MThd") {
+        return Err("not a Standard MIDI File".to_string());
+    }
+    let truncated = || "file is cut short".to_string();
+    let length = reader.number(4).ok_or_else(truncated)? as usize;
+    let mut header = Reader {
+        bytes: reader.take(length).ok_or_else(truncated)?,
+        at: 0,
+    };
+    let (_format, _count) = (header.number(2), header.number(2));
+    let division = header.number(2).ok_or_else(truncated)?;
+    if division & 0x8000 != 0 || division == 0 {
+        return Err("only files timed in ticks per quarter note can be read".to_string());
+    }
+
+    let mut tracks = vec![];
+    while !reader.done() {
+        let kind = reader.take(4).ok_or_else(truncated)?;
+        let length = reader.number(4).ok_or_else(truncated)? as usize;
+        let chunk = reader.take(length).ok_or_else(truncated)?;
+        if kind == b"MTrk" {
+            let number = tracks.len() + 1;
+            let track =
+                read_track(chunk).ok_or_else(|| format!("track {} is malformed", number))?;
+            tracks.push(track);
+        }
+    }
+
+    let mut lines = vec![(format!("PPQ {}", division), "header".to_string())];
+    // the tempo map is the same for every track, wherever it's written
+    let mut tempos = tracks
+        .iter()
+        .flat_map(|track| track.tempos.iter().copied())
+        .collect::<Vec<_>>();
+    tempos.sort_by_key(|&(tick, _)| tick);
+    tempos.dedup_by_key(|&mut (tick, _)| tick);
+    let start = match tempos.first() {
+        Some(&(0, tempo)) => tempo,
+        _ => DEFAULT_TEMPO,
+    };
+    let bpm = |tempo: u32| 60_000_000.0 / tempo.max(1) as f64;
+    lines.push((format!("BPM {}", bpm(start)), "tempo at tick 0".to_string()));
+    for &(tick, tempo) in tempos.iter().filter(|&&(tick, _)| tick > 0) {
+        let at = format!("tempo at tick {}", tick);
+        lines.push((format!("BPM {} @ {}", bpm(tempo), tick), at));
+    }
+
+    let mut names = vec![];
+    for (i, track) in tracks.iter().enumerate() {
+        if track.notes.is_empty() {
+            continue;
+        }
+        let number = (i + 1).to_string();
+        let name = track.name.as_ref().map(|name| name.trim().to_string());
+        let wave = waves
+            .iter()
+            .find(|(t, _)| *t == number || Some(t) == name.as_ref())
+            .map(|(_, wave)| wave.as_str());
+        // a track is mixed under its name, unless it has none that can be
+        // written on a `TRACK` line, or another track has it already
+        let label = match name.map(|name| name.split_whitespace().collect::<Vec<_>>().join("_")) {
+            Some(name) if !name.is_empty() && !names.contains(&name) => name,
+            _ => format!("track{}", number),
+        };
+        lines.push((format!("TRACK {}", label), format!("track {}", number)));
+        names.push(label);
+        for note in track.notes.iter() {
+            let wave = match wave {
+                Some(wave) => wave,
+                None if note.channel == PERCUSSION => drum(note.key),
+                None => "S",
+            };
+            let line = format!(
+                "{} {} {} {:.3} {}",
+                note.start,
+                note.length,
+                note_name(note.key as i32 - 12),
+                note.velocity as f64 / 127.0,
+                wave
+            );
+            let at = format!("track {}, note at tick {}", number, note.start);
+            lines.push((line, at));
+        }
+    }
+    Ok(lines)
+}
+
+// parse the MIDI file at `path`, whose bytes are `bytes`, playing the tracks
+// named in `waves` with the waveforms given there; an error is reported
+// against the part of the file that it comes from
+pub fn parse_midi(
+    bytes: &[u8],
+    path: &Path,
+    options: &PieceOptions,
+    waves: &[(String, String)],
+) -> Result<MusicalPiece, MusicError> {
+    let file = path.display().to_string();
+    let lines = midi_to_score(bytes, waves).map_err(|e| MusicError::StructureError(file, e))?;
+    parse_lines(&lines, path, options, |at: &String, e| {
+        MusicError::StructuredSyntaxError(at.clone(), e)
+    })
+}
+
+// a variable-length quantity, as written in a file
+fn varlen(value: u32) -> Vec<u8> {
+    let mut bytes = vec![(value & 0x7f) as u8];
+    let mut value = value >> 7;
+    while value > 0 {
+        bytes.insert(0, (value & 0x7f) as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes
+}
+
+// a track chunk holding the given events, as the tick each falls on and its
+// bytes, which are written in order of tick (keeping the order of events on
+// the same tick) and followed by the end of the track
+fn track_chunk(mut events: Vec<(u32, Vec<u8>)>) -> Vec<u8> {
+    events.sort_by_key(|&(tick, _)| tick);
+    let mut data = vec![];
+    let mut last = 0;
+    for (tick, event) in events {
+        data.extend(varlen(tick - last));
+        data.extend(event);
+        last = tick;
+    }
+    data.extend([0x00, 0xff, 0x2f, 0x00]);
+    let mut chunk = b"MTrk".to_vec();
+    chunk.extend((data.len() as u32).to_be_bytes());
+    chunk.extend(data);
+    chunk
+}
+
+// a meta event of the given kind
+fn meta(kind: u8, body: &[u8]) -> Vec<u8> {
+    let mut event = vec![0xff, kind];
+    event.extend(varlen(body.len() as u32));
+    event.extend(body);
+    event
+}
+
+// a tempo meta event for the given tempo in beats per minute
+fn tempo_event(bpm: f64) -> Vec<u8> {
+    let tempo = (60_000_000.0 / bpm).round().clamp(1.0, 0xffffff as f64) as u32;
+    meta(0x51, &tempo.to_be_bytes()[1..])
+}
+
+// the bytes of a file of type 1 that plays the given piece; a note that can't
+// be written (below C-1 or above G9) is left out
+pub fn write_midi(piece: &MusicalPiece) -> Vec<u8> {
+    let scale = WRITTEN_PPQ as f64 / 16.0;
+    let tick = |t: f64| (t.max(0.0) * scale).round() as u32;
+
+    // the tempo map, with each ramp stepped through
+    let changes = piece.tempo().changes();
+    let mut conductor = vec![];
+    for (i, &(start, bpm, ramp)) in changes.iter().enumerate() {
+        conductor.push((tick(start), tempo_event(bpm)));
+        if let (true, Some(&(next, _, _))) = (ramp, changes.get(i + 1)) {
+            let mut t = start + RAMP_STEP;
+            while t < next {
+                conductor.push((tick(t), tempo_event(piece.tempo().bpm(t))));
+                t += RAMP_STEP;
+            }
+        }
+    }
+    let mut chunks = vec![track_chunk(conductor)];
+
+    let notes = piece.notes();
+    let names = piece.track_names();
+    let mut channels = (0..16).filter(|&c| c != PERCUSSION).cycle();
+    for (index, name) in names.iter().enumerate() {
+        let notes = notes
+            .iter()
+            .filter(|(_, _, signal)| signal.track == index)
+            .collect::<Vec<_>>();
+        if notes.is_empty() {
+            continue;
+        }
+        let channel = channels.next().unwrap_or_default();
+        let mut events = vec![];
+        if !name.is_empty() {
+            events.push((0, meta(0x03, name.as_bytes())));
+        }
+        // note-offs come before note-ons on the same tick, so that a note
+        // struck again straight away isn't cut short
+        let mut offs = vec![];
+        let mut ons = vec![];
+        for &(start, end, signal) in notes {
+            let (channel, key) = match signal.wavetype {
+                WaveType::Kick => (PERCUSSION, Some(36)),
+                WaveType::Snare => (PERCUSSION, Some(38)),
+                WaveType::HiHat => (PERCUSSION, Some(42)),
+                _ => (channel, signal.pitch.to_midi()),
+            };
+            let Some(key) = key else { continue };
+            let velocity = (signal.ampl * 127.0).round().clamp(1.0, 127.0) as u8;
+            let (start, end) = (tick(start), tick(end));
+            ons.push((start, vec![0x90 | channel, key, velocity]));
+            offs.push((end.max(start + 1), vec![0x80 | channel, key, 0]));
+        }
+        events.extend(offs);
+        events.extend(ons);
+        chunks.push(track_chunk(events));
+    }
+
+    let mut bytes = b"MThd".to_vec();
+    bytes.extend(6u32.to_be_bytes());
+    bytes.extend(1u16.to_be_bytes());
+    bytes.extend((chunks.len() as u16).to_be_bytes());
+    bytes.extend(WRITTEN_PPQ.to_be_bytes());
+    for chunk in chunks {
+        bytes.extend(chunk);
+    }
+    bytes
+}
@@ -0,0 +1,269 @@
+//! This module reads MML (Music Macro Language), the terse notation that
+//! chiptune music has long been written in, such as `t120 o4 l8 cdefgab>c`.
+//! Notes `a` to `g` take `+`/`#` or `-` for a sharp or flat, and a length
+//! (`4` for a quarter) with any dots; `r` is a rest. Then `o` sets the octave,
+//! which `>` and `<` move up and down, `l` the length of notes without one,
+//! `v` the volume (0 to 15), `@` the waveform, and `t` the tempo. A note tied
+//! on with `&`, or a length added with `^`, sounds as one, and `[...]n` plays
+//! what is inside `n` times. MML can be written into a score on `MML` lines,
+//! each note following on from the last, or make up a whole file, in which
+//! each line starts with the channels (`A`, `B`, ...) that it's played on,
+//! each a track of its own.
+
+use crate::error::{MusicError, SyntaxErrorType};
+use crate::piece::{MusicalPiece, PieceOptions};
+use crate::signal::Defaults;
+
+use super::{note_name, on_line, parse_lines, Fraction};
+
+use std::path::Path;
+
+// the waveforms that `@0`, `@1` and so on pick: the square, triangle and
+// sawtooth waves and noise of a sound chip, and a sine wave
+const MML_WAVES: [&str; 5] = ["Q", "T", "A", "N", "S"];
+
+// each note letter, with its semitones above C
+const LETTERS: [(char, i32); 7] = [
+    ('c', 0),
+    ('d', 2),
+    ('e', 4),
+    ('f', 5),
+    ('g', 7),
+    ('a', 9),
+    ('b', 11),
+];
+
+// the settings that carry on from one stretch of MML to the next in a track:
+// the octave, the length of notes without one, and the volume and waveform
+// (which the track's defaults stand in for, until they are set)
+#[derive(Clone)]
+pub struct Mml {
+    octave: i32,
+    length: Fraction,
+    volume: Option<f64>,
+    wave: Option<String>,
+}
+
+impl Default for Mml {
+    fn default() -> Self {
+        Self {
+            octave: 4,
+            length: Fraction::new(1, 4),
+            volume: None,
+            wave: None,
+        }
+    }
+}
+
+impl Mml {
+    // the tempo that a stretch of MML sets, if any (the first, if it sets
+    // more than one), and the note lines that it spells out, each starting
+    // with `+` to follow on from the last; a note's amplitude and waveform
+    // come from `defaults` unless `v` or `@` has set them, and failing that
+    // it's a square wave at full amplitude
+    pub fn read(
+        &mut self,
+        input: &str,
+        defaults: &Defaults,
+    ) -> Result<(Option<f64>, Vec<String>), SyntaxErrorType> {
+        let chars = expand_loops(input)?.chars().collect::<Vec<_>>();
+        let bad = |i: usize| SyntaxErrorType::BadMml(chars[i..].iter().collect());
+        let mut tempo = None;
+        let mut lines = vec![];
+        // the note being written, as its pitch (or `None` for a rest), length,
+        // and amplitude and waveform, which is only finished once it's clear
+        // that no tie carries it on
+        let mut note: Option<(Option<i32>, Fraction, String)> = None;
+        let mut tied = false;
+        let mut i = 0;
+        while i < chars.len() {
+            let start = i;
+            let command = chars[i].to_ascii_lowercase();
+            i += 1;
+            match command {
+                ' ' | '\t' | '|' => {}
+                '>' => self.octave += 1,
+                '<' => self.octave -= 1,
+                '&' => tied = true,
+                'o' => self.octave = number(&chars, &mut i).ok_or_else(|| bad(start))? as i32,
+                'l' => self.length = length(&chars, &mut i).ok_or_else(|| bad(start))?,
+                'v' => match number(&chars, &mut i) {
+                    Some(volume) if volume <= 15 => self.volume = Some(volume as f64 / 15.0),
+                    _ => return Err(bad(start)),
+                },
+                '@' => match number(&chars, &mut i).and_then(|n| MML_WAVES.get(n as usize)) {
+                    Some(wave) => self.wave = Some(wave.to_string()),
+                    None => return Err(bad(start)),
+                },
+                't' => match number(&chars, &mut i) {
+                    Some(bpm) if bpm > 0 => {
+                        tempo = tempo.or(Some(bpm as f64));
+                    }
+                    _ => return Err(bad(start)),
+                },
+                '^' => match (&mut note, length(&chars, &mut i)) {
+                    (Some((_, held, _)), Some(extra)) => *held = *held + extra,
+                    _ => return Err(bad(start)),
+                },
+                'r' | 'a'..='g' => {
+                    let pitch = match LETTERS.iter().find(|&&(l, _)| l == command) {
+                        Some(&(_, semitones)) => {
+                            let mut pitch = 12 * self.octave + semitones;
+                            while let Some(c @ ('+' | '#' | '-')) = chars.get(i) {
+                                pitch += if *c == '-' { -1 } else { 1 };
+                                i += 1;
+                            }
+                            Some(pitch)
+                        }
+                        None => None,
+                    };
+                    let held = match chars.get(i) {
+                        Some(c) if c.is_ascii_digit() => {
+                            length(&chars, &mut i).ok_or_else(|| bad(start))?
+                        }
+                        _ => self.length * dots(&chars, &mut i),
+                    };
+                    // a note tied to one of the same pitch carries it on
+                    match note {
+                        Some((last, ref mut length, _)) if tied && last == pitch => {
+                            *length = *length + held;
+                        }
+                        _ => {
+                            lines
+                                .extend(note.take().map(|(pitch, length, sound)| {
+                                    self.line(pitch, length, &sound)
+                                }));
+                            let ampl = self.volume.or(defaults.ampl).unwrap_or(1.0);
+                            let wave = self.wave.as_ref().or(defaults.wave.as_ref());
+                            let sound = format!("{} {}", ampl, wave.map_or("Q", |w| w));
+                            note = Some((pitch, held, sound));
+                        }
+                    }
+                    tied = false;
+                }
+                _ => return Err(bad(start)),
+            }
+        }
+        lines.extend(note.map(|(pitch, length, sound)| self.line(pitch, length, &sound)));
+        Ok((tempo, lines))
+    }
+
+    // the note line for a note (or rest) that follows on from the last
+    fn line(&self, pitch: Option<i32>, length: Fraction, sound: &str) -> String {
+        match pitch {
+            Some(pitch) => format!("+ {} {} {}", length, note_name(pitch), sound),
+            None => format!("+ {} R", length),
+        }
+    }
+}
+
+// a whole number, if one is written here
+fn number(chars: &[char], i: &mut usize) -> Option<u32> {
+    let start = *i;
+    while *i < chars.len() && chars[*i].is_ascii_digit() {
+        *i += 1;
+    }
+    chars[start..*i].iter().collect::<String>().parse().ok()
+}
+
+// how much any dots written here lengthen a note by: half as much again for
+// one, then half as much as that again for each one after it
+fn dots(chars: &[char], i: &mut usize) -> Fraction {
+    let mut count = 0;
+    while chars.get(*i) == Some(&'.') {
+        count += 1;
+        *i += 1;
+    }
+    Fraction::new(2, 1) - Fraction::new(1, 1 << count.min(8))
+}
+
+// a length written as the kind of note (e.g. `8` for an eighth), with any dots
+fn length(chars: &[char], i: &mut usize) -> Option<Fraction> {
+    match number(chars, i) {
+        Some(n) if n > 0 => Some(Fraction::new(1, n as i64) * dots(chars, i)),
+        _ => None,
+    }
+}
+
+// the longest that a stretch of MML may grow to once its loops are written
+// out, so that nested loops can't multiply it past what memory holds
+const MAX_EXPANDED: usize = 1_000_000;
+
+// write out each loop in full: `[...]n` (or `[...]` for twice) stands for `n`
+// copies of what's inside it, and loops can be nested; a loop played no times
+// at all, or one that would take the whole past `MAX_EXPANDED` characters, is
+// an error
+fn expand_loops(input: &str) -> Result<String, SyntaxErrorType> {
+    let chars = input.chars().collect::<Vec<_>>();
+    let bad = || SyntaxErrorType::BadMml(input.to_string());
+    // the text of each loop still open, innermost last, under that outside
+    let mut open = vec![String::new()];
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '[' => open.push(String::new()),
+            ']' => {
+                i += 1;
+                let count = match chars.get(i) {
+                    Some(c) if c.is_ascii_digit() => number(&chars, &mut i).ok_or_else(bad)?,
+                    _ => 2,
+                };
+                let body = open.pop().filter(|_| !open.is_empty()).ok_or_else(bad)?;
+                // everything written so far, at every level, ends up in the
+                // expansion at least once
+                let written = open.iter().map(String::len).sum::<usize>();
+                let length = body.len().checked_mul(count as usize).ok_or_else(bad)?;
+                if count == 0 || written.saturating_add(length) > MAX_EXPANDED {
+                    return Err(bad());
+                }
+                let outer = open.last_mut().ok_or_else(bad)?;
+                outer.push_str(&body.repeat(count as usize));
+                continue;
+            }
+            c => open.last_mut().ok_or_else(bad)?.push(c),
+        }
+        i += 1;
+    }
+    match open.len() {
+        1 => Ok(open.pop().unwrap_or_default()),
+        _ => Err(bad()),
+    }
+}
+
+// the lines of a score that plays the given MML file, each with the line of
+// the file that it comes from; a line starts with the letters of the channels
+// that it's played on (or carries on with those of the line before), `;`
+// starts a comment, and of the lines starting with `#`, only a
+// `#wav-maker` line means anything: the rest of it is a line of the score
+pub fn mml_to_score(input: &str) -> Vec<(String, usize)> {
+    let mut lines = vec![];
+    for (n, text) in input.lines().enumerate() {
+        let text = text.split(';').next().unwrap_or_default().trim();
+        if let Some(header) = text.strip_prefix('#') {
+            if let Some(line) = header.strip_prefix("wav-maker") {
+                lines.push((line.trim().to_string(), n + 1));
+            }
+            continue;
+        }
+        let (first, rest) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+        if !first.is_empty() && first.chars().all(|c| c.is_ascii_uppercase()) {
+            for channel in first.chars() {
+                lines.push((format!("TRACK {}", channel), n + 1));
+                lines.push((format!("MML {}", rest), n + 1));
+            }
+        } else if !text.is_empty() {
+            lines.push((format!("MML {}", text), n + 1));
+        }
+    }
+    lines
+}
+
+// parse a file of MML, which is in the file at `path`; an error is reported
+// against the line of the file that it comes from
+pub fn parse_mml(
+    input: &str,
+    path: &Path,
+    options: &PieceOptions,
+) -> Result<MusicalPiece, MusicError> {
+    parse_lines(&mml_to_score(input), path, options, on_line)
+}
@@ -0,0 +1,119 @@
+//! This module holds the readers for other notations that a score can be
+//! written in, each of which converts its notation into the lines of an
+//! ordinary score, so that the piece is parsed and played just as if it had
+//! been written out by hand.
+
+pub mod abc;
+pub mod csv;
+pub mod midi;
+pub mod mml;
+pub mod musicxml;
+
+use crate::error::{MusicError, SyntaxErrorType};
+use crate::piece::{MusicalPiece, PieceOptions};
+
+use std::fmt;
+use std::ops::{Add, Mul, Sub};
+use std::path::Path;
+
+// the names that notes are written out with, from C up to B
+const NOTE_NAMES: [&str; 12] = [
+    "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+];
+
+// an exact length or time, as a fraction of a whole note
+#[derive(Clone, Copy, PartialEq)]
+struct Fraction {
+    num: i64,
+    den: i64,
+}
+
+impl Fraction {
+    const ZERO: Self = Self { num: 0, den: 1 };
+
+    fn new(num: i64, den: i64) -> Self {
+        let divisor = gcd(num.abs(), den.abs()).max(1) * den.signum();
+        Self {
+            num: num / divisor,
+            den: den / divisor,
+        }
+    }
+
+    fn ticks(self) -> f64 {
+        64.0 * self.num as f64 / self.den as f64
+    }
+}
+
+fn gcd(a: i64, b: i64) -> i64 {
+    match b {
+        0 => a,
+        _ => gcd(b, a % b),
+    }
+}
+
+impl Add for Fraction {
+    type Output = Self;
+    fn add(self, other: Self) -> Self {
+        Self::new(
+            self.num * other.den + other.num * self.den,
+            self.den * other.den,
+        )
+    }
+}
+
+impl Sub for Fraction {
+    type Output = Self;
+    fn sub(self, other: Self) -> Self {
+        Self::new(
+            self.num * other.den - other.num * self.den,
+            self.den * other.den,
+        )
+    }
+}
+
+impl Mul for Fraction {
+    type Output = Self;
+    fn mul(self, other: Self) -> Self {
+        Self::new(self.num * other.num, self.den * other.den)
+    }
+}
+
+impl fmt::Display for Fraction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.num, self.den)
+    }
+}
+
+// the name of the note this many semitones above C0, such as `F#4`
+fn note_name(pitch: i32) -> String {
+    let name = NOTE_NAMES[pitch.rem_euclid(12) as usize];
+    format!("{}{}", name, pitch.div_euclid(12))
+}
+
+// the error for something wrong on the given line of the file
+fn on_line(&line: &usize, e: SyntaxErrorType) -> MusicError {
+    MusicError::SyntaxError(line, e)
+}
+
+// parse the lines of a score converted from another notation, in the file at
+// `path`, each with where in the file it comes from, which `located` turns an
+// error in it into an error against
+fn parse_lines<T>(
+    lines: &[(String, T)],
+    path: &Path,
+    options: &PieceOptions,
+    located: impl Fn(&T, SyntaxErrorType) -> MusicError,
+) -> Result<MusicalPiece, MusicError> {
+    let text = lines
+        .iter()
+        .map(|(line, _)| line.as_str())
+        .collect::<Vec<_>>()
+        .join("\n");
+    MusicalPiece::new(&text, path, options).map_err(|e| match e {
+        MusicError::SyntaxError(n, e) => match lines.get(n.wrapping_sub(1)) {
+            Some((_, at)) => located(at, e),
+            None => MusicError::SyntaxError(n, e),
+        },
+        e => e,
+    })
+}
@@ -0,0 +1,233 @@
+//! This module reads scores in MusicXML, the format that notation programs
+//! such as MuseScore export to, so that they can be heard without being typed
+//! out again. Only a subset is understood: the notes and rests of the first
+//! part, with their pitches and durations, chords, ties, the other voices that
+//! `<backup>` and `<forward>` make room for, the tempo set by `<sound>`, and
+//! dynamics such as `<mf/>`, which set the amplitude of the notes after them.
+//! Grace and cue notes are left out, and repeats are played as written, once.
+
+use crate::error::MusicError;
+use crate::piece::{MusicalPiece, PieceOptions};
+use crate::signal::DYNAMICS;
+
+use super::{note_name, parse_lines, Fraction};
+
+use roxmltree::{Document, Node, ParsingOptions};
+use std::collections::HashMap;
+use std::path::Path;
+
+// each step, with its semitones above C
+const STEPS: [(&str, i32); 7] = [
+    ("C", 0),
+    ("D", 2),
+    ("E", 4),
+    ("F", 5),
+    ("G", 7),
+    ("A", 9),
+    ("B", 11),
+];
+
+// a note or rest (which has no pitch), with its start and length, the
+// amplitude that it's played at, and which note of which measure it is
+struct XmlNote {
+    start: Fraction,
+    length: Fraction,
+    pitch: Option<i32>,
+    ampl: String,
+    at: String,
+}
+
+// the trimmed text of the named child of an element, if it has one
+fn child_text<'a>(node: Node<'a, '_>, name: &str) -> Option<&'a str> {
+    node.children()
+        .find(|child| child.has_tag_name(name))
+        .and_then(|child| child.text())
+        .map(str::trim)
+}
+
+fn has_child(node: Node, name: &str) -> bool {
+    node.children().any(|child| child.has_tag_name(name))
+}
+
+// the `<duration>` of an element, in divisions of a quarter note, as a
+// fraction of a whole note
+fn duration(node: Node, divisions: i64) -> Option<Fraction> {
+    let duration = child_text(node, "duration")?.parse::<i64>().ok()?;
+    (duration >= 0).then(|| Fraction::new(duration, 4 * divisions))
+}
+
+// the pitch of a `<pitch>` element, in semitones above C0 (a fractional
+// alteration, for a microtone, is rounded to the nearest semitone)
+fn pitch(node: Node) -> Option<i32> {
+    let step = child_text(node, "step")?;
+    let &(_, semitones) = STEPS.iter().find(|&&(s, _)| s == step)?;
+    let alter = match child_text(node, "alter") {
+        Some(alter) => alter.parse::<f64>().ok()?.round() as i32,
+        None => 0,
+    };
+    let octave = child_text(node, "octave")?.parse::<i32>().ok()?;
+    Some(12 * octave + semitones + alter)
+}
+
+// whether a note has a tie of the given type (`start` or `stop`)
+fn tie(node: Node, kind: &str) -> bool {
+    node.children()
+        .any(|child| child.has_tag_name("tie") && child.attribute("type") == Some(kind))
+}
+
+// the lines of a score that plays the first part of the given MusicXML
+// score, each with where in the score it comes from (e.g. `measure 3, note 2`)
+pub fn musicxml_to_score(input: &str) -> Result<Vec<(String, String)>, String> {
+    // exported scores start with a `<!DOCTYPE>` naming the MusicXML DTD
+    let options = ParsingOptions {
+        allow_dtd: true,
+        ..ParsingOptions::default()
+    };
+    let document = Document::parse_with_options(input, options).map_err(|e| e.to_string())?;
+    let root = document.root_element();
+    if !root.has_tag_name("score-partwise") {
+        return Err("only partwise MusicXML scores can be read".to_string());
+    }
+    let part = root
+        .children()
+        .find(|node| node.has_tag_name("part"))
+        .ok_or("the score has no parts")?;
+
+    let mut notes: Vec<XmlNote> = vec![];
+    let mut tempos: Vec<(Fraction, f64, String)> = vec![];
+    let mut divisions = 1;
+    let mut ampl = "1".to_string();
+    // where the next note starts, and where the last one started, which is
+    // where a note of a chord with it starts too
+    let mut time = Fraction::ZERO;
+    let mut last = Fraction::ZERO;
+    // the note that a tie from each pitch carries on into
+    let mut tied: HashMap<i32, usize> = HashMap::new();
+    let measures = part.children().filter(|node| node.has_tag_name("measure"));
+    for (m, measure) in measures.enumerate() {
+        let number = measure
+            .attribute("number")
+            .map_or_else(|| (m + 1).to_string(), str::to_string);
+        let bad = |what: &str| format!("measure {}: invalid {}", number, what);
+        let mut count = 0;
+        for element in measure.children().filter(Node::is_element) {
+            match element.tag_name().name() {
+                "attributes" => {
+                    if let Some(value) = child_text(element, "divisions") {
+                        divisions = value
+                            .parse::<i64>()
+                            .ok()
+                            .filter(|&d| d > 0)
+                            .ok_or_else(|| bad("divisions"))?;
+                    }
+                }
+                "backup" => {
+                    time = time - duration(element, divisions).ok_or_else(|| bad("backup"))?
+                }
+                "forward" => {
+                    time = time + duration(element, divisions).ok_or_else(|| bad("forward"))?
+                }
+                "direction" | "sound" => {
+                    for node in element.descendants() {
+                        if let Some(tempo) = node
+                            .attribute("tempo")
+                            .filter(|_| node.has_tag_name("sound"))
+                        {
+                            let tempo = tempo.parse::<f64>().ok().filter(|t| *t > 0.0);
+                            let at = format!("measure {}, tempo", number);
+                            tempos.push((time, tempo.ok_or_else(|| bad("tempo"))?, at));
+                        }
+                        if node.is_element()
+                            && node.parent().is_some_and(|p| p.has_tag_name("dynamics"))
+                        {
+                            let name = node.tag_name().name();
+                            if DYNAMICS.iter().any(|&(dynamic, _)| dynamic == name) {
+                                ampl = name.to_string();
+                            }
+                        }
+                    }
+                }
+                "note" => {
+                    if has_child(element, "grace") || has_child(element, "cue") {
+                        continue;
+                    }
+                    count += 1;
+                    let length = duration(element, divisions).ok_or_else(|| bad("duration"))?;
+                    let chord = has_child(element, "chord");
+                    let start = if chord { last } else { time };
+                    if !chord {
+                        (last, time) = (time, time + length);
+                    }
+                    let pitch = match element.children().find(|node| node.has_tag_name("pitch")) {
+                        Some(node) => Some(pitch(node).ok_or_else(|| bad("pitch"))?),
+                        None => None,
+                    };
+                    // a note tied from the one before sounds on as part of it
+                    if let Some(pitch) = pitch.filter(|_| tie(element, "stop")) {
+                        if let Some(&index) = tied.get(&pitch) {
+                            notes[index].length = notes[index].length + length;
+                            if !tie(element, "start") {
+                                tied.remove(&pitch);
+                            }
+                            continue;
+                        }
+                    }
+                    if let Some(pitch) = pitch.filter(|_| tie(element, "start")) {
+                        tied.insert(pitch, notes.len());
+                    }
+                    notes.push(XmlNote {
+                        start,
+                        length,
+                        pitch,
+                        ampl: ampl.clone(),
+                        at: format!("measure {}, note {}", number, count),
+                    });
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let mut lines = vec![("DEFAULT WAVE S".to_string(), "score".to_string())];
+    // the first tempo set at the start is where the piece starts, and the rest
+    // are changes of tempo
+    tempos.dedup_by_key(|(start, _, _)| *start);
+    for (start, bpm, at) in tempos {
+        let line = match start == Fraction::ZERO {
+            true => format!("BPM {}", bpm),
+            false => format!("BPM {} @ {}", bpm, start.ticks()),
+        };
+        lines.push((line, at));
+    }
+    for note in notes
+        .into_iter()
+        .filter(|note| note.length != Fraction::ZERO)
+    {
+        let line = match note.pitch {
+            Some(pitch) => format!(
+                "{} {} {} {}",
+                note.start.ticks(),
+                note.length,
+                note_name(pitch),
+                note.ampl
+            ),
+            None => format!("{} {} R", note.start.ticks(), note.length),
+        };
+        lines.push((line, note.at));
+    }
+    Ok(lines)
+}
+
+// parse a MusicXML score, which is in the file at `path`; an error in a note
+// is reported against the measure and note that it comes from
+pub fn parse_musicxml(
+    input: &str,
+    path: &Path,
+    options: &PieceOptions,
+) -> Result<MusicalPiece, MusicError> {
+    let file = path.display().to_string();
+    let lines = musicxml_to_score(input).map_err(|e| MusicError::StructureError(file, e))?;
+    parse_lines(&lines, path, options, |at: &String, e| {
+        MusicError::StructuredSyntaxError(at.clone(), e)
+    })
+}
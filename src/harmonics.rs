@@ -0,0 +1,60 @@
+//! This module defines additive timbres: named series of harmonic amplitudes,
+//! set up with the `HARMONICS` directive, which are played by summing a sine
+//! partial for each harmonic that falls below the Nyquist frequency.
+
+use crate::error::SyntaxErrorType;
+
+use std::collections::HashMap;
+use std::f64::consts::PI;
+
+// every harmonic series defined so far, so that a waveform can refer to its
+// series by index; redefining a name only affects the notes after it
+#[derive(Default)]
+pub struct Harmonics {
+    names: HashMap<String, usize>,
+    series: Vec<Vec<f64>>,
+}
+
+impl Harmonics {
+    // define the series given by the arguments of a `HARMONICS` directive,
+    // which are a name followed by the amplitude of each harmonic in turn
+    pub fn define(&mut self, args: &[&str]) -> Result<(), SyntaxErrorType> {
+        let bad = || SyntaxErrorType::BadHarmonics(args.join(" "));
+        let (name, amplitudes) = args.split_first().ok_or_else(bad)?;
+        let amplitudes = amplitudes
+            .iter()
+            .map(|s| s.parse::<f64>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| bad())?;
+        let index = self.add(amplitudes).ok_or_else(bad)?;
+        self.names.insert(name.to_string(), index);
+        Ok(())
+    }
+
+    // add a series of harmonic amplitudes, scaled so that they sum to 1 and
+    // so can never peak above full amplitude, returning its index
+    pub fn add(&mut self, mut amplitudes: Vec<f64>) -> Option<usize> {
+        let total = amplitudes.iter().map(|a| a.abs()).sum::<f64>();
+        if total == 0.0 || !total.is_finite() {
+            return None;
+        }
+        amplitudes.iter_mut().for_each(|a| *a /= total);
+        self.series.push(amplitudes);
+        Some(self.series.len() - 1)
+    }
+
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.names.get(name).copied()
+    }
+
+    // the value of a series at the given phase (in cycles) of a fundamental
+    // at `freq` Hz, leaving out any harmonic at or above Nyquist
+    pub fn sample(&self, index: usize, phase: f64, freq: f64, rate: u32) -> f64 {
+        self.series[index]
+            .iter()
+            .enumerate()
+            .take_while(|&(k, _)| (k + 1) as f64 * freq < rate as f64 / 2.0)
+            .map(|(k, a)| a * f64::sin(2.0 * PI * (k + 1) as f64 * phase))
+            .sum()
+    }
+}
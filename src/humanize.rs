@@ -0,0 +1,49 @@
+//! This module defines humanization, which nudges the start and amplitude of
+//! each note by a small random amount, so that a piece doesn't sound quite so
+//! mechanically exact. The amounts come from a generator seeded with the
+//! piece's seed (0 unless `--seed` says otherwise), so rendering the same
+//! piece with the same seed always gives the same output.
+
+use crate::error::SyntaxErrorType;
+use crate::meter::{parse_ticks, Meter};
+use crate::noise::Noise;
+
+// how far a note may be moved either way from where it's written: up to
+// `timing` ticks earlier or later, and up to `ampl` of its amplitude (e.g.
+// 0.05 for 5%) louder or quieter
+#[derive(Clone, Copy, Default)]
+pub struct Humanize {
+    pub timing: f64,
+    pub ampl: f64,
+}
+
+impl Humanize {
+    // parse the arguments of a `HUMANIZE [timing=<ticks>] [ampl=<fraction>]`
+    // directive, with the ticks written at the meter's resolution; whatever
+    // is left out isn't humanized, so a bare `HUMANIZE` turns it off
+    pub fn new(args: &[&str], meter: &Meter) -> Result<Self, SyntaxErrorType> {
+        let bad = || SyntaxErrorType::BadHumanize(args.join(" "));
+        let mut humanize = Self::default();
+        for arg in args {
+            match arg.split_once('=').ok_or_else(bad)? {
+                ("timing", ticks) => {
+                    humanize.timing = meter.ticks(parse_ticks(ticks).ok_or_else(bad)?)
+                }
+                ("ampl", ampl) => match ampl.parse::<f64>() {
+                    Ok(ampl) if (0.0..=1.0).contains(&ampl) => humanize.ampl = ampl,
+                    _ => return Err(bad()),
+                },
+                _ => return Err(bad()),
+            }
+        }
+        Ok(humanize)
+    }
+
+    // a random offset for a note's start, in ticks, and a factor to scale its
+    // amplitude by, drawn from the given generator
+    pub fn jitter(&self, noise: &mut Noise) -> (f64, f64) {
+        let ticks = self.timing * noise.white();
+        let factor = 1.0 + self.ampl * noise.white();
+        (ticks, factor)
+    }
+}
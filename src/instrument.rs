@@ -0,0 +1,111 @@
+//! This module defines patches and instruments. A patch is the set of
+//! note-shaping settings (envelope, vibrato, filter and so on) that the
+//! directives for them pile up, to be handed to every note that doesn't set
+//! its own; an instrument is a waveform with a patch of its own, defined in an
+//! `INSTRUMENT` block and played by writing `I:<name>` as a note's waveform.
+//! A handful of preset instruments come built in, and can be played by name.
+
+use crate::envelope::Envelope;
+use crate::error::SyntaxErrorType;
+use crate::filter::{Filter, FilterEnvelope};
+use crate::piece::Directive;
+use crate::signal::{NoteSignal, Tremolo, Unison, Vibrato, WaveType};
+use crate::timbre::Timbres;
+
+#[derive(Clone, Copy, Default)]
+pub struct Patch {
+    pub envelope: Option<Envelope>,
+    pub vibrato: Option<Vibrato>,
+    pub tremolo: Option<Tremolo>,
+    pub unison: Option<Unison>,
+    pub filter: Option<Filter>,
+    pub filter_envelope: Option<FilterEnvelope>,
+}
+
+#[derive(Clone, Copy)]
+pub struct Instrument {
+    pub wavetype: WaveType,
+    pub patch: Patch,
+}
+
+// the built-in instruments: a name, a waveform (or, if the series isn't
+// empty, the amplitudes of an additive timbre) and the lines that set up the
+// patch, just as they'd be written in an `INSTRUMENT` block
+#[allow(clippy::type_complexity)]
+pub const PRESETS: [(&str, &str, &[f64], &[&str]); 6] = [
+    (
+        "organ",
+        "",
+        &[1.0, 0.5, 0.33, 0.0, 0.25, 0.0, 0.0, 0.2],
+        &["ENV 0.01 0 1 0.05", "VIB 6 4"],
+    ),
+    ("pluck", "P", &[], &["FILTER LP 5000 0.7"]),
+    (
+        "pad",
+        "A",
+        &[],
+        &["ENV 0.6 0.4 0.8 0.8", "UNISON 5 12", "FILTER LP 1800 0.8"],
+    ),
+    (
+        "bass",
+        "A",
+        &[],
+        &[
+            "ENV 0.005 0.1 0.8 0.05",
+            "FILTER LP 400 3",
+            "FENV 0.005 0.15 0.2 0.05 2",
+        ],
+    ),
+    ("bell", "FM:3.5,2", &[], &["ENV 0.002 1.5 0 0.3"]),
+    (
+        "chip-lead",
+        "Q",
+        &[],
+        &["ENV 0.005 0.05 0.8 0.05", "VIB 6 15"],
+    ),
+];
+
+impl Instrument {
+    // build the preset with the given name, if there is one
+    pub fn preset(name: &str, timbres: &mut Timbres) -> Option<Self> {
+        let &(_, waveform, series, lines) = PRESETS.iter().find(|(n, _, _, _)| *n == name)?;
+        let wavetype = if series.is_empty() {
+            WaveType::new(waveform, timbres).ok()?
+        } else {
+            WaveType::Harmonics(timbres.harmonics.add(series.to_vec())?)
+        };
+        let mut patch = Patch::default();
+        for line in lines {
+            let words = line.split_whitespace().collect::<Vec<_>>();
+            patch.set(Directive::find(words[0])?, &words[1..]).ok()?;
+        }
+        Some(Self { wavetype, patch })
+    }
+}
+
+impl Patch {
+    // set whichever part of the patch the given directive is for; any other
+    // directive leaves the patch alone
+    pub fn set(&mut self, directive: Directive, args: &[&str]) -> Result<(), SyntaxErrorType> {
+        match directive {
+            Directive::Envelope => self.envelope = Some(Envelope::new(args)?),
+            Directive::Vibrato => self.vibrato = Some(Vibrato::new(&args.join(","))?),
+            Directive::Tremolo => self.tremolo = Some(Tremolo::new(&args.join(","))?),
+            Directive::Unison => self.unison = Some(Unison::new(&args.join(","))?),
+            Directive::Filter => self.filter = Some(Filter::new(args)?),
+            Directive::FilterEnvelope => self.filter_envelope = Some(FilterEnvelope::new(args)?),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    // give a signal every part of the patch that it doesn't have already
+    pub fn apply(&self, signal: &mut NoteSignal) {
+        signal.envelope = signal.envelope.or(self.envelope);
+        signal.vibrato = signal.vibrato.or(self.vibrato);
+        signal.tremolo = signal.tremolo.or(self.tremolo);
+        signal.unison = signal.unison.or(self.unison);
+        signal.filter = signal.filter.or(self.filter);
+        signal.filter_envelope = signal.filter_envelope.or(self.filter_envelope);
+    }
+}
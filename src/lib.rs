@@ -0,0 +1,35 @@
+//! wav-maker turns a simple text score into a PCM WAV file. The text
+//! format is parsed by `MusicalPiece::new()` (after `read_score()` has read it
+//! in, if it's on disk), but a piece can equally be put
+//! together in code with `PieceBuilder` and then synthesized the same way. A
+//! score can also be written as JSON or TOML, or in ABC notation, all of
+//! which `parse_score()` reads.
+
+pub mod chord;
+pub mod effects;
+pub mod envelope;
+pub mod error;
+pub mod filter;
+pub mod formats;
+pub mod harmonics;
+pub mod humanize;
+pub mod instrument;
+pub mod mastering;
+pub mod meter;
+pub mod mixer;
+pub mod noise;
+pub mod note;
+pub mod ornament;
+pub mod piece;
+pub mod reference;
+pub mod sampler;
+pub mod signal;
+pub mod source;
+pub mod speakers;
+pub mod structured;
+pub mod tempo;
+pub mod timbre;
+pub mod tuning;
+pub mod utils;
+pub mod voice;
+pub mod wavetable;
@@ -1,53 +1,379 @@
-mod error;
-mod note;
-mod piece;
-mod signal;
-mod utils;
-
-use crate::error::MusicError;
-use crate::piece::MusicalPiece;
-use crate::utils::header;
+use wav_maker::effects::{Effect, FadeLength};
+use wav_maker::error::MusicError;
+use wav_maker::formats::csv::write_csv;
+use wav_maker::formats::midi::{parse_midi, write_midi};
+use wav_maker::mastering::{db_to_gain, limit, loudness, normalize, remove_dc};
+use wav_maker::piece::PieceOptions;
+use wav_maker::reference::token_reference;
+use wav_maker::signal::{parse_base_amplitude, VelocityCurve};
+use wav_maker::source::{read_bytes, read_score};
+use wav_maker::speakers::layout;
+use wav_maker::structured::{parse_score, Format};
+use wav_maker::tuning::{parse_tonic, Temperament, Tuning};
+use wav_maker::utils::{
+    quantize, write_wav, Dither, Metadata, WavFormat, BIT_DEPTHS, DEFAULT_BIT_DEPTH,
+    DEFAULT_CLICK_GUARD, DEFAULT_MAX_DURATION,
+};
 
 use clap::Parser;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::process::ExitCode;
 
 #[derive(Parser)]
 struct Args {
-    file: String,
+    #[arg(required_unless_present = "list_tokens")]
+    file: Option<String>,
+    /// where to write the WAV file (the score's name with a .wav extension
+    /// unless given, or - for stdout), creating any directories on the way to it
+    #[arg(short, long, value_name = "FILE")]
+    output: Option<String>,
     #[arg(short, long)]
     bpm: Option<f64>,
-    #[arg(short, long)]
+    /// base amplitude, out of 32767 or in dB relative to full scale (e.g. -6dB)
+    #[arg(short, long, value_parser = parse_ampl, allow_hyphen_values = true)]
     ampl: Option<u16>,
+    /// the format of the score, if not the one its extension suggests
+    #[arg(long, value_enum)]
+    format: Option<Format>,
+    /// read the file as a Standard MIDI File, whatever its name
+    #[arg(long, conflicts_with = "format")]
+    from_midi: bool,
+    /// read the file as a table of notes, whatever its name
+    #[arg(long, conflicts_with_all = ["format", "from_midi"])]
+    from_csv: bool,
+    /// waveform for a MIDI track, by its number (from 1) or name (may be repeated)
+    #[arg(long, value_name = "TRACK=WAVE", value_parser = parse_midi_wave)]
+    midi_wave: Vec<(String, String)>,
+    /// write the piece out as a MIDI file here, instead of rendering it
+    #[arg(long, value_name = "FILE")]
+    emit_midi: Option<String>,
+    /// write the piece's notes out as a CSV table here, instead of rendering it
+    #[arg(long, value_name = "FILE")]
+    emit_csv: Option<String>,
+    /// write a "fact" chunk giving the number of sample frames
+    #[arg(long)]
+    fact: bool,
+    /// add a cue point at the given tick (may be repeated)
+    #[arg(long)]
+    cue: Vec<u32>,
+    /// title to tag the WAV file with, overriding any TITLE directive
+    #[arg(long, value_name = "TEXT")]
+    title: Option<String>,
+    /// artist to tag the WAV file with, overriding any ARTIST directive
+    #[arg(long, value_name = "TEXT")]
+    artist: Option<String>,
+    /// comment to tag the WAV file with, overriding any COMMENT directive
+    #[arg(long, value_name = "TEXT")]
+    comment: Option<String>,
+    /// how note amplitudes map onto loudness
+    #[arg(long, value_enum, default_value_t)]
+    velocity_curve: VelocityCurve,
+    /// tune notes in this temperament, overriding any TEMPERAMENT directive
+    #[arg(long, value_enum)]
+    temperament: Option<Temperament>,
+    /// tune notes to the scale in this Scala (.scl) file, a degree per semitone
+    #[arg(long, value_name = "FILE", conflicts_with = "temperament")]
+    tuning: Option<String>,
+    /// frequency in Hz that A4 is tuned to, overriding any A4 directive
+    #[arg(long, value_parser = parse_reference)]
+    a4: Option<f64>,
+    /// seed for the random nudges of HUMANIZE, so that renders can vary
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+    /// the tonic that the temperament or scale is built on (C unless given)
+    #[arg(long, value_parser = parse_tonic_arg)]
+    tonic: Option<i32>,
+    /// reject repeated directives and directives that disagree with flags
+    #[arg(long)]
+    strict: bool,
+    /// length in milliseconds of the fade at each end of every note
+    #[arg(long, default_value_t = DEFAULT_CLICK_GUARD)]
+    click_guard: f64,
+    /// refuse to render pieces longer than this many seconds
+    #[arg(long, default_value_t = DEFAULT_MAX_DURATION)]
+    max_duration: f64,
+    /// number of output channels: 1 (mono), 2 (stereo, honouring note pans),
+    /// 4 (quad), 6 (5.1) or 8 (7.1)
+    #[arg(long, default_value_t = 1, value_parser = parse_channels)]
+    channels: u16,
+    /// output sample rate in Hz
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    sample_rate: Option<u32>,
+    /// bits per output sample
+    #[arg(long, default_value_t = DEFAULT_BIT_DEPTH, value_parser = parse_bit_depth)]
+    bit_depth: u16,
+    /// write 32-bit IEEE float samples, which are never clipped
+    #[arg(long, conflicts_with = "bit_depth")]
+    float: bool,
+    /// dither integer samples as they're quantized, optionally noise-shaped
+    #[arg(long, value_enum, num_args = 0..=1, default_missing_value = "tpdf")]
+    dither: Option<Dither>,
+    /// use band-limited square, triangle and sawtooth waves to avoid aliasing
+    #[arg(long)]
+    hq: bool,
+    /// add a reverb, unless the piece has one already
+    #[arg(long)]
+    reverb: bool,
+    /// compress the piece, in place of any compressor it sets up itself
+    #[arg(long, value_name = "THRESHOLD,RATIO,ATTACK,RELEASE")]
+    #[arg(allow_hyphen_values = true, value_parser = parse_compressor)]
+    compress: Option<Effect>,
+    /// fade the piece in over this many seconds, or this note duration
+    #[arg(long, value_parser = parse_fade)]
+    fade_in: Option<FadeLength>,
+    /// fade the piece out over this many seconds, or this note duration
+    #[arg(long, value_parser = parse_fade)]
+    fade_out: Option<FadeLength>,
+    /// leave any DC offset in the mix, rather than filtering it out
+    #[arg(long)]
+    no_dc_block: bool,
+    /// squash peaks smoothly as they near full scale, rather than clipping them
+    #[arg(long)]
+    limiter: bool,
+    /// scale the mix so that its peak sits at this level in dBFS (default -1)
+    #[arg(long, value_name = "DBFS", num_args = 0..=1, default_missing_value = "-1")]
+    #[arg(allow_negative_numbers = true, value_parser = parse_peak)]
+    normalize: Option<f64>,
+    /// scale the mix to this integrated loudness in LUFS (e.g. -14)
+    #[arg(long, value_name = "LUFS", conflicts_with = "normalize")]
+    #[arg(allow_negative_numbers = true, value_parser = parse_loudness)]
+    target_lufs: Option<f64>,
+    /// turn the mix down just enough that its notes can never clip together
+    #[arg(long, conflicts_with_all = ["normalize", "target_lufs"])]
+    headroom: bool,
+    /// print every token the input format understands, then exit
+    #[arg(long, exclusive = true)]
+    list_tokens: bool,
 }
 
-fn main() {
+fn main() -> ExitCode {
     match run() {
-        Ok(_) => return,
-        Err(e) => println!("{}", e),
+        Ok(_) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
     }
 }
 
 fn run() -> Result<(), MusicError> {
     let args = Args::parse();
 
-    let input = match std::fs::read_to_string(&args.file) {
-        Ok(s) => s,
-        Err(_) => return Err(MusicError::FileReadError(args.file)),
-    };
+    if args.list_tokens {
+        print!("{}", token_reference());
+        return Ok(());
+    }
+    // clap makes sure a file is given whenever --list-tokens isn't
+    let file = args.file.unwrap_or_default();
 
     // generate output waveform values
-    let piece = MusicalPiece::new(&input, args.bpm, args.ampl)?;
-    let data = piece.synthesize();
+    let tonic = args.tonic.unwrap_or_default();
+    let tuning = match (&args.tuning, args.temperament) {
+        (Some(scl), _) => {
+            let input = read_score(scl)?;
+            let scale = Tuning::from_scala(&input, tonic);
+            Some(scale.map_err(|e| MusicError::StructureError(scl.clone(), e))?)
+        }
+        (None, Some(temperament)) => Some(Tuning::temperament(temperament, tonic)),
+        (None, None) => None,
+    };
+    let options = PieceOptions {
+        bpm: args.bpm,
+        ampl: args.ampl,
+        velocity_curve: args.velocity_curve,
+        strict: args.strict,
+        click_guard: args.click_guard,
+        max_duration: args.max_duration,
+        channels: args.channels,
+        sample_rate: args.sample_rate,
+        band_limited: args.hq,
+        reverb: args.reverb,
+        fade_in: args.fade_in,
+        fade_out: args.fade_out,
+        compressor: args.compress,
+        tuning,
+        a4: args.a4,
+        seed: args.seed,
+        metadata: Metadata {
+            title: args.title,
+            artist: args.artist,
+            comment: args.comment,
+        },
+    };
+    let format = match (args.from_midi, args.from_csv) {
+        (true, _) => Format::Midi,
+        (_, true) => Format::Csv,
+        _ => args.format.unwrap_or_else(|| Format::detect(&file)),
+    };
+    let path = Path::new(&file);
+    let piece = match format {
+        Format::Midi => parse_midi(&read_bytes(&file)?, path, &options, &args.midi_wave)?,
+        format => parse_score(&read_score(&file)?, format, path, &options)?,
+    };
+    // writing the piece out in another format takes the place of rendering it
+    if let Some(midi) = &args.emit_midi {
+        emit(midi, &write_midi(&piece))?;
+    }
+    if let Some(csv) = &args.emit_csv {
+        emit(csv, write_csv(&piece).as_bytes())?;
+    }
+    if args.emit_midi.is_some() || args.emit_csv.is_some() {
+        return Ok(());
+    }
+    let format = WavFormat {
+        bits_per_sample: if args.float { 32 } else { args.bit_depth },
+        float: args.float,
+        ..piece.wav_format()
+    };
+    let mut samples = piece.synthesize()?;
+    if !args.no_dc_block {
+        remove_dc(&mut samples, format.channels as usize, format.sample_rate);
+    }
+    if args.headroom {
+        let gain = 1.0 / piece.peak_amplitude().max(1.0);
+        samples.iter_mut().for_each(|s| *s *= gain);
+        eprintln!("headroom: applied a gain of {:.2} dB", 20.0 * gain.log10());
+    }
+    if let Some(target) = args.target_lufs {
+        let channels = format.channels as usize;
+        if let Some(measured) = loudness(&samples, channels, format.sample_rate) {
+            let gain = db_to_gain(target - measured);
+            samples.iter_mut().for_each(|s| *s *= gain);
+        }
+    }
+    if args.limiter {
+        limit(&mut samples);
+    }
+    if let Some(target) = args.normalize {
+        normalize(&mut samples, target);
+    }
+    let data = quantize(&samples, &format, args.dither);
+
+    // cue points are given in ticks, but the file wants sample offsets; those
+    // from the command line have no names, unlike the piece's own markers
+    let cues = args
+        .cue
+        .iter()
+        .map(|&tick| (piece.tick_to_sample(tick as f64), None))
+        .chain(
+            piece
+                .markers()
+                .into_iter()
+                .map(|(name, sample)| (sample, Some(name))),
+        )
+        .collect::<Vec<_>>();
+
+    // wrap the waveform data with the header and any extra chunks as it's
+    // written out
+    let path = args.output.unwrap_or_else(|| default_output(&file));
+    let mut output = open_output(&path)?;
+    write_wav(
+        &mut output,
+        &data,
+        &format,
+        args.fact,
+        &cues,
+        piece.metadata(),
+        &piece.loops(),
+    )
+    .and_then(|_| output.flush())
+    .map_err(|_| MusicError::FileWriteError(path))
+}
+
+// the score's name with its extension (and any `.gz` after that) replaced by
+// `.wav`, so that `song.txt` is rendered to `song.wav`
+fn default_output(file: &str) -> String {
+    let file = file.strip_suffix(".gz").unwrap_or(file);
+    Path::new(file)
+        .with_extension("wav")
+        .to_string_lossy()
+        .into_owned()
+}
+
+// open a file to write out to, first creating any directories on the way to
+// it that don't exist yet; `-` stands for stdout
+fn open_output(file: &str) -> Result<BufWriter<Box<dyn Write>>, MusicError> {
+    if file == "-" {
+        return Ok(BufWriter::new(Box::new(std::io::stdout().lock())));
+    }
+    let failed = |_| MusicError::FileWriteError(file.to_string());
+    if let Some(dir) = Path::new(file).parent() {
+        std::fs::create_dir_all(dir).map_err(failed)?;
+    }
+    let output = File::create(file).map_err(failed)?;
+    Ok(BufWriter::new(Box::new(output)))
+}
+
+// write out a file whole
+fn emit(file: &str, bytes: &[u8]) -> Result<(), MusicError> {
+    let mut output = open_output(file)?;
+    output
+        .write_all(bytes)
+        .and_then(|_| output.flush())
+        .map_err(|_| MusicError::FileWriteError(file.to_string()))
+}
+
+fn parse_channels(arg: &str) -> Result<u16, String> {
+    match arg.parse() {
+        Ok(channels) if layout(channels).is_some() => Ok(channels),
+        _ => Err("must be 1, 2, 4, 6 or 8".to_string()),
+    }
+}
+
+fn parse_bit_depth(arg: &str) -> Result<u16, String> {
+    match arg.parse() {
+        Ok(bits) if BIT_DEPTHS.contains(&bits) => Ok(bits),
+        _ => Err(format!("must be one of {:?}", BIT_DEPTHS)),
+    }
+}
+
+fn parse_midi_wave(arg: &str) -> Result<(String, String), String> {
+    match arg.split_once('=') {
+        Some((track, wave)) if !track.is_empty() && !wave.is_empty() => {
+            Ok((track.to_string(), wave.to_string()))
+        }
+        _ => Err("must be a track number or name, then = and a waveform".to_string()),
+    }
+}
+
+fn parse_compressor(arg: &str) -> Result<Effect, String> {
+    Effect::compressor(&arg.split(',').collect::<Vec<_>>()).map_err(|e| e.to_string())
+}
+
+fn parse_fade(arg: &str) -> Result<FadeLength, String> {
+    arg.parse()
+        .map_err(|_| "must be a number of seconds or a note duration".to_string())
+}
+
+fn parse_tonic_arg(arg: &str) -> Result<i32, String> {
+    parse_tonic(arg).ok_or_else(|| "must be a letter name, such as D or Eb".to_string())
+}
 
-    // prepare output buffer with header
-    let mut output = header(data.len() as u32);
+fn parse_ampl(arg: &str) -> Result<u16, String> {
+    parse_base_amplitude(arg).ok_or_else(|| {
+        "must be a number up to 65535, or a level in dB no higher than 0".to_string()
+    })
+}
 
-    // load output waveform data into buffer
-    output.extend_from_slice(&data);
+fn parse_reference(arg: &str) -> Result<f64, String> {
+    match arg.parse::<f64>() {
+        Ok(hz) if hz.is_finite() && hz > 0.0 => Ok(hz),
+        _ => Err("must be a frequency in Hz above 0".to_string()),
+    }
+}
 
-    // write buffer into file
-    if std::fs::write(&format!("{}.wav", args.file), &output).is_err() {
-        return Err(MusicError::FileWriteError(format!("{}.wav", args.file)));
+fn parse_peak(arg: &str) -> Result<f64, String> {
+    match arg.parse::<f64>() {
+        Ok(db) if db <= 0.0 => Ok(db),
+        _ => Err("must be a level in dBFS, no higher than 0".to_string()),
     }
+}
 
-    Ok(())
+fn parse_loudness(arg: &str) -> Result<f64, String> {
+    match arg.parse::<f64>() {
+        Ok(lufs) if (-70.0..=0.0).contains(&lufs) => Ok(lufs),
+        _ => Err("must be a loudness in LUFS, from -70 to 0".to_string()),
+    }
 }
@@ -0,0 +1,112 @@
+//! This module defines the passes that can be run over a finished mix, after
+//! its effects and just before it is quantized, to set its level and keep it
+//! within full scale.
+
+use crate::filter::{Biquad, Filter, FilterKind};
+
+use std::f64::consts::{FRAC_1_SQRT_2, PI};
+
+// the cutoff (in Hz) of the DC blocker, well below anything audible
+const DC_CUTOFF: f64 = 5.0;
+
+// the level (as a fraction of full scale) above which the limiter starts to
+// squash peaks
+const LIMITER_THRESHOLD: f64 = 0.8;
+
+// a DC blocker: a one-pole high-pass per channel that takes out any constant
+// offset (left by asymmetric waveforms) while passing everything audible
+pub fn remove_dc(samples: &mut [f64], channels: usize, rate: u32) {
+    let pole = f64::exp(-2.0 * PI * DC_CUTOFF / rate as f64);
+    for channel in 0..channels {
+        let (mut last_in, mut last_out) = (0.0, 0.0);
+        for sample in samples.iter_mut().skip(channel).step_by(channels) {
+            last_out = *sample - last_in + pole * last_out;
+            last_in = *sample;
+            *sample = last_out;
+        }
+    }
+}
+
+// a soft limiter: samples below the threshold pass untouched, and anything
+// above it is bent smoothly over towards full scale, never quite reaching it,
+// rather than being clipped flat
+pub fn limit(samples: &mut [f64]) {
+    let headroom = 1.0 - LIMITER_THRESHOLD;
+    for sample in samples.iter_mut() {
+        let excess = sample.abs() - LIMITER_THRESHOLD;
+        if excess > 0.0 {
+            let limited = LIMITER_THRESHOLD + headroom * f64::tanh(excess / headroom);
+            *sample = limited.copysign(*sample);
+        }
+    }
+}
+
+// scale the mix so that its loudest sample sits at the given level (in dBFS,
+// so 0 or below); a silent mix is left alone
+pub fn normalize(samples: &mut [f64], target: f64) {
+    let peak = samples.iter().fold(0.0f64, |peak, s| peak.max(s.abs()));
+    if peak > 0.0 {
+        let gain = db_to_gain(target) / peak;
+        samples.iter_mut().for_each(|s| *s *= gain);
+    }
+}
+
+pub fn db_to_gain(db: f64) -> f64 {
+    10f64.powf(db / 20.0)
+}
+
+// the integrated loudness of a mix in LUFS, as EBU R128 (ITU-R BS.1770)
+// measures it: each channel is K-weighted (a high shelf to mimic the head,
+// then a high-pass), and the mean power is taken over overlapping 400ms
+// blocks, ignoring blocks quieter than -70 LUFS and then those more than 10 LU
+// below the average of the rest; `None` if every block is gated away
+pub fn loudness(samples: &[f64], channels: usize, rate: u32) -> Option<f64> {
+    let weightings = [
+        Filter {
+            kind: FilterKind::HighShelf(4.0),
+            cutoff: 1500.0,
+            resonance: FRAC_1_SQRT_2,
+        },
+        Filter {
+            kind: FilterKind::HighPass,
+            cutoff: 38.0,
+            resonance: 0.5,
+        },
+    ];
+    let frames = samples.len() / channels;
+    let mut power = vec![0.0; frames];
+    for channel in 0..channels {
+        let mut biquads = weightings.map(|filter| {
+            let mut biquad = Biquad::default();
+            biquad.tune(&filter, rate);
+            biquad
+        });
+        for (frame, sample) in samples.iter().skip(channel).step_by(channels).enumerate() {
+            let weighted = biquads
+                .iter_mut()
+                .fold(*sample, |x, biquad| biquad.process(x));
+            power[frame] += weighted * weighted;
+        }
+    }
+
+    // 400ms blocks, each starting 100ms after the last
+    let (block, step) = (rate as usize * 2 / 5, rate as usize / 10);
+    let blocks = (0..frames.saturating_sub(block) / step.max(1) + 1)
+        .map(|n| {
+            power[n * step..(n * step + block).min(frames)]
+                .iter()
+                .sum::<f64>()
+                / block as f64
+        })
+        .collect::<Vec<_>>();
+    let lufs = |power: f64| -0.691 + 10.0 * power.log10();
+    let gated = |threshold: f64| {
+        let kept = blocks
+            .iter()
+            .filter(|&&p| lufs(p) > threshold)
+            .collect::<Vec<_>>();
+        (!kept.is_empty()).then(|| kept.iter().copied().sum::<f64>() / kept.len() as f64)
+    };
+    let relative = lufs(gated(-70.0)?) - 10.0;
+    gated(relative.max(-70.0)).map(lufs)
+}
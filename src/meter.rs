@@ -0,0 +1,169 @@
+//! This module defines time signatures, and the meter of a piece: the time
+//! signature that each measure is in, which is what lets a start time be
+//! written as `measure:beat:tick` (e.g. `4:2:0`) rather than as a plain tick
+//! count. Measures and beats are counted from 1, as on a score, and ticks from
+//! 0, so `1:1:0` is the very start of the piece. The meter also holds the
+//! resolution that the score writes ticks in, which is 16 to a quarter note
+//! (so that a tick is a 64th note, just as inside the program) unless a `PPQ`
+//! directive says otherwise.
+
+use crate::error::SyntaxErrorType;
+
+use std::str::FromStr;
+
+// a time signature such as 3/4: `beats` beats to the measure, each of them
+// lasting a 1/`unit` note
+#[derive(Clone, Copy)]
+pub struct TimeSignature {
+    pub beats: u32,
+    pub unit: u32,
+}
+
+// every change of time signature in a piece so far, in order, as the measure
+// it takes effect from, the tick that measure starts on, and the signature;
+// there is always at least one, for measure 1, which is 4/4 unless a `TIME`
+// directive says otherwise; `ppq` is how many of the ticks written in the
+// score make a quarter note
+#[derive(Clone)]
+pub struct Meter {
+    changes: Vec<(u32, f64, TimeSignature)>,
+    pub ppq: u32,
+}
+
+// how many ticks make a quarter note inside the program, whatever the score
+// writes them as
+pub const PPQ: u32 = 16;
+
+impl Default for TimeSignature {
+    fn default() -> Self {
+        Self { beats: 4, unit: 4 }
+    }
+}
+
+impl FromStr for TimeSignature {
+    type Err = SyntaxErrorType;
+
+    // the beat unit has to be a whole number of ticks, so it can be any power
+    // of two note from a whole note down to a 64th
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let bad = || SyntaxErrorType::BadTime(input.to_string());
+        let (beats, unit) = input.split_once('/').ok_or_else(bad)?;
+        let beats = beats.parse::<u32>().map_err(|_| bad())?;
+        let unit = unit.parse::<u32>().map_err(|_| bad())?;
+        if beats == 0 || !unit.is_power_of_two() || unit > 64 {
+            return Err(bad());
+        }
+        Ok(Self { beats, unit })
+    }
+}
+
+impl TimeSignature {
+    pub fn beat_ticks(&self) -> f64 {
+        64.0 / self.unit as f64
+    }
+
+    pub fn measure_ticks(&self) -> f64 {
+        self.beats as f64 * self.beat_ticks()
+    }
+}
+
+// the number of the program's ticks that make up `written` ticks at the given
+// resolution; the default resolution leaves them untouched
+pub fn scale_ticks(written: f64, ppq: u32) -> f64 {
+    if ppq == PPQ {
+        return written;
+    }
+    written * PPQ as f64 / ppq as f64
+}
+
+// a number of ticks as the score writes it, which may be fractional, either
+// as a decimal (e.g. `12.5`) or as a fraction (e.g. `25/2`, which keeps a
+// tick such as `32/3` exact rather than rounded); None if it's negative
+pub fn parse_ticks(input: &str) -> Option<f64> {
+    let ticks = match input.split_once('/') {
+        Some((num, den)) => match den.parse::<f64>().ok()? {
+            den if den > 0.0 => num.parse::<f64>().ok()? / den,
+            _ => return None,
+        },
+        None => input.parse::<f64>().ok()?,
+    };
+    (ticks.is_finite() && ticks >= 0.0).then_some(ticks)
+}
+
+impl Default for Meter {
+    fn default() -> Self {
+        Self {
+            changes: vec![(1, 0.0, TimeSignature::default())],
+            ppq: PPQ,
+        }
+    }
+}
+
+impl Meter {
+    // apply a `TIME <beats>/<unit> [@ <measure>]` directive: the signature
+    // holds from the given measure (or from the start) until the next change,
+    // and changes have to be made in order through the piece
+    pub fn set(&mut self, args: &[&str]) -> Result<(), SyntaxErrorType> {
+        let bad = || SyntaxErrorType::BadTime(args.join(" "));
+        let (signature, measure) = match args[..] {
+            [signature] => (signature, 1),
+            [signature, "@", measure] => match measure.parse::<u32>() {
+                Ok(measure) if measure > 0 => (signature, measure),
+                _ => return Err(bad()),
+            },
+            _ => return Err(bad()),
+        };
+        let signature = signature.parse()?;
+        let &(last, tick, previous) = self.changes.last().ok_or_else(bad)?;
+        if measure < last {
+            return Err(bad());
+        }
+        if measure == last {
+            self.changes.pop();
+        }
+        let tick = tick + (measure - last) as f64 * previous.measure_ticks();
+        self.changes.push((measure, tick, signature));
+        Ok(())
+    }
+
+    // apply a `PPQ <ticks>` directive, setting the resolution of later ticks
+    pub fn set_ppq(&mut self, args: &[&str]) -> Result<(), SyntaxErrorType> {
+        match args[..] {
+            [ppq] => match ppq.parse() {
+                Ok(ppq) if ppq > 0 => {
+                    self.ppq = ppq;
+                    Ok(())
+                }
+                _ => Err(SyntaxErrorType::BadPpq(ppq.to_string())),
+            },
+            _ => Err(SyntaxErrorType::BadPpq(args.join(" "))),
+        }
+    }
+
+    // the number of the program's ticks that the given number of the score's
+    // ticks makes
+    pub fn ticks(&self, written: f64) -> f64 {
+        scale_ticks(written, self.ppq)
+    }
+
+    // the tick on which the given beat of the given measure starts, plus
+    // `tick` more (as written in the score); the beat has to be one that the
+    // measure has, and the tick has to fall within it
+    pub fn tick(&self, measure: u32, beat: u32, tick: f64) -> Option<f64> {
+        let tick = self.ticks(tick);
+        let &(first, start, signature) = self
+            .changes
+            .iter()
+            .rev()
+            .find(|(first, _, _)| *first <= measure)?;
+        if beat == 0 || beat > signature.beats || !(0.0..signature.beat_ticks()).contains(&tick) {
+            return None;
+        }
+        Some(
+            start
+                + (measure - first) as f64 * signature.measure_ticks()
+                + (beat - 1) as f64 * signature.beat_ticks()
+                + tick,
+        )
+    }
+}
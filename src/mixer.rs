@@ -0,0 +1,100 @@
+//! This module defines tracks, the groups into which a piece's notes can be
+//! split with `TRACK` lines. Each track is rendered into a buffer of its own
+//! and run through its own chain of effects, and then the buffers are summed
+//! into the mix that the piece's effects run over. A track can also have its
+//! volume automated, swelling or fading between two points in time with `CRESC`
+//! and `DIM` lines, and its notes can leave out columns that it fills in.
+
+use crate::effects::Effect;
+use crate::signal::Defaults;
+
+// the notes before any `TRACK` line belong to the first track, which has no
+// name; a track's effects run in order, before it is mixed with the others,
+// and its defaults fill in whatever its notes leave out
+#[derive(Clone, Default)]
+pub struct Track {
+    pub name: String,
+    pub effects: Vec<Effect>,
+    pub gain: Automation,
+    pub defaults: Defaults,
+}
+
+// a lane of automation, giving the gain at every tick: 1 until the first ramp,
+// and then moving in a straight line over each ramp and holding where it ends;
+// each ramp is kept as the ticks it runs between, the gain it starts from (or
+// `None` to carry on from wherever the lane has got to) and the gain it ends
+// on, in order of the tick it starts on
+#[derive(Clone, Default)]
+pub struct Automation {
+    ramps: Vec<(f64, f64, Option<f64>, f64)>,
+}
+
+impl Automation {
+    pub fn ramp(&mut self, from: f64, to: f64, start: Option<f64>, end: f64) {
+        let i = self.ramps.partition_point(|&(f, _, _, _)| f <= from);
+        self.ramps.insert(i, (from, to, start, end));
+    }
+
+    pub fn is_flat(&self) -> bool {
+        self.ramps.is_empty()
+    }
+
+    // the gain at the given tick; where ramps overlap, the later one takes
+    // over from wherever the earlier one has got to when it starts
+    pub fn gain(&self, tick: f64) -> f64 {
+        let mut gain = 1.0;
+        for (i, &(from, to, start, end)) in self.ramps.iter().enumerate() {
+            if tick < from {
+                break;
+            }
+            let start = start.unwrap_or(gain);
+            let until = match self.ramps.get(i + 1) {
+                Some(&(next, _, _, _)) if next <= tick => next,
+                _ => tick,
+            };
+            gain = match until < to {
+                true => start + (end - start) * (until - from) / (to - from),
+                false => end,
+            };
+        }
+        gain
+    }
+
+    // the greatest gain anywhere between the two ticks, which (with the lane
+    // being straight between the ends of its ramps) is at one of those ends
+    // or at one of the two ticks
+    pub fn peak(&self, from: f64, to: f64) -> f64 {
+        self.ramps
+            .iter()
+            .flat_map(|&(f, t, _, _)| [f, t])
+            .filter(|&tick| from < tick && tick < to)
+            .chain([from, to])
+            .map(|tick| self.gain(tick))
+            .fold(f64::NEG_INFINITY, f64::max)
+    }
+}
+
+// add a track's samples into the mix, which grows to fit them if the track
+// runs on for longer (with the tail of a reverb, say)
+pub fn mix_into(mix: &mut Vec<f64>, samples: &[f64]) {
+    if mix.len() < samples.len() {
+        mix.resize(samples.len(), 0.0);
+    }
+    for (total, sample) in mix.iter_mut().zip(samples) {
+        *total += sample;
+    }
+}
+
+// the index of the track with the given name, adding it if there isn't one
+pub fn find_track(tracks: &mut Vec<Track>, name: &str) -> usize {
+    match tracks.iter().position(|track| track.name == name) {
+        Some(index) => index,
+        None => {
+            tracks.push(Track {
+                name: name.to_string(),
+                ..Track::default()
+            });
+            tracks.len() - 1
+        }
+    }
+}
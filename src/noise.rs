@@ -0,0 +1,58 @@
+//! This module defines the noise generators behind the noise waveforms. Each
+//! noise signal gets its own generator, seeded from its place in the piece, so
+//! that rendering the same piece twice gives the same output.
+
+// a xorshift random number generator, plus the filter state that turns its
+// white noise into pink noise (Paul Kellet's filter) or brown noise (a leaky
+// integrator)
+#[derive(Clone, Copy)]
+pub struct Noise {
+    state: u64,
+    pink: [f64; 7],
+    brown: f64,
+}
+
+impl Noise {
+    pub fn new(seed: u64) -> Self {
+        // scramble the seed (splitmix64), since xorshift needs a nonzero state
+        // and behaves poorly when started from small numbers
+        let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        Self {
+            state: (z ^ (z >> 31)).max(1),
+            pink: [0.0; 7],
+            brown: 0.0,
+        }
+    }
+
+    // uniform noise between -1.0 and 1.0
+    pub fn white(&mut self) -> f64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        (self.state >> 11) as f64 / (1u64 << 52) as f64 - 1.0
+    }
+
+    // noise falling off by 3 dB per octave
+    pub fn pink(&mut self) -> f64 {
+        let white = self.white();
+        let b = &mut self.pink;
+        b[0] = 0.99886 * b[0] + white * 0.0555179;
+        b[1] = 0.99332 * b[1] + white * 0.0750759;
+        b[2] = 0.96900 * b[2] + white * 0.1538520;
+        b[3] = 0.86650 * b[3] + white * 0.3104856;
+        b[4] = 0.55000 * b[4] + white * 0.5329522;
+        b[5] = -0.7616 * b[5] - white * 0.0168980;
+        let sum = b.iter().sum::<f64>() + white * 0.5362;
+        b[6] = white * 0.115926;
+        // scale back to roughly the same level as the white noise
+        sum * 0.11
+    }
+
+    // noise falling off by 6 dB per octave
+    pub fn brown(&mut self) -> f64 {
+        self.brown = (self.brown + 0.02 * self.white()) / 1.02;
+        self.brown * 3.5
+    }
+}
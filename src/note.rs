@@ -1,8 +1,11 @@
 //! In this module we define a single note to be a pitch class together with
 //! an octave, and provide a method (`equal_tempered()`) to extract from a note
-//! its frequency in Hz.
+//! its frequency in Hz. It also defines key signatures, which decide what a
+//! bare letter name without an accidental means, and the notations that note
+//! names can be written in besides English letter names.
 
 use crate::error::SyntaxErrorType;
+use crate::tuning::{Tuning, DEFAULT_A4};
 
 #[derive(Clone, Copy)]
 pub enum PitchClass {
@@ -20,51 +23,355 @@ pub enum PitchClass {
     AFlat,
 }
 
+// every natural letter name, in the order C, D, E, F, G, A, B, with its
+// semitones above C
+const LETTERS: [(char, i32); 7] = [
+    ('C', 0),
+    ('D', 2),
+    ('E', 4),
+    ('F', 5),
+    ('G', 7),
+    ('A', 9),
+    ('B', 11),
+];
+
+// every accidental that can follow a letter name, with the semitones that it
+// moves the letter by and its name; a letter without one takes its accidental
+// from the key
+pub const ACCIDENTALS: [(&str, i32, &str); 6] = [
+    ("n", 0, "natural"),
+    ("#", 1, "sharp"),
+    ("b", -1, "flat"),
+    ("x", 2, "double sharp"),
+    ("##", 2, "double sharp"),
+    ("bb", -2, "double flat"),
+];
+
+// the systems of note names that a score can be written in: English letter
+// names, German ones, in which H is B and B is Bb and an accidental is a
+// suffix (as in `Fis` and `Es`), and fixed-do solfege, whose syllables take
+// the same accidentals as English letters (as in `Sib`)
+#[derive(Clone, Copy, Default)]
+pub enum Notation {
+    #[default]
+    English,
+    German,
+    Solfege,
+}
+
+// every notation, by the name it's written with
+pub const NOTATIONS: [(&str, Notation); 3] = [
+    ("english", Notation::English),
+    ("german", Notation::German),
+    ("solfege", Notation::Solfege),
+];
+
+// every solfege syllable, with the letter name that it stands for; a longer
+// syllable comes before any shorter one that it starts with
+const SYLLABLES: [(&str, &str); 9] = [
+    ("Do", "C"),
+    ("Re", "D"),
+    ("Mi", "E"),
+    ("Fa", "F"),
+    ("Sol", "G"),
+    ("So", "G"),
+    ("La", "A"),
+    ("Si", "B"),
+    ("Ti", "B"),
+];
+
+// the German accidental suffixes, with the English accidentals that they stand
+// for; E and A drop the first `e` of the flats (`Es`, `Ases`)
+const GERMAN_SUFFIXES: [(&str, &str); 4] =
+    [("isis", "x"), ("is", "#"), ("eses", "bb"), ("es", "b")];
+const GERMAN_VOWEL_SUFFIXES: [(&str, &str); 4] =
+    [("isis", "x"), ("is", "#"), ("ses", "bb"), ("s", "b")];
+
 // a note is represented as just a pitch class (like A, or F#), plus an octave
 // number; for example, A4 is 440 Hz, A3 is 220 Hz, C4 is "middle C" and is the
-// note right after B3
+// note right after B3; `cents` detunes it from equal temperament (e.g. the
+// `+14c` of `C4+14c`), for microtonal material
 #[derive(Clone, Copy)]
 pub struct Note {
     pub pitch_class: PitchClass,
-    pub octave: u32,
+    pub octave: i32,
+    pub cents: f64,
+}
+
+// what a signal sounds at: a note, or a frequency in Hz written out for itself
+// (e.g. `432.5Hz`) for material outside equal temperament
+#[derive(Clone, Copy)]
+pub enum Pitch {
+    Note(Note),
+    Frequency(f64),
+}
+
+// the lowest octave that a note can be in, which is where MIDI's notes start
+// (C-1 is about 8.2 Hz)
+pub const LOWEST_OCTAVE: i32 = -1;
+
+// the lowest frequency that can be written in Hz, or reached by transposing
+// one; anything lower is far below hearing, and a plucked string at such a
+// pitch would need a delay line longer than memory holds
+pub const LOWEST_FREQUENCY: f64 = 1.0;
+
+// a key signature is stored as the accidental (in semitones) that it applies to
+// each natural letter name, in the order C, D, E, F, G, A, B
+#[derive(Clone, Copy, Default)]
+pub struct KeySignature {
+    accidentals: [i32; 7],
+}
+
+impl PitchClass {
+    // the pitch class a given number of semitones (in 0..12) above C
+    fn from_semitone(n: i32) -> Self {
+        match n {
+            0 => Self::C,
+            1 => Self::DFlat,
+            2 => Self::D,
+            3 => Self::EFlat,
+            4 => Self::E,
+            5 => Self::F,
+            6 => Self::GFlat,
+            7 => Self::G,
+            8 => Self::AFlat,
+            9 => Self::A,
+            10 => Self::BFlat,
+            _ => Self::B,
+        }
+    }
+}
+
+impl KeySignature {
+    pub fn new(tonic: &str, mode: &str) -> Result<Self, SyntaxErrorType> {
+        // place the tonic on the circle of fifths, counting sharps as positive
+        // and flats as negative, so that e.g. D major is 2 and Bb major is -2;
+        // a minor key has the same signature as the major key a minor third up
+        let bad_key = || SyntaxErrorType::BadKey(format!("{} {}", tonic, mode));
+        let mut chars = tonic.chars();
+        let mut fifths = match chars.next() {
+            Some('F') => -1,
+            Some('C') => 0,
+            Some('G') => 1,
+            Some('D') => 2,
+            Some('A') => 3,
+            Some('E') => 4,
+            Some('B') => 5,
+            _ => return Err(bad_key()),
+        };
+        fifths += match chars.as_str() {
+            "" => 0,
+            "#" => 7,
+            "b" => -7,
+            _ => return Err(bad_key()),
+        };
+        fifths -= match mode {
+            "major" => 0,
+            "minor" => 3,
+            _ => return Err(bad_key()),
+        };
+        if !(-7..=7).contains(&fifths) {
+            return Err(bad_key());
+        }
+
+        // sharps are added in the order F C G D A E B, flats in the reverse
+        let mut accidentals = [0; 7];
+        let order = [3, 0, 4, 1, 5, 2, 6];
+        if fifths > 0 {
+            for &letter in order.iter().take(fifths as usize) {
+                accidentals[letter] = 1;
+            }
+        } else {
+            for &letter in order.iter().rev().take(-fifths as usize) {
+                accidentals[letter] = -1;
+            }
+        }
+        Ok(Self { accidentals })
+    }
+}
+
+impl KeySignature {
+    // whether the given pitch class (in semitones above C) is in the key
+    fn contains(&self, semitone: i32) -> bool {
+        LETTERS
+            .iter()
+            .zip(self.accidentals)
+            .any(|(&(_, natural), shift)| (natural + shift).rem_euclid(12) == semitone)
+    }
+
+    // the pitch a step of the key above (or below) the given one, which is
+    // the nearest note in the key that way, or a whole tone for a frequency;
+    // None if it would fall below the lowest octave
+    pub fn step(&self, pitch: &Pitch, up: bool) -> Option<Pitch> {
+        let direction = if up { 1 } else { -1 };
+        let semitones = match pitch {
+            Pitch::Note(note) => (1..=3)
+                .map(|k| k * direction)
+                .find(|k| self.contains((note.semitones() + k).rem_euclid(12)))?,
+            Pitch::Frequency(_) => 2 * direction,
+        };
+        pitch.transpose(semitones)
+    }
+}
+
+impl Notation {
+    // the same note with its name (e.g. the `Fis` of `Fis4`) spelled as an
+    // English letter name, leaving the octave and any cents that follow it
+    // alone; None if it doesn't start with a name in this notation
+    pub fn to_english(&self, note: &str) -> Option<String> {
+        match self {
+            Self::English => Some(note.to_string()),
+            Self::Solfege => SYLLABLES.iter().find_map(|&(syllable, letter)| {
+                let rest = note.strip_prefix(syllable)?;
+                Some(format!("{}{}", letter, rest))
+            }),
+            Self::German => {
+                let mut chars = note.chars();
+                let (letter, suffixes) = match chars.next()? {
+                    // B is always B flat, so it can't take an accidental
+                    'B' => {
+                        let rest = chars.as_str();
+                        let bare = !rest.starts_with(|ch: char| ch.is_alphabetic() || ch == '#');
+                        return bare.then(|| format!("Bb{}", rest));
+                    }
+                    'H' => ('B', &GERMAN_SUFFIXES),
+                    letter @ ('E' | 'A') => (letter, &GERMAN_VOWEL_SUFFIXES),
+                    letter => (letter, &GERMAN_SUFFIXES),
+                };
+                let rest = chars.as_str();
+                let (accidental, rest) = suffixes
+                    .iter()
+                    .find_map(|&(suffix, accidental)| {
+                        rest.strip_prefix(suffix).map(|rest| (accidental, rest))
+                    })
+                    .unwrap_or(("", rest));
+                Some(format!("{}{}{}", letter, accidental, rest))
+            }
+        }
+    }
 }
 
 impl Note {
     pub fn new(note: &str) -> Result<Self, SyntaxErrorType> {
-        // at least want to handle black key enharmonics, but let's not go crazy
-        // with stuff like B# or Gbb for now
-        let pitch_class = match &note[..(note.len() - 1)] {
-            "A" => PitchClass::A,
-            "A#" | "Bb" => PitchClass::BFlat,
-            "B" => PitchClass::B,
-            "C" => PitchClass::C,
-            "C#" | "Db" => PitchClass::DFlat,
-            "D" => PitchClass::D,
-            "D#" | "Eb" => PitchClass::EFlat,
-            "E" => PitchClass::E,
-            "F" => PitchClass::F,
-            "F#" | "Gb" => PitchClass::GFlat,
-            "G" => PitchClass::G,
-            "G#" | "Ab" => PitchClass::AFlat,
-            e => return Err(SyntaxErrorType::BadPitchClass(e.to_string())),
+        Self::in_key(note, &KeySignature::default())
+    }
+
+    // parse a note, letting the key signature supply the accidental for a bare
+    // letter name; a trailing `n` on the letter (e.g. `Fn4`) forces a natural,
+    // and an offset in cents can follow the octave
+    pub fn in_key(note: &str, key: &KeySignature) -> Result<Self, SyntaxErrorType> {
+        let (note, cents) = split_cents(note)?;
+        // the octave is the whole trailing run of digits, with the minus sign
+        // before it if it's negative (e.g. `A-1`), and everything before that
+        // is taken to name the pitch class
+        let digits = note.trim_end_matches(|ch: char| ch.is_ascii_digit());
+        let name = match digits.len() < note.len() {
+            true => digits.strip_suffix('-').unwrap_or(digits),
+            false => digits,
         };
-        // as currently coded, the octave can only go up to 9; all but the last
-        // char of the string we're parsing is assumed to be part of the note
-        let octave = match note.chars().last() {
-            Some(ch) => match ch.to_digit(10) {
-                Some(n) => n,
-                None => return Err(SyntaxErrorType::BadOctave(ch.to_string())),
-            },
-            None => return Err(SyntaxErrorType::MissingEntry),
+        let octave = match note[name.len()..].parse::<i8>() {
+            Ok(n) => n as i32,
+            Err(_) => return Err(SyntaxErrorType::BadOctave(note.to_string())),
         };
+        let note = Self::from_name(name, octave, key)?;
+        Ok(Self { cents, ..note })
+    }
 
+    // a letter name with any accidental, in the given octave; the accidental
+    // may carry the note over into the neighbouring octave (e.g. B#3 sounds as
+    // C4, and Cb4 as B3)
+    pub fn from_name(name: &str, octave: i32, key: &KeySignature) -> Result<Self, SyntaxErrorType> {
+        let bad = || SyntaxErrorType::BadPitchClass(name.to_string());
+        let mut chars = name.chars();
+        let index = chars
+            .next()
+            .and_then(|letter| LETTERS.iter().position(|&(l, _)| l == letter))
+            .ok_or_else(bad)?;
+        let shift = match chars.as_str() {
+            "" => key.accidentals[index],
+            accidental => match ACCIDENTALS.iter().find(|&&(a, _, _)| a == accidental) {
+                Some(&(_, shift, _)) => shift,
+                None => return Err(bad()),
+            },
+        };
+        let semitone = LETTERS[index].1 + shift;
+        let octave = octave + semitone.div_euclid(12);
+        if octave < LOWEST_OCTAVE {
+            return Err(SyntaxErrorType::BadOctave(octave.to_string()));
+        }
         Ok(Self {
-            pitch_class,
+            pitch_class: PitchClass::from_semitone(semitone.rem_euclid(12)),
             octave,
+            cents: 0.0,
         })
     }
 
+    // the note a given number of semitones above (or, if it's negative, below)
+    // C0, if that isn't below the lowest octave
+    pub fn from_semitones(semitones: i32) -> Option<Self> {
+        if semitones < 12 * LOWEST_OCTAVE {
+            return None;
+        }
+        Some(Self {
+            pitch_class: PitchClass::from_semitone(semitones.rem_euclid(12)),
+            octave: semitones.div_euclid(12),
+            cents: 0.0,
+        })
+    }
+
+    // the note the given number of semitones above (or below) this one, as
+    // long as that isn't below the lowest octave
+    pub fn transpose(&self, semitones: i32) -> Option<Self> {
+        let note = Self::from_semitones(self.semitones() + semitones)?;
+        Some(Self {
+            cents: self.cents,
+            ..note
+        })
+    }
+
+    // the note with the given MIDI note number, where 60 is C4 and 0 is C-1,
+    // the lowest note there is
+    pub fn from_midi(number: u8) -> Self {
+        Self {
+            pitch_class: PitchClass::from_semitone(number as i32 % 12),
+            octave: number as i32 / 12 + LOWEST_OCTAVE,
+            cents: 0.0,
+        }
+    }
+
+    // the note's MIDI note number, if it has one (MIDI stops at G9)
+    pub fn to_midi(&self) -> Option<u8> {
+        u8::try_from(self.semitones() - 12 * LOWEST_OCTAVE)
+            .ok()
+            .filter(|&number| number <= 127)
+    }
+
+    // how many semitones above C0 the note is (negative for a note below it)
+    pub fn semitones(&self) -> i32 {
+        let semitone = match self.pitch_class {
+            PitchClass::C => 0,
+            PitchClass::DFlat => 1,
+            PitchClass::D => 2,
+            PitchClass::EFlat => 3,
+            PitchClass::E => 4,
+            PitchClass::F => 5,
+            PitchClass::GFlat => 6,
+            PitchClass::G => 7,
+            PitchClass::AFlat => 8,
+            PitchClass::A => 9,
+            PitchClass::BFlat => 10,
+            PitchClass::B => 11,
+        };
+        12 * self.octave + semitone
+    }
+
     pub fn equal_tempered(&self) -> f64 {
+        self.equal_tempered_at(DEFAULT_A4)
+    }
+
+    // the frequency of the note in equal temperament, with A4 tuned to the
+    // given reference pitch in place of the usual 440 Hz
+    pub fn equal_tempered_at(&self, a4: f64) -> f64 {
         // Since we're using 12-tone equal temperament, we just have to pick a
         // base frequency; then, the octave number tells us how many times we
         // should double or halve it, and the pitch class tells us how many
@@ -75,10 +382,12 @@ impl Note {
         // more convenient base frequency as it is the only one set at a
         // rational number in, for example, A440 (A0 is 27.5 Hz, so we start at
         // 13.75 Hz, and the lowest representable frequency in the program is
-        // C0).
-        13.75
+        // C0, below which the octave numbers go negative). Any offset in cents
+        // then moves it off the equal-tempered pitch.
+        a4 / 32.0
+            * 2.0f64.powf(self.cents / 1200.0)
             * match self.pitch_class {
-                PitchClass::A => 2.0f64.powi(self.octave as i32 + 1),
+                PitchClass::A => 2.0f64.powi(self.octave + 1),
                 PitchClass::BFlat => 2.0f64.powf((self.octave + 1) as f64 + 1.0 / 12.0),
                 PitchClass::B => 2.0f64.powf((self.octave + 1) as f64 + 1.0 / 6.0),
                 PitchClass::C => 2.0f64.powf(self.octave as f64 + 0.25),
@@ -93,3 +402,135 @@ impl Note {
             }
     }
 }
+
+// split an offset in cents (e.g. the `+14c` of `C4+14c`) off the end of a
+// note, if it has one
+fn split_cents(note: &str) -> Result<(&str, f64), SyntaxErrorType> {
+    let Some(body) = note.strip_suffix('c') else {
+        return Ok((note, 0.0));
+    };
+    let bad = || SyntaxErrorType::BadCents(note.to_string());
+    let sign = body.rfind(['+', '-']).ok_or_else(bad)?;
+    match body[sign..].parse::<f64>() {
+        Ok(cents) if cents.is_finite() => Ok((&body[..sign], cents)),
+        _ => Err(bad()),
+    }
+}
+
+impl From<Note> for Pitch {
+    fn from(note: Note) -> Self {
+        Self::Note(note)
+    }
+}
+
+impl Pitch {
+    // a frequency is a number of at least 1 followed by `Hz`, and `m`
+    // followed by a MIDI note number (e.g. `m60`) is that note, whatever the
+    // key; anything else is parsed as a note named in the given notation, in
+    // which a bare letter takes its accidental from the key and, if there is
+    // one, its octave from `octave`
+    pub fn new(
+        input: &str,
+        octave: Option<i32>,
+        key: &KeySignature,
+        notation: Notation,
+    ) -> Result<Self, SyntaxErrorType> {
+        if let Some(freq) = input.strip_suffix("Hz") {
+            return match freq.parse::<f64>() {
+                Ok(freq) if freq.is_finite() && freq >= LOWEST_FREQUENCY => {
+                    Ok(Self::Frequency(freq))
+                }
+                _ => Err(SyntaxErrorType::BadFrequency(input.to_string())),
+            };
+        }
+        if let Some(number) = input.strip_prefix('m') {
+            return match number.parse::<u8>() {
+                Ok(number) if number <= 127 => Ok(Self::Note(Note::from_midi(number))),
+                _ => Err(SyntaxErrorType::BadPitchClass(input.to_string())),
+            };
+        }
+        let english = notation
+            .to_english(input)
+            .ok_or_else(|| SyntaxErrorType::BadPitchClass(input.to_string()))?;
+        let (name, cents) = split_cents(&english)?;
+        let note = match octave {
+            Some(octave) if !name.ends_with(|ch: char| ch.is_ascii_digit()) => {
+                Note::from_name(name, octave, key)?
+            }
+            _ => Note::in_key(name, key)?,
+        };
+        Ok(Self::Note(Note { cents, ..note }))
+    }
+
+    // the frequency of the pitch, with a note tuned as `tuning` says
+    pub fn frequency(&self, tuning: &Tuning) -> f64 {
+        match self {
+            Self::Note(note) => tuning.frequency(note),
+            Self::Frequency(freq) => *freq,
+        }
+    }
+
+    // how many semitones above C0 the pitch is, which for a frequency is
+    // likely to fall between two notes
+    pub fn semitones(&self) -> f64 {
+        match self {
+            Self::Note(note) => note.semitones() as f64 + note.cents / 100.0,
+            Self::Frequency(freq) => {
+                let c0 = Note::from_semitones(0).map_or(1.0, |c0| c0.equal_tempered());
+                12.0 * (freq / c0).log2()
+            }
+        }
+    }
+
+    // the MIDI note number of the nearest note to the pitch, if it has one
+    pub fn to_midi(&self) -> Option<u8> {
+        Note::from_semitones(self.semitones().round() as i32)?.to_midi()
+    }
+
+    // the octave that the pitch falls in
+    pub fn octave(&self) -> i32 {
+        (self.semitones() / 12.0).floor() as i32
+    }
+
+    // the pitch the given number of semitones above (or below) this one, as
+    // long as a note isn't taken below the lowest octave, or a frequency below
+    // the lowest one
+    pub fn transpose(&self, semitones: i32) -> Option<Self> {
+        match self {
+            Self::Note(note) => note.transpose(semitones).map(Self::Note),
+            Self::Frequency(freq) => Some(freq * 2.0f64.powf(semitones as f64 / 12.0))
+                .filter(|&freq| freq >= LOWEST_FREQUENCY)
+                .map(Self::Frequency),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn octave(input: &str) -> Option<i32> {
+        Note::new(input).ok().map(|note| note.octave)
+    }
+
+    #[test]
+    fn octaves_take_every_trailing_digit() {
+        assert_eq!(octave("C10"), Some(10));
+        assert!(matches!(
+            Note::new("C10").map(|n| n.pitch_class),
+            Ok(PitchClass::C)
+        ));
+        assert_eq!(octave("A4"), Some(4));
+        assert!(matches!(
+            Note::new("A4").map(|n| n.pitch_class),
+            Ok(PitchClass::A)
+        ));
+    }
+
+    #[test]
+    fn octaves_can_be_negative() {
+        assert_eq!(octave("C-1"), Some(-1));
+        assert_eq!(octave("C#-1"), Some(-1));
+        assert_eq!(octave("C-2"), None);
+    }
+}
@@ -0,0 +1,105 @@
+//! This module defines ornaments, which decorate a note with short notes
+//! around it: a grace note just before it (written `gD4,C4` in the pitch
+//! column), or a trill, mordent or turn (written as the modifiers `TR`, `MORD`
+//! and `TURN`). The notes around it are a step of the key above or below it,
+//! and are taken out of the note's own time, so the rhythm is left alone. A
+//! piece plays an ornamented note out as the notes that it stands for once it
+//! knows where the note starts.
+
+use crate::error::SyntaxErrorType;
+use crate::note::{KeySignature, Pitch};
+use crate::signal::Modifier;
+
+// how many ticks each of the short notes of an ornament lasts (a 32nd note),
+// unless the note is too short to fit them
+pub const ORNAMENT_TICKS: f64 = 2.0;
+
+// the most notes that a trill is played as; a longer note trills more slowly
+// rather than being split into ever more notes
+pub const MAX_TRILL_NOTES: usize = 1001;
+
+#[derive(Clone, Copy)]
+pub enum Ornament {
+    // a single short note before the main one
+    Grace(Pitch),
+    // the main note alternating quickly with the note above it
+    Trill(Pitch),
+    // the main note, the one above it (or below it, for a lower mordent) and
+    // the main note again
+    Mordent(Pitch),
+    // the notes above and below the main one, played in turn around it
+    Turn(Pitch, Pitch),
+}
+
+impl Ornament {
+    // the ornament that a `TR`, `MORD[:lower]` or `TURN` modifier puts on a
+    // note of the given pitch in the given key
+    pub fn new(
+        modifier: Modifier,
+        args: &str,
+        pitch: &Pitch,
+        key: &KeySignature,
+    ) -> Result<Self, SyntaxErrorType> {
+        let bad = || SyntaxErrorType::BadOrnament(args.to_string());
+        let step = |up| key.step(pitch, up).ok_or_else(bad);
+        match (modifier, args) {
+            (Modifier::Trill, "") => Ok(Self::Trill(step(true)?)),
+            (Modifier::Mordent, "") => Ok(Self::Mordent(step(true)?)),
+            (Modifier::Mordent, "lower") => Ok(Self::Mordent(step(false)?)),
+            (Modifier::Turn, "") => Ok(Self::Turn(step(true)?, step(false)?)),
+            _ => Err(bad()),
+        }
+    }
+
+    // the same ornament on a note moved by the given number of semitones
+    pub fn transpose(&self, semitones: i32) -> Option<Self> {
+        Some(match self {
+            Self::Grace(grace) => Self::Grace(grace.transpose(semitones)?),
+            Self::Trill(upper) => Self::Trill(upper.transpose(semitones)?),
+            Self::Mordent(other) => Self::Mordent(other.transpose(semitones)?),
+            Self::Turn(upper, lower) => {
+                Self::Turn(upper.transpose(semitones)?, lower.transpose(semitones)?)
+            }
+        })
+    }
+
+    // the notes that a note of the given pitch and length (in ticks) is
+    // played as, each as the ticks after the note's start that it starts on,
+    // how many ticks it lasts, and its pitch; the last is always the main note
+    pub fn expand(&self, pitch: Pitch, ticks: f64) -> Vec<(f64, f64, Pitch)> {
+        match *self {
+            Self::Grace(grace) => {
+                let short = ORNAMENT_TICKS.min(ticks / 2.0);
+                vec![(0.0, short, grace), (short, ticks - short, pitch)]
+            }
+            // an odd number of notes, so that the trill ends where it starts
+            Self::Trill(upper) => {
+                let count = ((ticks / ORNAMENT_TICKS) as usize).clamp(3, MAX_TRILL_NOTES);
+                let count = count - (1 - count % 2);
+                let each = ticks / count as f64;
+                (0..count)
+                    .map(|k| {
+                        let note = if k % 2 == 0 { pitch } else { upper };
+                        (k as f64 * each, each, note)
+                    })
+                    .collect()
+            }
+            Self::Mordent(other) => {
+                let short = ORNAMENT_TICKS.min(ticks / 4.0);
+                vec![
+                    (0.0, short, pitch),
+                    (short, short, other),
+                    (2.0 * short, ticks - 2.0 * short, pitch),
+                ]
+            }
+            Self::Turn(upper, lower) => {
+                let each = ticks / 4.0;
+                [upper, pitch, lower, pitch]
+                    .into_iter()
+                    .enumerate()
+                    .map(|(k, note)| (k as f64 * each, each, note))
+                    .collect()
+            }
+        }
+    }
+}
@@ -1,127 +1,1618 @@
 //! This module defines the type MusicalPiece, which is taken to be a sequence
 //! of notes, together with a tempo and an amplitude. It gives it a method to
 //! read a file input, and another to spit out the PCM output that the piece
-//! represents.
+//! represents. PieceBuilder offers a way to assemble a piece in code instead.
 
+use crate::chord::{Arpeggio, Voicing};
+use crate::effects::{fade, Effect, FadeLength, DEFAULT_REVERB};
 use crate::error::{MusicError, SyntaxErrorType};
-use crate::signal::{NoteSignal, WaveType};
+use crate::formats::mml::Mml;
+use crate::humanize::Humanize;
+use crate::instrument::{Instrument, Patch};
+use crate::meter::Meter;
+use crate::mixer::{find_track, mix_into, Automation, Track};
+use crate::noise::Noise;
+use crate::note::{KeySignature, Notation, Pitch, NOTATIONS};
+use crate::signal::{
+    parse_amplitude, parse_base_amplitude, parse_tuplet, NoteDuration, NoteSignal, NoteStart,
+    VelocityCurve, WaveType,
+};
+use crate::source::{expand_includes, strip_comment, syntax_error, SourceLine};
+use crate::speakers::{layout, Speakers};
+use crate::tempo::{Swing, TempoMap};
+use crate::timbre::Timbres;
+use crate::tuning::{Tuning, DEFAULT_A4};
 use crate::utils::*;
+use crate::voice::Voice;
+
+use std::collections::{HashMap, HashSet};
+use std::num::NonZeroU32;
+use std::path::Path;
+use std::str::FromStr;
 
 // by a musical piece, we simply mean a score with a tempo
 pub struct MusicalPiece {
+    signals: Vec<NoteSignal>,
+    tempo: TempoMap,
+    ampl: u16,
+    click_guard: f64,
+    max_duration: f64,
+    channels: u16,
+    sample_rate: u32,
+    band_limited: bool,
+    timbres: Timbres,
+    tracks: Vec<Track>,
+    effects: Vec<Effect>,
+    fade_in: Option<FadeLength>,
+    fade_out: Option<FadeLength>,
+    markers: Vec<(String, f64)>,
+    loops: Vec<(f64, f64)>,
+    tuning: Tuning,
+    metadata: Metadata,
+}
+
+// the lines of a piece that set something up rather than play a note
+#[derive(Clone, Copy)]
+pub enum Directive {
+    Bpm,
+    Ampl,
+    Key,
+    Notation,
+    Label,
+    Mark,
+    Loop,
+    Goto,
+    Envelope,
+    Rate,
+    Title,
+    Artist,
+    Comment,
+    Harmonics,
+    Vibrato,
+    Tremolo,
+    Unison,
+    Filter,
+    FilterEnvelope,
+    Instrument,
+    End,
+    Reverb,
+    Delay,
+    Chorus,
+    Distortion,
+    FadeIn,
+    FadeOut,
+    Compress,
+    Eq,
+    Track,
+    Default,
+    Fx,
+    WaveDef,
+    Sample,
+    Voicing,
+    Arp,
+    Humanize,
+    Time,
+    Ppq,
+    Swing,
+    Temperament,
+    A4,
+    Ramp,
+    Swell,
+    Repeat,
+    Pattern,
+    Play,
+    EndBlock,
+    Include,
+    Transpose,
+    Tuplet,
+    Mml,
+}
+
+// the blocks that a `}` can close, with the transposition that was in force
+// before a `TRANSPOSE` block (or the scale of durations, before a `TUPLET`
+// block), to go back to at its end
+enum Block {
+    Repeat,
+    Pattern(String),
+    Transpose(i32),
+    Tuplet(f64),
+}
+
+// the first pass over a piece reads each line into one of these (a `PLAY`
+// holds the pattern's name, the tick it starts on, unless it follows on, the
+// semitones it is transposed by and the track it plays in, and a `MARK` its
+// name, its tick, unless it follows on, and its track), and the
+// second pass then expands any `GOTO` or `REPEAT` block into copies of the
+// material it replays;
+// the signals of a line (more than one, for a chord) all start together,
+// unless the arpeggio in force for it staggers them
+#[derive(Clone)]
+enum Entry {
+    Signals(Vec<NoteSignal>, Arpeggio),
+    Label(String),
+    Goto(String, u32),
+    Repeat(u32),
+    EndRepeat,
+    Play(String, Option<f64>, i32, usize),
+    Mark(String, Option<f64>, usize),
+}
+
+// every directive keyword, with the arguments it takes and what it does
+pub const DIRECTIVES: [(&str, Directive, &str, &str); 55] = [
+    (
+        "BPM",
+        Directive::Bpm,
+        "<bpm> [@ <tick>]",
+        "tempo in quarter-note beats per minute, from <tick> on",
+    ),
+    (
+        "RIT",
+        Directive::Ramp,
+        "<from> <to> <bpm>",
+        "slow the tempo gradually between two ticks, ending at <bpm>",
+    ),
+    (
+        "ACCEL",
+        Directive::Ramp,
+        "<from> <to> <bpm>",
+        "same as RIT, but speeding up",
+    ),
+    (
+        "CRESC",
+        Directive::Swell,
+        "<from> <to> [<level>] <level>",
+        "ramp the current track's gain between two ticks, ending at <level>",
+    ),
+    (
+        "DIM",
+        Directive::Swell,
+        "<from> <to> [<level>] <level>",
+        "same as CRESC, but getting quieter",
+    ),
+    (
+        "AMPL",
+        Directive::Ampl,
+        "<ampl>",
+        "base amplitude that note amplitudes scale",
+    ),
+    ("AMPLITUDE", Directive::Ampl, "<ampl>", "same as AMPL"),
+    (
+        "KEY",
+        Directive::Key,
+        "<tonic> <major|minor>",
+        "key signature applied to bare letter names",
+    ),
+    (
+        "NOTATION",
+        Directive::Notation,
+        "<english|german|solfege>",
+        "names that later notes and KEY tonics are written with",
+    ),
+    (
+        "VOICING",
+        Directive::Voicing,
+        "<close|open|drop2> <octave>",
+        "how later chord symbols are spread out, and the octave of their root",
+    ),
+    (
+        "ARP",
+        Directive::Arp,
+        "<ticks> [up|down]",
+        "stagger the notes of later chords by <ticks>, lowest first unless down",
+    ),
+    (
+        "HUMANIZE",
+        Directive::Humanize,
+        "[timing=<ticks>] [ampl=<fraction>]",
+        "nudge later notes' starts and amplitudes at random, by up to these",
+    ),
+    (
+        "TIME",
+        Directive::Time,
+        "<beats>/<unit> [@ <measure>]",
+        "time signature for measure:beat:tick start times, from <measure> on",
+    ),
+    (
+        "PPQ",
+        Directive::Ppq,
+        "<ticks>",
+        "ticks per quarter note in later tick counts (16 unless set)",
+    ),
+    (
+        "SWING",
+        Directive::Swing,
+        "<ratio> [E|S]",
+        "play pairs of eighths (or sixteenths) long-short, 0.5 being straight",
+    ),
+    (
+        "TEMPERAMENT",
+        Directive::Temperament,
+        "<equal|just|pythagorean|meantone> [<tonic>]",
+        "tune notes in the given temperament, built on <tonic> (C unless given)",
+    ),
+    (
+        "A4",
+        Directive::A4,
+        "<hz>",
+        "reference pitch that A4 is tuned to (440 unless set)",
+    ),
+    (
+        "MARK",
+        Directive::Mark,
+        "<name> [@ <tick>]",
+        "name a point in the piece, written as a cue point",
+    ),
+    (
+        "LOOP",
+        Directive::Loop,
+        "<start> <end>",
+        "loop points (in ticks) for samplers, written as a smpl chunk",
+    ),
+    (
+        "LABEL",
+        Directive::Label,
+        "<name>",
+        "mark the start of a section for GOTO",
+    ),
+    (
+        "GOTO",
+        Directive::Goto,
+        "<name> <count>",
+        "replay everything since LABEL <name> <count> more times",
+    ),
+    (
+        "REPEAT",
+        Directive::Repeat,
+        "<count> {",
+        "play everything up to the matching } <count> times over",
+    ),
+    (
+        "TRANSPOSE",
+        Directive::Transpose,
+        "<+n|-n> [{]",
+        "shift later notes n semitones, or, with {, those up to the matching }",
+    ),
+    (
+        "TUPLET",
+        Directive::Tuplet,
+        "<n>[:<m>] {",
+        "fit n notes in the time of m, up to the matching }",
+    ),
+    (
+        "PATTERN",
+        Directive::Pattern,
+        "<name> {",
+        "define the notes up to the matching } as a pattern, played by PLAY",
+    ),
+    (
+        "PLAY",
+        Directive::Play,
+        "<name> [@ <tick>] [+n|-n]",
+        "play a pattern from <tick> (or following on), transposed n semitones",
+    ),
+    (
+        "}",
+        Directive::EndBlock,
+        "",
+        "close a REPEAT, PATTERN, TRANSPOSE or TUPLET block",
+    ),
+    (
+        "MML",
+        Directive::Mml,
+        "<mml>",
+        "play notes written in MML (e.g. t120 o4 l8 cdefgab>c), following on",
+    ),
+    (
+        "INCLUDE",
+        Directive::Include,
+        "<file>",
+        "read the lines of another score in here, relative to this one",
+    ),
+    (
+        "ENV",
+        Directive::Envelope,
+        "<a> <d> <s> <r>",
+        "ADSR envelope (seconds, sustain level) for later notes",
+    ),
+    ("RATE", Directive::Rate, "<hz>", "output sample rate"),
+    (
+        "TITLE",
+        Directive::Title,
+        "<text>",
+        "title written into the WAV file",
+    ),
+    (
+        "ARTIST",
+        Directive::Artist,
+        "<text>",
+        "artist written into the WAV file",
+    ),
+    (
+        "COMMENT",
+        Directive::Comment,
+        "<text>",
+        "comment written into the WAV file",
+    ),
+    (
+        "HARMONICS",
+        Directive::Harmonics,
+        "<name> <ampl>...",
+        "additive timbre played by H:<name>, one amplitude per harmonic",
+    ),
+    (
+        "WAVEDEF",
+        Directive::WaveDef,
+        "<name> <ampl>...",
+        "waveform of sine partials, played by writing <name> as the waveform",
+    ),
+    (
+        "SAMPLE",
+        Directive::Sample,
+        "<name> <file> [root]",
+        "WAV sample played by <name>, at its own speed on <root> (C4)",
+    ),
+    (
+        "VIB",
+        Directive::Vibrato,
+        "<rate> <depth>",
+        "vibrato (Hz, cents either way) for later notes",
+    ),
+    (
+        "TREM",
+        Directive::Tremolo,
+        "<rate> <depth>",
+        "tremolo (Hz, depth from 0 to 1) for later notes",
+    ),
+    (
+        "UNISON",
+        Directive::Unison,
+        "<voices> <detune>",
+        "detuned unison (voice count, cents either way) for later notes",
+    ),
+    (
+        "FILTER",
+        Directive::Filter,
+        "<LP|HP> <cutoff> <q>",
+        "resonant low- or high-pass filter (Hz, Q) for later notes",
+    ),
+    (
+        "FENV",
+        Directive::FilterEnvelope,
+        "<a> <d> <s> <r> <depth>",
+        "ADSR sweep of the FILTER cutoff by up to <depth> octaves",
+    ),
+    (
+        "INSTRUMENT",
+        Directive::Instrument,
+        "<name> <waveform>",
+        "start defining an instrument, played by I:<name>",
+    ),
+    ("END", Directive::End, "", "finish an INSTRUMENT block"),
+    (
+        "REVERB",
+        Directive::Reverb,
+        "<size> <damp> <mix>",
+        "reverb over the whole piece (each from 0 to 1)",
+    ),
+    (
+        "DELAY",
+        Directive::Delay,
+        "<duration> <feedback> <mix>",
+        "echoes over the whole piece, a note duration apart",
+    ),
+    (
+        "CHORUS",
+        Directive::Chorus,
+        "<rate> <depth> <mix>",
+        "chorus over the whole piece (Hz, milliseconds, 0 to 1)",
+    ),
+    (
+        "DISTORTION",
+        Directive::Distortion,
+        "<TANH|CLIP> <drive> <tone>",
+        "overdrive the whole piece (drive at least 1, tone 0 to 1)",
+    ),
+    (
+        "COMPRESS",
+        Directive::Compress,
+        "<threshold> <ratio> <attack> <release>",
+        "compress the whole piece (dBFS, ratio, milliseconds)",
+    ),
+    (
+        "EQ",
+        Directive::Eq,
+        "<LOW|MID|HIGH> <freq> <gain> [q]",
+        "shelf (LOW, HIGH) or peak (MID, with a Q) over the whole piece, in dB",
+    ),
+    (
+        "TRACK",
+        Directive::Track,
+        "<name> [<setting>:<value> ...]",
+        "mix later notes in the named track, with defaults for their columns",
+    ),
+    (
+        "DEFAULT",
+        Directive::Default,
+        "<WAVE|AMPL|OCTAVE|PAN> <value>",
+        "fill in a column that later notes in the current track leave out",
+    ),
+    (
+        "FX",
+        Directive::Fx,
+        "<effect> -> <effect> ...",
+        "effects chain for the current track, before it is mixed",
+    ),
+    (
+        "FADEIN",
+        Directive::FadeIn,
+        "<seconds|duration>",
+        "fade the whole piece in from silence",
+    ),
+    (
+        "FADEOUT",
+        Directive::FadeOut,
+        "<seconds|duration>",
+        "fade the whole piece out to silence, tails and all",
+    ),
+];
+
+// the settings that can be passed in alongside the text of a piece, mostly
+// from the command line; those that stand for a directive win over it
+#[derive(Clone)]
+pub struct PieceOptions {
+    // the starting tempo, base amplitude and sample rate, in place of the
+    // `BPM`, `AMPL` and `RATE` directives
+    pub bpm: Option<f64>,
+    pub ampl: Option<u16>,
+    // how note amplitudes map onto loudness
+    pub velocity_curve: VelocityCurve,
+    // whether a repeated directive, or one that disagrees with the command
+    // line, is an error rather than ignored
+    pub strict: bool,
+    // the length in milliseconds of the fade at either end of every note
+    pub click_guard: f64,
+    // the longest render in seconds that synthesis will attempt, tails and all
+    pub max_duration: f64,
+    // 1 for mono output, 2 for stereo output that obeys pans, or 4, 6 or 8
+    // for quad, 5.1 or 7.1 output (see `speakers`)
+    pub channels: u16,
+    pub sample_rate: Option<u32>,
+    // whether to swap the naive waveforms for ones that alias far less
+    pub band_limited: bool,
+    // whether to add a default reverb if the piece doesn't ask for one itself
+    pub reverb: bool,
+    // in place of the `FADEIN` and `FADEOUT` directives
+    pub fade_in: Option<FadeLength>,
+    pub fade_out: Option<FadeLength>,
+    // a compressor to take the place of any that the piece has
+    pub compressor: Option<Effect>,
+    // in place of the `TEMPERAMENT` and `A4` directives
+    pub tuning: Option<Tuning>,
+    pub a4: Option<f64>,
+    // what the random nudges of `HUMANIZE` are drawn from
+    pub seed: u64,
+    // in place of the `TITLE`, `ARTIST` and `COMMENT` directives
+    pub metadata: Metadata,
+}
+
+// builds up a piece note by note, without going through the text format
+pub struct PieceBuilder {
     signals: Vec<NoteSignal>,
     bpm: f64,
+    tempo_changes: Vec<(f64, f64)>,
+    tempo_ramps: Vec<(f64, f64, f64)>,
+    swing: Option<Swing>,
+    markers: Vec<(String, f64)>,
+    loops: Vec<(f64, f64)>,
     ampl: u16,
+    click_guard: f64,
+    max_duration: f64,
+    channels: u16,
+    sample_rate: u32,
+    band_limited: bool,
+    timbres: Timbres,
+    tracks: Vec<Track>,
+    track: usize,
+    effects: Vec<Effect>,
+    fade_in: Option<FadeLength>,
+    fade_out: Option<FadeLength>,
+    tuning: Tuning,
 }
 
 impl MusicalPiece {
-    pub fn new(
-        input: &str,
-        mut bpm: Option<f64>,
-        mut ampl: Option<u16>,
-    ) -> Result<Self, MusicError> {
-        let mut signals = vec![];
+    // parse the text of the score at `path`, which is where any files that it
+    // includes are looked for, relative to; errors are reported against the
+    // file and line that they turn up on
+    pub fn new(input: &str, path: &Path, options: &PieceOptions) -> Result<Self, MusicError> {
+        let lines = expand_includes(input, path)?;
+        Self::parse(&lines, options).map_err(|e| match e {
+            MusicError::SyntaxError(n, e) => match lines.get(n.wrapping_sub(1)) {
+                Some(line) => syntax_error(line.file.as_deref(), line.number, e),
+                None => MusicError::SyntaxError(n, e),
+            },
+            e => e,
+        })
+    }
 
-        for (n, line) in input.lines().enumerate() {
-            if line.starts_with("BPM ") {
-                if bpm.is_none() {
-                    bpm = Some(
-                        match line.split_whitespace().nth(1).unwrap_or_default().parse() {
-                            Ok(n) => n,
-                            Err(_) => {
-                                return Err(MusicError::SyntaxError(
-                                    n + 1,
-                                    SyntaxErrorType::BadBPM(line[4..].to_string()),
-                                ))
+    // outside of strict mode, the first `BPM`/`AMPL`/`RATE` (or `FADEIN`,
+    // `FADEOUT`, `SWING`, `TEMPERAMENT`, `A4`, `TITLE`, `ARTIST` or `COMMENT`)
+    // directive in the file wins over any later ones, and a
+    // command-line value wins over them; the line numbers in any errors count
+    // through all of `lines`, whichever file each one came from
+    fn parse(lines: &[SourceLine], options: &PieceOptions) -> Result<Self, MusicError> {
+        let mut entries = vec![];
+        let mut bpm = options.bpm;
+        let mut ampl = options.ampl;
+        let mut sample_rate = options.sample_rate.and_then(NonZeroU32::new);
+        let mut bpm_seen = false;
+        let mut tempo_changes: Vec<(f64, f64)> = vec![];
+        let mut tempo_ramps = vec![];
+        let mut swing = None;
+        let mut tuning = options.tuning.clone();
+        let mut tuning_seen = false;
+        let mut a4 = options.a4;
+        let mut a4_seen = false;
+        let mut ampl_seen = false;
+        let mut rate_seen = false;
+        let mut fade_in = options.fade_in;
+        let mut fade_out = options.fade_out;
+        let mut fade_in_seen = false;
+        let mut fade_out_seen = false;
+        let mut metadata = options.metadata.clone();
+        let mut title_seen = false;
+        let mut artist_seen = false;
+        let mut comment_seen = false;
+        let mut loops = vec![];
+        let mut key = KeySignature::default();
+        let mut notation = Notation::default();
+        let mut voicing = Voicing::default();
+        let mut arpeggio = Arpeggio::default();
+        let mut humanize = Humanize::default();
+        let mut meter = Meter::default();
+        let mut patch = Patch::default();
+        let mut timbres = Timbres::default();
+        let mut effects = vec![];
+        let mut tracks = vec![Track::default()];
+        let mut track = 0;
+        // the line, name and definition of the instrument being set up, when
+        // inside an `INSTRUMENT` block
+        let mut block: Option<(usize, String, Instrument)> = None;
+        // the line of each `REPEAT`, `PATTERN` or `TRANSPOSE` block still
+        // open, innermost last; while a pattern is being defined, its entries
+        // are gathered in place of those of the piece, which wait in `outer`
+        // until it is closed
+        let mut blocks: Vec<(usize, Block)> = vec![];
+        let mut outer = vec![];
+        let mut patterns = HashMap::new();
+        let mut transpose = 0;
+        // how much the durations of notes are scaled by, inside tuplets
+        let mut tuplet = 1.0;
+        // where the MML of each track has got to, in its octave, note length
+        // and so on
+        let mut mml: HashMap<usize, Mml> = HashMap::new();
+
+        for (n, line) in lines.iter().enumerate() {
+            let text = line.text.as_str();
+            let line = strip_comment(text);
+            if line.trim().is_empty() {
+                continue;
+            }
+            let mut words = line.split_whitespace();
+            let keyword = words.next().unwrap_or_default();
+            let args = words.collect::<Vec<_>>();
+            let directive = Directive::find(keyword);
+
+            // inside an `INSTRUMENT` block, the directives that shape notes
+            // shape the instrument instead, until `END` puts it to use
+            if let Some((start, name, mut instrument)) = block.take() {
+                let result = match directive {
+                    Some(Directive::End) => {
+                        timbres.instruments.insert(name, instrument);
+                        Ok(())
+                    }
+                    Some(directive) if directive.shapes_notes() => {
+                        let result = instrument.patch.set(directive, &args);
+                        block = Some((start, name, instrument));
+                        result
+                    }
+                    _ => Err(SyntaxErrorType::InstrumentBody(line.to_string())),
+                };
+                if let Err(e) = result {
+                    return Err(MusicError::SyntaxError(n + 1, e));
+                }
+                continue;
+            }
+
+            // an `MML` line stands for the note lines that it spells out, and
+            // sets the tempo just as a `BPM` line would
+            let mut notes = vec![line.to_string()];
+            if let Some(Directive::Mml) = directive {
+                let state = mml.entry(track).or_default();
+                let result = state
+                    .read(&args.join(" "), &tracks[track].defaults)
+                    .and_then(|(tempo, mml_notes)| {
+                        notes = mml_notes;
+                        match tempo {
+                            Some(tempo) => set_directive(
+                                line,
+                                &[&tempo.to_string()],
+                                &mut bpm,
+                                &mut bpm_seen,
+                                options.bpm.is_some(),
+                                options.strict,
+                                SyntaxErrorType::BadBPM,
+                            ),
+                            None => Ok(()),
+                        }
+                    });
+                if let Err(e) = result {
+                    return Err(MusicError::SyntaxError(n + 1, e));
+                }
+            }
+
+            let result = match directive {
+                // a tempo change later in the piece works like the starting
+                // tempo, except that the command line never overrides it
+                Some(Directive::Bpm) => match args[..] {
+                    [value, "@", at] => match (value.parse::<f64>(), NoteStart::new(at, &meter)) {
+                        (Ok(value), Ok(NoteStart::Ticks(tick)))
+                            if value.is_finite() && value > 0.0 =>
+                        {
+                            match tempo_changes.iter().any(|&(t, _)| t == tick) {
+                                true if options.strict => {
+                                    Err(SyntaxErrorType::DuplicateDirective(line.to_string()))
+                                }
+                                true => Ok(()),
+                                false => {
+                                    tempo_changes.push((tick, value));
+                                    Ok(())
+                                }
+                            }
+                        }
+                        _ => Err(SyntaxErrorType::BadBPM(args.join(" "))),
+                    },
+                    _ => set_directive(
+                        line,
+                        &args,
+                        &mut bpm,
+                        &mut bpm_seen,
+                        options.bpm.is_some(),
+                        options.strict,
+                        SyntaxErrorType::BadBPM,
+                    ),
+                },
+                Some(Directive::Ampl) => match args.first().and_then(|a| parse_base_amplitude(a)) {
+                    Some(level) => set_directive(
+                        line,
+                        &[&level.to_string()],
+                        &mut ampl,
+                        &mut ampl_seen,
+                        options.ampl.is_some(),
+                        options.strict,
+                        SyntaxErrorType::BadAmplitude,
+                    ),
+                    None => Err(SyntaxErrorType::BadAmplitude(args.join(" "))),
+                },
+                Some(Directive::Rate) => set_directive(
+                    line,
+                    &args,
+                    &mut sample_rate,
+                    &mut rate_seen,
+                    options.sample_rate.is_some(),
+                    options.strict,
+                    SyntaxErrorType::BadSampleRate,
+                ),
+                // the text is the rest of the line, spaces and all, and isn't
+                // cut short by a comment, so that a title can have a `#` in it
+                Some(directive @ (Directive::Title | Directive::Artist | Directive::Comment)) => {
+                    let given = &options.metadata;
+                    let (value, seen, from_cli) = match directive {
+                        Directive::Title => {
+                            (&mut metadata.title, &mut title_seen, given.title.is_some())
+                        }
+                        Directive::Artist => (
+                            &mut metadata.artist,
+                            &mut artist_seen,
+                            given.artist.is_some(),
+                        ),
+                        _ => (
+                            &mut metadata.comment,
+                            &mut comment_seen,
+                            given.comment.is_some(),
+                        ),
+                    };
+                    match text.trim().split_once(char::is_whitespace) {
+                        Some((_, text)) => set_directive(
+                            line,
+                            &[text.trim()],
+                            value,
+                            seen,
+                            from_cli,
+                            options.strict,
+                            SyntaxErrorType::BadMetadata,
+                        ),
+                        None => Err(SyntaxErrorType::BadMetadata(line.trim().to_string())),
+                    }
+                }
+                Some(Directive::FadeIn) => set_directive(
+                    line,
+                    &args,
+                    &mut fade_in,
+                    &mut fade_in_seen,
+                    options.fade_in.is_some(),
+                    options.strict,
+                    SyntaxErrorType::BadDuration,
+                ),
+                Some(Directive::FadeOut) => set_directive(
+                    line,
+                    &args,
+                    &mut fade_out,
+                    &mut fade_out_seen,
+                    options.fade_out.is_some(),
+                    options.strict,
+                    SyntaxErrorType::BadDuration,
+                ),
+                Some(Directive::Key) => match args[..] {
+                    [tonic, mode] => match notation.to_english(tonic) {
+                        Some(tonic) => KeySignature::new(&tonic, mode).map(|k| key = k),
+                        None => Err(SyntaxErrorType::BadKey(args.join(" "))),
+                    },
+                    _ => Err(SyntaxErrorType::BadKey(args.join(" "))),
+                },
+                Some(Directive::Notation) => match args[..] {
+                    [name] => match NOTATIONS.iter().find(|(n, _)| *n == name) {
+                        Some(&(_, n)) => {
+                            notation = n;
+                            Ok(())
+                        }
+                        None => Err(SyntaxErrorType::BadNotation(name.to_string())),
+                    },
+                    _ => Err(SyntaxErrorType::BadNotation(args.join(" "))),
+                },
+                Some(Directive::Ramp) => match parse_ramp(&args, &meter) {
+                    Some(ramp) => {
+                        tempo_ramps.push(ramp);
+                        Ok(())
+                    }
+                    None => Err(SyntaxErrorType::BadBPM(args.join(" "))),
+                },
+                Some(Directive::Swell) => match parse_swell(&args, &meter) {
+                    Some((from, to, start, end)) => {
+                        // the levels are amplitudes, just like those of notes
+                        let curve = |level| options.velocity_curve.apply(level);
+                        tracks[track]
+                            .gain
+                            .ramp(from, to, start.map(curve), curve(end));
+                        Ok(())
+                    }
+                    None => Err(SyntaxErrorType::BadSwell(args.join(" "))),
+                },
+                Some(Directive::Voicing) => Voicing::new(&args).map(|v| voicing = v),
+                Some(Directive::Arp) => Arpeggio::new(&args, &meter).map(|a| arpeggio = a),
+                Some(Directive::Humanize) => Humanize::new(&args, &meter).map(|h| humanize = h),
+                Some(Directive::Time) => meter.set(&args),
+                Some(Directive::Ppq) => meter.set_ppq(&args),
+                Some(Directive::Swing) => Swing::parse(&args).and_then(|s| match swing {
+                    Some(_) if options.strict => {
+                        Err(SyntaxErrorType::DuplicateDirective(line.to_string()))
+                    }
+                    Some(_) => Ok(()),
+                    None => {
+                        swing = Some(s);
+                        Ok(())
+                    }
+                }),
+                Some(Directive::Temperament) => Tuning::new(&args).and_then(|t| {
+                    if tuning_seen && options.strict {
+                        return Err(SyntaxErrorType::DuplicateDirective(line.to_string()));
+                    }
+                    if options.strict && options.tuning.as_ref().is_some_and(|cli| *cli != t) {
+                        return Err(SyntaxErrorType::ConflictingDirective(line.to_string()));
+                    }
+                    tuning.get_or_insert(t);
+                    tuning_seen = true;
+                    Ok(())
+                }),
+                Some(Directive::A4) => match args.first().map(|hz| hz.parse::<f64>()) {
+                    Some(Ok(hz)) if hz.is_finite() && hz > 0.0 => set_directive(
+                        line,
+                        &args,
+                        &mut a4,
+                        &mut a4_seen,
+                        options.a4.is_some(),
+                        options.strict,
+                        SyntaxErrorType::BadFrequency,
+                    ),
+                    _ => Err(SyntaxErrorType::BadFrequency(args.join(" "))),
+                },
+                Some(Directive::Mark) => match args[..] {
+                    [name] => {
+                        entries.push((n + 1, Entry::Mark(name.to_string(), None, track)));
+                        Ok(())
+                    }
+                    [name, "@", at] => match NoteStart::new(at, &meter) {
+                        Ok(NoteStart::Ticks(tick)) => {
+                            let mark = Entry::Mark(name.to_string(), Some(tick), track);
+                            entries.push((n + 1, mark));
+                            Ok(())
+                        }
+                        _ => Err(SyntaxErrorType::BadMark(args.join(" "))),
+                    },
+                    _ => Err(SyntaxErrorType::BadMark(args.join(" "))),
+                },
+                // loop points are absolute, wherever the line is
+                Some(Directive::Loop) => match args[..] {
+                    [start, end] => {
+                        match (NoteStart::new(start, &meter), NoteStart::new(end, &meter)) {
+                            (Ok(NoteStart::Ticks(start)), Ok(NoteStart::Ticks(end)))
+                                if start < end =>
+                            {
+                                loops.push((start, end));
+                                Ok(())
                             }
-                        },
-                    );
+                            _ => Err(SyntaxErrorType::BadLoop(args.join(" "))),
+                        }
+                    }
+                    _ => Err(SyntaxErrorType::BadLoop(args.join(" "))),
+                },
+                Some(Directive::Label) => match args[..] {
+                    [name] => {
+                        entries.push((n + 1, Entry::Label(name.to_string())));
+                        Ok(())
+                    }
+                    _ => Err(SyntaxErrorType::BadLabel(args.join(" "))),
+                },
+                Some(Directive::Goto) => match args[..] {
+                    [name, count] => match count.parse() {
+                        Ok(count) => {
+                            entries.push((n + 1, Entry::Goto(name.to_string(), count)));
+                            Ok(())
+                        }
+                        Err(_) => Err(SyntaxErrorType::BadGoto(args.join(" "))),
+                    },
+                    _ => Err(SyntaxErrorType::BadGoto(args.join(" "))),
+                },
+                Some(Directive::Repeat) => match args[..] {
+                    [count, "{"] => match count.parse::<u32>() {
+                        Ok(count) if count > 0 => {
+                            blocks.push((n + 1, Block::Repeat));
+                            entries.push((n + 1, Entry::Repeat(count)));
+                            Ok(())
+                        }
+                        _ => Err(SyntaxErrorType::BadRepeat(line.to_string())),
+                    },
+                    _ => Err(SyntaxErrorType::BadRepeat(line.to_string())),
+                },
+                Some(Directive::Pattern) => match args[..] {
+                    [_, "{"] if blocks.iter().any(|(_, b)| matches!(b, Block::Pattern(_))) => {
+                        Err(SyntaxErrorType::BadPattern(line.to_string()))
+                    }
+                    [name, "{"] if patterns.contains_key(name) => {
+                        Err(SyntaxErrorType::DuplicatePattern(name.to_string()))
+                    }
+                    [name, "{"] => {
+                        blocks.push((n + 1, Block::Pattern(name.to_string())));
+                        outer = std::mem::take(&mut entries);
+                        Ok(())
+                    }
+                    _ => Err(SyntaxErrorType::BadPattern(line.to_string())),
+                },
+                Some(Directive::Play) => match parse_play(&args, &meter) {
+                    Some((name, _, _)) if !patterns.contains_key(name) => {
+                        Err(SyntaxErrorType::UndefinedPattern(name.to_string()))
+                    }
+                    Some((name, start, semitones)) => {
+                        let play = Entry::Play(name.to_string(), start, semitones, track);
+                        entries.push((n + 1, play));
+                        Ok(())
+                    }
+                    None => Err(SyntaxErrorType::BadPattern(line.to_string())),
+                },
+                // a transposition on its own holds from here on, but one that
+                // opens a block adds to the one in force, until the block ends
+                Some(Directive::Transpose) => match args[..] {
+                    [shift] => parse_shift(shift).map(|shift| transpose = shift),
+                    [shift, "{"] => parse_shift(shift).map(|shift| {
+                        blocks.push((n + 1, Block::Transpose(transpose)));
+                        transpose += shift;
+                    }),
+                    _ => Err(SyntaxErrorType::BadTranspose(line.to_string())),
+                },
+                Some(Directive::Tuplet) => match args[..] {
+                    [ratio, "{"] => match parse_tuplet(ratio) {
+                        Some((notes, time)) => {
+                            blocks.push((n + 1, Block::Tuplet(tuplet)));
+                            tuplet *= time as f64 / notes as f64;
+                            Ok(())
+                        }
+                        None => Err(SyntaxErrorType::BadDuration(line.to_string())),
+                    },
+                    _ => Err(SyntaxErrorType::BadDuration(line.to_string())),
+                },
+                Some(Directive::EndBlock) => match (blocks.pop(), args.is_empty()) {
+                    (Some((_, Block::Repeat)), true) => {
+                        entries.push((n + 1, Entry::EndRepeat));
+                        Ok(())
+                    }
+                    (Some((_, Block::Pattern(name))), true) => {
+                        let pattern = std::mem::replace(&mut entries, std::mem::take(&mut outer));
+                        patterns.insert(name, pattern);
+                        Ok(())
+                    }
+                    (Some((_, Block::Transpose(previous))), true) => {
+                        transpose = previous;
+                        Ok(())
+                    }
+                    (Some((_, Block::Tuplet(previous))), true) => {
+                        tuplet = previous;
+                        Ok(())
+                    }
+                    _ => Err(SyntaxErrorType::BadBlock(line.to_string())),
+                },
+                // `expand_includes()` has already put the file in its place
+                Some(Directive::Include) => Ok(()),
+                Some(Directive::Harmonics) => timbres.harmonics.define(&args),
+                Some(Directive::WaveDef) => timbres.define_wave(&args),
+                Some(Directive::Sample) => timbres.define_sample(&args),
+                Some(Directive::Reverb) => Effect::reverb(&args).map(|e| effects.push(e)),
+                Some(Directive::Delay) => Effect::delay(&args, meter.ppq).map(|e| effects.push(e)),
+                Some(Directive::Chorus) => Effect::chorus(&args).map(|e| effects.push(e)),
+                Some(Directive::Distortion) => Effect::distortion(&args).map(|e| effects.push(e)),
+                Some(Directive::Compress) => Effect::compressor(&args).map(|e| effects.push(e)),
+                Some(Directive::Eq) => Effect::equalizer(&args).map(|e| effects.push(e)),
+                // settings after the name change the track's defaults, from
+                // here on
+                Some(Directive::Track) => match args[..] {
+                    [name, ref settings @ ..] => {
+                        track = find_track(&mut tracks, name);
+                        settings
+                            .iter()
+                            .try_for_each(|setting| match setting.split_once(':') {
+                                Some((name, value)) => tracks[track].defaults.set(name, value),
+                                None => Err(SyntaxErrorType::BadDefault(setting.to_string())),
+                            })
+                    }
+                    _ => Err(SyntaxErrorType::BadTrack(args.join(" "))),
+                },
+                Some(Directive::Default) => match args[..] {
+                    [name, value] => tracks[track].defaults.set(name, value),
+                    _ => Err(SyntaxErrorType::BadDefault(args.join(" "))),
+                },
+                Some(Directive::Fx) => {
+                    Effect::chain(&args, meter.ppq).map(|chain| tracks[track].effects = chain)
+                }
+                Some(Directive::Instrument) => match args[..] {
+                    [name, waveform] => WaveType::new(waveform, &mut timbres).map(|wavetype| {
+                        let instrument = Instrument {
+                            wavetype,
+                            patch: Patch::default(),
+                        };
+                        block = Some((n + 1, name.to_string(), instrument));
+                    }),
+                    _ => Err(SyntaxErrorType::BadInstrument(args.join(" "))),
+                },
+                Some(Directive::End) => Err(SyntaxErrorType::BadInstrument(line.to_string())),
+                Some(Directive::Mml) | None => notes.iter().try_for_each(|note| {
+                    // the notes that `MML` spells out are named in English
+                    let notation = match directive {
+                        Some(Directive::Mml) => Notation::English,
+                        _ => notation,
+                    };
+                    let signals = NoteSignal::parse(
+                        note,
+                        &key,
+                        notation,
+                        &voicing,
+                        &meter,
+                        &tracks[track].defaults,
+                        &mut timbres,
+                    )?;
+                    let mut transposed = vec![];
+                    for signal in signals {
+                        let mut signal = signal.transposed(transpose).ok_or_else(|| {
+                            SyntaxErrorType::BadOctave(signal.pitch.octave().to_string())
+                        })?;
+                        signal.duration = signal.duration.scaled(tuplet);
+                        patch.apply(&mut signal);
+                        // map the amplitude through the curve once, here,
+                        // rather than on every sample during synthesis
+                        signal.ampl = options.velocity_curve.apply(signal.ampl);
+                        signal.track = track;
+                        signal.humanize = humanize;
+                        transposed.push(signal);
+                    }
+                    entries.push((n + 1, Entry::Signals(transposed, arpeggio)));
+                    Ok(())
+                }),
+                Some(directive) => patch.set(directive, &args),
+            };
+            if let Err(e) = result {
+                return Err(MusicError::SyntaxError(n + 1, e));
+            }
+        }
+        if let Some((start, name, _)) = block {
+            return Err(MusicError::SyntaxError(
+                start,
+                SyntaxErrorType::UnclosedInstrument(name),
+            ));
+        }
+        if let Some((start, block)) = blocks.pop() {
+            let block = match block {
+                Block::Repeat => "REPEAT".to_string(),
+                Block::Pattern(name) => format!("PATTERN {}", name),
+                Block::Transpose(_) => "TRANSPOSE".to_string(),
+                Block::Tuplet(_) => "TUPLET".to_string(),
+            };
+            return Err(MusicError::SyntaxError(
+                start,
+                SyntaxErrorType::UnclosedBlock(block),
+            ));
+        }
+        let mut piece = Self::from_signals(
+            vec![],
+            bpm.unwrap_or(DEFAULT_BPM),
+            ampl.unwrap_or(DEFAULT_AMPL),
+        );
+        for (tick, bpm) in tempo_changes {
+            piece.tempo.set(tick, bpm);
+        }
+        // a ramp starts from whatever tempo the changes leave it at, so the
+        // ramps go in last, and in order
+        tempo_ramps.sort_by(|a: &(f64, f64, f64), b| a.0.total_cmp(&b.0));
+        for (from, to, bpm) in tempo_ramps {
+            piece.tempo.ramp(from, to, bpm);
+        }
+        if let Some(swing) = swing {
+            piece.tempo.swing(swing);
+        }
+        piece.click_guard = options.click_guard;
+        piece.max_duration = options.max_duration;
+        piece.channels = options.channels;
+        piece.band_limited = options.band_limited;
+        piece.timbres = timbres;
+        piece.tracks = tracks;
+        if options.reverb && !effects.iter().any(Effect::is_reverb) {
+            effects.push(DEFAULT_REVERB);
+        }
+        // a compressor from the command line takes the place of any the piece
+        // sets up itself
+        if let Some(compressor) = options.compressor {
+            effects.retain(|effect| !effect.is_compressor());
+            effects.push(compressor);
+        }
+        piece.effects = effects;
+        piece.fade_in = fade_in;
+        piece.fade_out = fade_out;
+        piece.sample_rate = sample_rate.map_or(DEFAULT_SAMPLE_RATE, |rate| rate.get());
+        piece.tuning = tuning.unwrap_or_default();
+        piece.tuning.reference = a4.unwrap_or(DEFAULT_A4);
+        piece.metadata = metadata;
+        // the entries are only resolved now, once the tempo and sample rate
+        // are known, so that jumps can shift notes given as sample offsets as
+        // well as tick counts, and so that notes can be checked against Nyquist
+        let mut markers = vec![];
+        piece.signals = piece.resolve(entries, &patterns, &mut markers)?;
+        piece.humanize(options.seed);
+        piece.markers = markers;
+        piece.loops = loops;
+        Ok(piece)
+    }
+
+    // play out the entries in order, replacing each `GOTO` with copies of the
+    // signals since its label, each copy starting where the previous one ends,
+    // and each `PLAY` with the signals of its pattern; the name and tick of
+    // each marker go into `markers`, but markers aren't repeated
+    fn resolve(
+        &self,
+        entries: Vec<(usize, Entry)>,
+        patterns: &HashMap<String, Vec<(usize, Entry)>>,
+        markers: &mut Vec<(String, f64)>,
+    ) -> Result<Vec<NoteSignal>, MusicError> {
+        let all_labels = entries
+            .iter()
+            .filter_map(|(_, entry)| match entry {
+                Entry::Label(name) => Some(name.clone()),
+                _ => None,
+            })
+            .collect::<HashSet<_>>();
+        let mut labels = HashMap::new();
+        let mut signals = vec![];
+        // where the last signal in each track ended, for those that follow on
+        let mut ends = HashMap::new();
+        // where each open `REPEAT` block started, and how many times it plays
+        let mut repeats = vec![];
+
+        for (line, entry) in entries {
+            match entry {
+                Entry::Signals(mut line_signals, arpeggio) => {
+                    // every note of a chord goes where its first note does,
+                    // and then any arpeggio delays each note by its place in
+                    // the chord, leaving them all to end together
+                    if let Some((first, rest)) = line_signals.split_first_mut() {
+                        self.place(first, &mut ends);
+                        rest.iter_mut()
+                            .for_each(|signal| signal.start = first.start);
+                    }
+                    let count = line_signals.len();
+                    for (k, signal) in line_signals.iter_mut().enumerate() {
+                        let delay = arpeggio.offset(k, count).min(signal.duration.ticks);
+                        if delay > 0.0 {
+                            *signal = self.shifted(signal, delay);
+                            signal.duration.ticks -= delay;
+                        }
+                    }
+                    for signal in line_signals.iter().flat_map(|s| self.ornamented(s)) {
+                        self.check_pitch(line, &signal)?;
+                        signals.push(signal)
+                    }
                 }
-            } else if line.starts_with("AMPL ") || line.starts_with("AMPLITUDE ") {
-                if ampl.is_none() {
-                    ampl = Some(
-                        match line.split_whitespace().nth(1).unwrap_or_default().parse() {
-                            Ok(n) => n,
-                            Err(_) => {
+                Entry::Play(name, start, semitones, track) => {
+                    // the parser makes sure the pattern is defined by now
+                    let pattern = patterns.get(&name).cloned().unwrap_or_default();
+                    let start = start.unwrap_or(ends.get(&track).copied().unwrap_or(0.0));
+                    let mut pattern_markers = vec![];
+                    let pattern = self.resolve(pattern, patterns, &mut pattern_markers)?;
+                    for (name, tick) in pattern_markers {
+                        markers.push((name, tick + start));
+                    }
+                    // patterns can play patterns, each doubling the last, so
+                    // check the size of this one before copying it in
+                    let end = pattern
+                        .iter()
+                        .map(|s| self.start_tick(s) + s.duration.ticks)
+                        .fold(0.0, f64::max);
+                    let total = signals.len() + pattern.len();
+                    self.check_expansion(total as f64, start + end)?;
+                    for signal in pattern {
+                        let mut signal = match signal.transposed(semitones) {
+                            Some(signal) => self.shifted(&signal, start),
+                            None => {
                                 return Err(MusicError::SyntaxError(
-                                    n + 1,
-                                    SyntaxErrorType::BadAmplitude(line[4..].to_string()),
+                                    line,
+                                    SyntaxErrorType::BadOctave(semitones.to_string()),
                                 ))
                             }
-                        },
-                    );
+                        };
+                        signal.track = track;
+                        self.check_pitch(line, &signal)?;
+                        let end = self.start_tick(&signal) + signal.duration.ticks;
+                        let track_end = ends.entry(track).or_insert(end);
+                        *track_end = track_end.max(end);
+                        signals.push(signal);
+                    }
+                }
+                // a marker that follows on goes where the next note in its
+                // track would start
+                Entry::Mark(name, tick, track) => {
+                    let tick = tick.unwrap_or(ends.get(&track).copied().unwrap_or(0.0));
+                    markers.push((name, tick));
+                }
+                Entry::Label(name) => {
+                    if labels.insert(name.clone(), signals.len()).is_some() {
+                        return Err(MusicError::SyntaxError(
+                            line,
+                            SyntaxErrorType::DuplicateLabel(name),
+                        ));
+                    }
+                }
+                Entry::Goto(name, count) => {
+                    let from = match labels.get(&name) {
+                        Some(&from) => from,
+                        None if all_labels.contains(&name) => {
+                            return Err(MusicError::SyntaxError(
+                                line,
+                                SyntaxErrorType::ForwardGoto(name),
+                            ))
+                        }
+                        None => {
+                            return Err(MusicError::SyntaxError(
+                                line,
+                                SyntaxErrorType::UndefinedLabel(name),
+                            ))
+                        }
+                    };
+                    self.replay(&mut signals, from, count, &mut ends)?;
                 }
-            } else {
-                signals.push(match NoteSignal::new(line) {
-                    Ok(sig) => sig,
-                    Err(e) => return Err(MusicError::SyntaxError(n + 1, e)),
-                });
+                Entry::Repeat(count) => repeats.push((signals.len(), count)),
+                // the parser makes sure that every block is closed
+                Entry::EndRepeat => {
+                    if let Some((from, count)) = repeats.pop() {
+                        self.replay(&mut signals, from, count - 1, &mut ends)?;
+                    }
+                }
+            }
+        }
+        Ok(signals)
+    }
+
+    // anything at or above the Nyquist frequency can't be represented at this
+    // sample rate, at either end of a bend
+    fn check_pitch(&self, line: usize, signal: &NoteSignal) -> Result<(), MusicError> {
+        for pitch in std::iter::once(signal.pitch).chain(signal.bend) {
+            if pitch.frequency(&self.tuning) >= self.sample_rate as f64 / 2.0 {
+                let e = match pitch {
+                    Pitch::Note(note) => SyntaxErrorType::BadOctave(note.octave.to_string()),
+                    Pitch::Frequency(freq) => SyntaxErrorType::BadFrequency(format!("{}Hz", freq)),
+                };
+                return Err(MusicError::SyntaxError(line, e));
             }
         }
-        Ok(Self {
+        Ok(())
+    }
+
+    // add `count` more copies of the signals from index `from` on, each copy
+    // starting where the previous one ends (measured from the earliest start
+    // to the latest end among them); `GOTO`, `REPEAT` and a repeated `PLAY`
+    // all come through here, so this is where copies that would take the
+    // piece past its length or note limit are refused, before making them
+    fn replay(
+        &self,
+        signals: &mut Vec<NoteSignal>,
+        from: usize,
+        count: u32,
+        ends: &mut HashMap<usize, f64>,
+    ) -> Result<(), MusicError> {
+        let section = signals[from..].to_vec();
+        if section.is_empty() {
+            return Ok(());
+        }
+        let first = section
+            .iter()
+            .map(|s| self.start_tick(s))
+            .fold(f64::INFINITY, f64::min);
+        let last = section
+            .iter()
+            .map(|s| self.start_tick(s) + s.duration.ticks)
+            .fold(f64::NEG_INFINITY, f64::max);
+        let total = signals.len() as f64 + count as f64 * section.len() as f64;
+        self.check_expansion(total, last + count as f64 * (last - first))?;
+        for k in 1..=count {
+            let offset = k as f64 * (last - first);
+            signals.extend(section.iter().map(|s| self.shifted(s, offset)));
+        }
+        // anything that follows on from the section follows on from its last
+        // copy
+        for signal in section.iter() {
+            let end =
+                self.start_tick(signal) + signal.duration.ticks + count as f64 * (last - first);
+            let track_end = ends.entry(signal.track).or_insert(end);
+            *track_end = track_end.max(end);
+        }
+        Ok(())
+    }
+
+    // refuse to go on expanding the piece into `count` signals that run on to
+    // the given tick, if that's more than it can have or longer than it can
+    // last
+    fn check_expansion(&self, count: f64, end: f64) -> Result<(), MusicError> {
+        if count > MAX_SIGNALS as f64 {
+            return Err(MusicError::TooManyNotes(count as usize, MAX_SIGNALS));
+        }
+        let rate = self.sample_rate;
+        let length = self.tempo.position(end, rate) / rate as f64;
+        if length > self.max_duration {
+            return Err(MusicError::TooLong(length, self.max_duration));
+        }
+        Ok(())
+    }
+
+    pub fn from_signals(signals: Vec<NoteSignal>, bpm: f64, ampl: u16) -> Self {
+        Self {
             signals,
-            bpm: bpm.unwrap_or(DEFAULT_BPM),
-            ampl: ampl.unwrap_or(DEFAULT_AMPL),
-        })
+            tempo: TempoMap::new(bpm),
+            ampl,
+            click_guard: DEFAULT_CLICK_GUARD,
+            max_duration: DEFAULT_MAX_DURATION,
+            channels: 1,
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            band_limited: false,
+            timbres: Timbres::default(),
+            tracks: vec![Track::default()],
+            effects: vec![],
+            fade_in: None,
+            fade_out: None,
+            markers: vec![],
+            loops: vec![],
+            tuning: Tuning::default(),
+            metadata: Metadata::default(),
+        }
+    }
+
+    // the text that the render is tagged with
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    // the name and sample offset of every marker, in order of where they fall
+    pub fn markers(&self) -> Vec<(String, u32)> {
+        let mut markers = self
+            .markers
+            .iter()
+            .map(|(name, tick)| (name.clone(), self.tick_to_sample(*tick)))
+            .collect::<Vec<_>>();
+        markers.sort_by_key(|&(_, sample)| sample);
+        markers
+    }
+
+    // the first and last sample frames of every loop, in the order given
+    pub fn loops(&self) -> Vec<(u32, u32)> {
+        self.loops
+            .iter()
+            .map(|&(start, end)| {
+                let end = self.tick_to_sample(end).saturating_sub(1);
+                (self.tick_to_sample(start), end)
+            })
+            .collect()
     }
 
-    // given a musical piece, produce a vector of bytes representing the 16-bit
-    // PCM encoding of the signal
-    pub fn synthesize(&self) -> Vec<u8> {
+    // every signal that sounds (leaving out rests), in order, with the
+    // (possibly fractional) ticks that it starts and stops sounding on, once
+    // any swing is played out; this is what exporting the piece needs, along
+    // with `tempo()` and `track_names()`
+    pub fn notes(&self) -> Vec<(f64, f64, &NoteSignal)> {
+        let mut notes = self
+            .signals
+            .iter()
+            .filter(|signal| signal.ampl > 0.0)
+            .map(|signal| {
+                let start = self.start_tick(signal);
+                let end = start + signal.sounding_ticks();
+                (self.tempo.swung(start), self.tempo.swung(end), signal)
+            })
+            .collect::<Vec<_>>();
+        notes.sort_by(|a, b| a.0.total_cmp(&b.0));
+        notes
+    }
+
+    // the waveform of a note as it could be written back into a score: its
+    // abbreviation, or else the name that `WAVEDEF` or `SAMPLE` gave one of the
+    // piece's own timbres (and failing that, a sine)
+    pub fn wave_name(&self, wavetype: &WaveType) -> String {
+        wavetype
+            .abbreviation()
+            .or_else(|| {
+                let same = |wave: &&WaveType| match (wave, wavetype) {
+                    (WaveType::Wavetable(a), WaveType::Wavetable(b))
+                    | (WaveType::Harmonics(a), WaveType::Harmonics(b))
+                    | (WaveType::Sample(a), WaveType::Sample(b)) => a == b,
+                    _ => false,
+                };
+                let names = self.timbres.waves.iter().filter(|(_, wave)| same(wave));
+                names.map(|(name, _)| name.clone()).min()
+            })
+            .unwrap_or_else(|| "S".to_string())
+    }
+
+    pub fn tempo(&self) -> &TempoMap {
+        &self.tempo
+    }
+
+    // the name of each track, in order (the first, which takes the notes
+    // before any `TRACK` line, has an empty name)
+    pub fn track_names(&self) -> Vec<&str> {
+        self.tracks
+            .iter()
+            .map(|track| track.name.as_str())
+            .collect()
+    }
+
+    // the speaker that each channel of the output feeds
+    fn layout(&self) -> &'static [u32] {
+        layout(self.channels).unwrap_or_default()
+    }
+
+    pub fn wav_format(&self) -> WavFormat {
+        WavFormat {
+            channels: self.channels,
+            sample_rate: self.sample_rate,
+            bits_per_sample: DEFAULT_BIT_DEPTH,
+            float: false,
+        }
+    }
+
+    // given a musical piece, produce the samples of the signal, with those for
+    // each channel interleaved: each track is rendered and run through its own
+    // effects, and then the tracks are mixed and run through the piece's
+    // effects; a sample of 1.0 is full scale, and samples are left unclamped
+    // so that `quantize()` can decide what to do with them
+    pub fn synthesize(&self) -> Result<Vec<f64>, MusicError> {
+        let rate = self.sample_rate;
+        // tempo-synced effects keep time with the tempo that the piece starts
+        // at, and a fade out with the one it ends at
+        let samples_per_tick = self.tempo.samples_per_tick(0.0, rate);
+
+        // refuse up front to render anything longer than the limit, rather
+        // than finding out after allocating the whole thing: each track runs
+        // on for the tails of its own effects, and the mix for those of the
+        // piece's
+        let tails = |effects: &[Effect]| {
+            effects.iter().fold(0usize, |total, effect| {
+                total.saturating_add(effect.tail(rate, samples_per_tick))
+            })
+        };
+        let mut ends = vec![None; self.tracks.len()];
+        for signal in self.signals.iter() {
+            let end = self.sample_end(signal);
+            let track_end = &mut ends[signal.track];
+            *track_end = Some(track_end.map_or(end, |e: f64| e.max(end)));
+        }
+        let frames = self
+            .tracks
+            .iter()
+            .zip(ends)
+            .filter_map(|(track, end)| Some(end? + tails(&track.effects) as f64))
+            .fold(0.0, f64::max)
+            + tails(&self.effects) as f64;
+        let length = frames / rate as f64;
+        if length > self.max_duration {
+            return Err(MusicError::TooLong(length, self.max_duration));
+        }
+
+        // a signal played in unison is split into as many voices as it asks
+        // for, and otherwise just gets the one
+        let mut voices = self
+            .signals
+            .iter()
+            .enumerate()
+            .flat_map(|(n, &signal)| {
+                let (start, held) = self.signal_span(&signal);
+                let span = (
+                    start,
+                    held,
+                    held.saturating_add(self.release_samples(&signal)),
+                );
+                let gains = signal.speakers.gains(signal.pan, self.layout());
+                let voices = signal.unison.map_or(1, |unison| unison.voices);
+                (0..voices).map(move |k| {
+                    let seed = (n as u64) << 32 | k as u64;
+                    Voice::new(signal, span, gains.clone(), k, seed, &self.tuning)
+                })
+            })
+            .collect::<Vec<_>>();
+        self.join_voices(&mut voices);
+
+        let channels = self.channels as usize;
+        let end = self
+            .signals
+            .iter()
+            .map(|s| self.start_tick(s) + s.duration.ticks)
+            .fold(0.0, f64::max);
+        let mut tracks = self
+            .tracks
+            .iter()
+            .map(|_| vec![])
+            .collect::<Vec<Vec<Voice>>>();
+        for voice in voices {
+            tracks[voice.signal.track].push(voice);
+        }
+
         let mut data = vec![];
-        let mut running_signals = self.signals.clone();
+        for (track, voices) in self.tracks.iter().zip(tracks) {
+            if voices.is_empty() {
+                continue;
+            }
+            let mut samples = self.render(voices, &track.gain);
+            for effect in track.effects.iter() {
+                effect.apply(&mut samples, channels, rate, samples_per_tick);
+            }
+            mix_into(&mut data, &samples);
+        }
+        for effect in self.effects.iter() {
+            effect.apply(&mut data, channels, rate, samples_per_tick);
+        }
+        let length = |fade: Option<FadeLength>, tick| {
+            fade.map_or(0, |f| {
+                f.samples(rate, self.tempo.samples_per_tick(tick, rate))
+            })
+        };
+        fade(
+            &mut data,
+            channels,
+            length(self.fade_in, 0.0),
+            length(self.fade_out, end),
+        );
+        Ok(data)
+    }
+
+    // join each voice onto the one before it in its track that it can carry
+    // straight on from (see `Voice::continues()`), if there is one that ends
+    // in the rhythm just as it starts and isn't cut short by an articulation;
+    // that one then stops where this one starts, rather than sounding on
+    // through the tick that it ends on or through any release
+    fn join_voices(&self, voices: &mut [Voice]) {
+        let mut ends: HashMap<(usize, u32), Vec<usize>> = HashMap::new();
+        for (k, voice) in voices.iter_mut().enumerate() {
+            voice.id = k;
+            let signal = &voice.signal;
+            if signal.gate >= 1.0 {
+                let end = self.tick_to_sample(self.start_tick(signal) + signal.duration.ticks);
+                ends.entry((signal.track, end)).or_default().push(k);
+            }
+        }
+        for k in 0..voices.len() {
+            let key = (voices[k].signal.track, voices[k].start);
+            let before = ends.get(&key).and_then(|candidates| {
+                candidates.iter().copied().find(|&j| {
+                    j != k && voices[j].carries_to.is_none() && voices[k].continues(&voices[j])
+                })
+            });
+            if let Some(j) = before {
+                voices[j].carries_to = Some(k);
+                voices[j].held = voices[k].start;
+                voices[j].end = voices[k].start;
+                voices[k].carried = true;
+            }
+        }
+    }
+
+    // the samples of the given voices superposed, from the start of the piece
+    // to the end of the last of them, with the gain of their track's lane
+    fn render(&self, mut running_signals: Vec<Voice>, gain: &Automation) -> Vec<f64> {
+        let mut data = vec![];
+        let rate = self.sample_rate;
+        let guard = (self.click_guard * rate as f64 / 1000.0).max(0.0);
+        // the oscillator state that each voice carrying on from another is
+        // waiting to pick up, by its place among the voices
+        let mut handoffs = HashMap::new();
         for sample in 0.. {
-            let mut acc = 0.0f64;
+            let mut acc = vec![0.0f64; self.channels as usize];
+            // only a lane that moves needs to know which tick this is
+            let track_gain = match gain.is_flat() {
+                true => 1.0,
+                false => gain.gain(self.tempo.tick(sample as f64, rate)),
+            };
             // at each point in time, we want to superpose all signals impinging
             // on that time
-            for &signal in running_signals.iter() {
+            for voice in running_signals.iter_mut() {
+                let (signal, start, end) = (voice.signal, voice.start, voice.end);
+                let held = voice.held;
                 // if a signal has started and it hasn't ended,
-                if signal.start + signal.duration.ticks >= self.sample_to_tick(sample)
-                    && signal.start <= self.sample_to_tick(sample)
-                {
-                    // add that signal to the running total
-                    acc += match signal.wavetype {
-                        WaveType::Sine => sine_wave(
-                            sample,
-                            signal.ampl * self.ampl as f64,
-                            signal.note.equal_tempered(),
-                        ),
-                        WaveType::Square => square_wave(
-                            sample,
-                            signal.ampl * self.ampl as f64,
-                            signal.note.equal_tempered(),
-                        ),
-                        WaveType::Triangle => triangle_wave(
-                            sample,
-                            signal.ampl * self.ampl as f64,
-                            signal.note.equal_tempered(),
-                        ),
-                        WaveType::Sawtooth => sawtooth_wave(
-                            sample,
-                            signal.ampl * self.ampl as f64,
-                            signal.note.equal_tempered(),
-                        ),
-                    };
+                if start <= sample && sample < end {
+                    let mut ampl = signal.ampl * self.ampl as f64 * track_gain;
+                    let t = (sample - start) as f64 / rate as f64;
+                    if let Some(tremolo) = signal.tremolo {
+                        ampl *= tremolo.gain(t);
+                    }
+                    if let Some(envelope) = signal.envelope {
+                        ampl *= envelope.ringing_gain(t, (held - start) as f64 / rate as f64);
+                    }
+                    // ramp the signal in and out over the guard at either end
+                    // of it, so that it doesn't switch on or off instantly,
+                    // unless it joins onto another voice there
+                    if guard > 0.0 {
+                        let fade_in = match voice.carried {
+                            true => 1.0,
+                            false => (sample - start) as f64 / guard,
+                        };
+                        let fade_out = match voice.carries_to {
+                            Some(_) => 1.0,
+                            None => (end - sample) as f64 / guard,
+                        };
+                        ampl *= fade_in.min(fade_out).min(1.0);
+                    }
+                    if sample == start {
+                        if let Some(state) = handoffs.remove(&voice.id) {
+                            voice.take_over(state);
+                        }
+                    }
+                    let value =
+                        voice.oscillate(sample, ampl, rate, self.band_limited, &self.timbres);
+                    if let (Some(next), true) = (voice.carries_to, sample + 1 == end) {
+                        handoffs.insert(next, voice.handoff());
+                    }
+                    // add that signal to the running total of each channel
+                    // that it sounds on (see `Speakers::gains()`)
+                    for (acc, gain) in acc.iter_mut().zip(&voice.gains) {
+                        *acc += value * gain;
+                    }
                 }
             }
-            // then, clamp the datum and convert to 16-bit integer
-            let height = if acc.round() > 32767.0 {
-                i16::MAX
-            } else if acc < -32768.0 {
-                i16::MIN
-            } else {
-                acc.round() as i16
-            };
-            // add datum to output
-            data.extend_from_slice(&height.to_le_bytes());
+            // the base amplitude is measured against 16-bit samples, so scale
+            // down by the 16-bit full scale and add each datum to the output
+            data.extend(acc.iter().map(|acc| acc / 32768.0));
 
             // remove all signals that have ended from consideration
-            running_signals = running_signals
-                .into_iter()
-                .filter(|&signal| {
-                    signal.start + signal.duration.ticks >= self.sample_to_tick(sample)
-                })
-                .collect();
+            running_signals.retain(|voice| sample < voice.end);
 
             // we're finished if there are no more signals
             if running_signals.is_empty() {
@@ -131,9 +1622,616 @@ impl MusicalPiece {
         data
     }
 
-    // if we're on the nth sample in the audio, what 64th-note beat of the piece
-    // are we in, given our tempo, if 0 marks the first such 64th-note beat?
-    fn sample_to_tick(&self, sample: u32) -> u32 {
-        ((sample as f64 / SAMPLE_RATE as f64) * 16.0 * self.bpm / 60.0).floor() as u32
+    // the loudest that the notes of the piece could possibly add up to on any
+    // channel (as a fraction of full scale), assuming that every note sounding
+    // at once peaks at once; this ignores anything that effects add
+    pub fn peak_amplitude(&self) -> f64 {
+        let mut events = vec![];
+        for signal in self.signals.iter() {
+            let (start, held) = self.signal_span(signal);
+            let end = held.saturating_add(self.release_samples(signal));
+            let tick = self.start_tick(signal);
+            let gain = self.tracks[signal.track]
+                .gain
+                .peak(tick, tick + signal.sounding_ticks());
+            let ampl = signal.ampl * self.ampl as f64 * gain / 32768.0;
+            let sides = signal.speakers.gains(signal.pan, self.layout());
+            events.push((
+                (start, true),
+                sides.iter().map(|side| ampl * side).collect::<Vec<_>>(),
+            ));
+            events.push((
+                (end, false),
+                sides.iter().map(|side| -ampl * side).collect(),
+            ));
+        }
+        // a signal has stopped sounding by the sample it ends on, so at any
+        // one sample the ends are counted before the starts
+        events.sort_by_key(|&(key, _)| key);
+        let mut running = vec![0.0; self.channels as usize];
+        let mut peak = 0.0f64;
+        for (_, changes) in events {
+            for (total, change) in running.iter_mut().zip(changes) {
+                *total += change;
+                peak = peak.max(*total);
+            }
+        }
+        peak
+    }
+
+    // given our tempo map, what is the first sample falling at or after the
+    // given (possibly fractional) 64th-note beat, if 0 marks the first such
+    // beat?
+    pub fn tick_to_sample(&self, tick: f64) -> u32 {
+        self.tempo.position(tick, self.sample_rate).ceil() as u32
+    }
+
+    // and the other way around, on which (possibly fractional) tick does the
+    // given sample fall?
+    pub fn sample_to_tick(&self, sample: u32) -> f64 {
+        self.tempo.tick(sample as f64, self.sample_rate)
+    }
+
+    // how many samples the given number of ticks lasts for, from `from` on
+    fn ticks_to_samples(&self, from: f64, ticks: f64) -> u32 {
+        self.tempo.length(from, ticks, self.sample_rate).ceil() as u32
+    }
+
+    // the (possibly fractional) tick on which a signal starts
+    fn start_tick(&self, signal: &NoteSignal) -> f64 {
+        match signal.start {
+            NoteStart::Ticks(start) => start,
+            NoteStart::Sample(start) => self.sample_to_tick(start),
+            NoteStart::Follow => 0.0,
+        }
+    }
+
+    // a copy of a signal moved later by the given number of ticks
+    fn shifted(&self, signal: &NoteSignal, ticks: f64) -> NoteSignal {
+        let mut signal = *signal;
+        signal.start = match signal.start {
+            NoteStart::Ticks(start) => NoteStart::Ticks(start + ticks),
+            NoteStart::Sample(start) => {
+                NoteStart::Sample(start + self.ticks_to_samples(self.sample_to_tick(start), ticks))
+            }
+            NoteStart::Follow => NoteStart::Follow,
+        };
+        signal
+    }
+
+    // nudge the start and amplitude of every humanized signal at random, once
+    // the piece is laid out, so that each copy of a repeated section is
+    // nudged differently; where the signals were placed isn't affected, so
+    // the nudges don't add up along a track
+    fn humanize(&mut self, seed: u64) {
+        let mut noise = Noise::new(seed);
+        for k in 0..self.signals.len() {
+            let signal = self.signals[k];
+            let humanize = signal.humanize;
+            if humanize.timing == 0.0 && humanize.ampl == 0.0 {
+                continue;
+            }
+            let (ticks, factor) = humanize.jitter(&mut noise);
+            let start = (self.start_tick(&signal) + ticks).max(0.0);
+            self.signals[k].start = NoteStart::Ticks(start);
+            self.signals[k].ampl *= factor;
+        }
+    }
+
+    // the notes that a signal is played as: just itself, unless it has an
+    // ornament, which takes the short notes around the main one out of its
+    // time; only the main note at the end keeps any bend and articulation
+    fn ornamented(&self, signal: &NoteSignal) -> Vec<NoteSignal> {
+        let Some(ornament) = signal.ornament else {
+            return vec![*signal];
+        };
+        let notes = ornament.expand(signal.pitch, signal.duration.ticks);
+        let last = notes.len() - 1;
+        notes
+            .into_iter()
+            .enumerate()
+            .map(|(k, (offset, ticks, pitch))| {
+                let mut note = self.shifted(signal, offset);
+                note.duration = NoteDuration { ticks };
+                note.pitch = pitch;
+                note.ornament = None;
+                if k < last {
+                    note.bend = None;
+                    note.gate = 1.0;
+                }
+                note
+            })
+            .collect()
+    }
+
+    // the range of samples [start, end) during which a signal sounds; note
+    // that a signal keeps sounding through the whole of the tick it ends on
+    fn signal_span(&self, signal: &NoteSignal) -> (u32, u32) {
+        let ticks = signal.sounding_ticks() + 1.0;
+        match signal.start {
+            NoteStart::Ticks(start) => (
+                self.tick_to_sample(start),
+                self.tick_to_sample(start + ticks),
+            ),
+            NoteStart::Sample(start) => (
+                start,
+                start.saturating_add(self.ticks_to_samples(self.sample_to_tick(start), ticks)),
+            ),
+            NoteStart::Follow => (0, self.ticks_to_samples(0.0, ticks)),
+        }
+    }
+
+    // the sample on which a signal falls silent, release and all, worked out
+    // as `signal_span` and `release_samples` do but without stopping at the
+    // largest u32, so that a piece far too long to render says how long it is
+    fn sample_end(&self, signal: &NoteSignal) -> f64 {
+        let rate = self.sample_rate;
+        let ticks = signal.sounding_ticks() + 1.0;
+        let held = match signal.start {
+            NoteStart::Ticks(start) => self.tempo.position(start + ticks, rate).ceil(),
+            NoteStart::Sample(start) => {
+                start as f64
+                    + self
+                        .tempo
+                        .length(self.sample_to_tick(start), ticks, rate)
+                        .ceil()
+            }
+            NoteStart::Follow => self.tempo.length(0.0, ticks, rate).ceil(),
+        };
+        held + signal
+            .envelope
+            .map_or(0.0, |envelope| (envelope.release * rate as f64).ceil())
+    }
+
+    // how many samples the release of a signal's envelope rings on for once
+    // the signal ends
+    fn release_samples(&self, signal: &NoteSignal) -> u32 {
+        signal.envelope.map_or(0, |envelope| {
+            (envelope.release * self.sample_rate as f64).ceil() as u32
+        })
+    }
+
+    // give a signal that follows on from the last one in its track the tick
+    // that the last one ended on (or 0, if it's the first), and then note down
+    // where this one ends in turn
+    fn place(&self, signal: &mut NoteSignal, ends: &mut HashMap<usize, f64>) {
+        if let NoteStart::Follow = signal.start {
+            signal.start = NoteStart::Ticks(ends.get(&signal.track).copied().unwrap_or(0.0));
+        }
+        ends.insert(
+            signal.track,
+            self.start_tick(signal) + signal.duration.ticks,
+        );
+    }
+}
+
+impl Directive {
+    pub fn find(keyword: &str) -> Option<Self> {
+        DIRECTIVES
+            .iter()
+            .find(|(k, _, _, _)| *k == keyword)
+            .map(|&(_, directive, _, _)| directive)
+    }
+
+    // whether the directive sets up part of a patch, and so can also appear
+    // in an `INSTRUMENT` block
+    pub fn shapes_notes(&self) -> bool {
+        matches!(
+            self,
+            Self::Envelope
+                | Self::Vibrato
+                | Self::Tremolo
+                | Self::Unison
+                | Self::Filter
+                | Self::FilterEnvelope
+        )
+    }
+}
+
+// parse the arguments of a `PLAY` directive, as the pattern's name, the tick
+// it starts on (if it doesn't follow on) and how far it is transposed
+fn parse_play<'a>(args: &[&'a str], meter: &Meter) -> Option<(&'a str, Option<f64>, i32)> {
+    let (&name, mut rest) = args.split_first()?;
+    let mut start = None;
+    if let ["@", at, more @ ..] = rest {
+        match NoteStart::new(at, meter) {
+            Ok(NoteStart::Ticks(tick)) => start = Some(tick),
+            _ => return None,
+        }
+        rest = more;
+    }
+    let semitones = match rest {
+        [] => 0,
+        [shift] => parse_shift(shift).ok()?,
+        _ => return None,
+    };
+    Some((name, start, semitones))
+}
+
+// parse a signed number of semitones to transpose by, such as `+5` or `-12`
+fn parse_shift(shift: &str) -> Result<i32, SyntaxErrorType> {
+    match shift.parse() {
+        Ok(semitones) if shift.starts_with(['+', '-']) || semitones == 0 => Ok(semitones),
+        _ => Err(SyntaxErrorType::BadTranspose(shift.to_string())),
+    }
+}
+
+// parse the arguments of a `RIT` or `ACCEL` directive, as the ticks that the
+// ramp runs between and the tempo it ends on
+fn parse_ramp(args: &[&str], meter: &Meter) -> Option<(f64, f64, f64)> {
+    let [from, to, bpm] = args[..] else {
+        return None;
+    };
+    let tick = |arg| match NoteStart::new(arg, meter) {
+        Ok(NoteStart::Ticks(tick)) => Some(tick),
+        _ => None,
+    };
+    let (from, to) = (tick(from)?, tick(to)?);
+    let bpm = bpm.parse::<f64>().ok()?;
+    if from >= to || !bpm.is_finite() || bpm <= 0.0 {
+        return None;
+    }
+    Some((from, to, bpm))
+}
+
+// parse the arguments of a `CRESC` or `DIM` directive, as the ticks that the
+// ramp runs between, the gain it starts from (if given) and the gain it ends on
+fn parse_swell(args: &[&str], meter: &Meter) -> Option<(f64, f64, Option<f64>, f64)> {
+    let (from, to, start, end) = match args[..] {
+        [from, to, end] => (from, to, None, end),
+        [from, to, start, end] => (from, to, Some(start), end),
+        _ => return None,
+    };
+    let tick = |arg| match NoteStart::new(arg, meter) {
+        Ok(NoteStart::Ticks(tick)) => Some(tick),
+        _ => None,
+    };
+    let level = |arg| parse_amplitude(arg).filter(|level| level.is_finite() && *level >= 0.0);
+    let (from, to) = (tick(from)?, tick(to)?);
+    let start = match start {
+        Some(start) => Some(level(start)?),
+        None => None,
+    };
+    let end = level(end)?;
+    if from >= to {
+        return None;
+    }
+    Some((from, to, start, end))
+}
+
+// parse the value of a directive line into `value`, unless it already holds
+// one; in strict mode, a directive seen twice, or one that disagrees with a
+// value given on the command line, is an error instead
+fn set_directive<T: FromStr + PartialEq>(
+    line: &str,
+    args: &[&str],
+    value: &mut Option<T>,
+    seen: &mut bool,
+    from_cli: bool,
+    strict: bool,
+    bad_value: fn(String) -> SyntaxErrorType,
+) -> Result<(), SyntaxErrorType> {
+    if value.is_some() && !strict {
+        return Ok(());
+    }
+    let arg = args.first().copied().unwrap_or_default();
+    let parsed = match arg.parse() {
+        Ok(v) => v,
+        Err(_) => return Err(bad_value(arg.to_string())),
+    };
+    if *seen {
+        return Err(SyntaxErrorType::DuplicateDirective(line.to_string()));
+    }
+    *seen = true;
+    match value {
+        Some(v) if from_cli && *v != parsed => {
+            Err(SyntaxErrorType::ConflictingDirective(line.to_string()))
+        }
+        Some(_) => Ok(()),
+        None => {
+            *value = Some(parsed);
+            Ok(())
+        }
+    }
+}
+
+impl PieceBuilder {
+    pub fn new() -> Self {
+        Self {
+            signals: vec![],
+            bpm: DEFAULT_BPM,
+            tempo_changes: vec![],
+            tempo_ramps: vec![],
+            swing: None,
+            markers: vec![],
+            loops: vec![],
+            ampl: DEFAULT_AMPL,
+            click_guard: DEFAULT_CLICK_GUARD,
+            max_duration: DEFAULT_MAX_DURATION,
+            channels: 1,
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            band_limited: false,
+            timbres: Timbres::default(),
+            tracks: vec![Track::default()],
+            track: 0,
+            effects: vec![],
+            fade_in: None,
+            fade_out: None,
+            tuning: Tuning::default(),
+        }
+    }
+
+    pub fn bpm(mut self, bpm: f64) -> Result<Self, MusicError> {
+        if !bpm.is_finite() || bpm <= 0.0 {
+            return Err(MusicError::BuildError(SyntaxErrorType::BadBPM(
+                bpm.to_string(),
+            )));
+        }
+        self.bpm = bpm;
+        Ok(self)
+    }
+
+    // change the tempo from the given tick on
+    pub fn tempo_change(mut self, tick: f64, bpm: f64) -> Result<Self, MusicError> {
+        if !tick.is_finite() || tick < 0.0 || !bpm.is_finite() || bpm <= 0.0 {
+            return Err(MusicError::BuildError(SyntaxErrorType::BadBPM(format!(
+                "{} @ {}",
+                bpm, tick
+            ))));
+        }
+        self.tempo_changes.push((tick, bpm));
+        Ok(self)
+    }
+
+    // move the tempo gradually from what it is at the tick `from` to `bpm` at
+    // the tick `to`
+    pub fn tempo_ramp(mut self, from: f64, to: f64, bpm: f64) -> Result<Self, MusicError> {
+        let ticks_ok = from.is_finite() && to.is_finite() && 0.0 <= from && from < to;
+        if !ticks_ok || !bpm.is_finite() || bpm <= 0.0 {
+            return Err(MusicError::BuildError(SyntaxErrorType::BadBPM(format!(
+                "{} {} {}",
+                from, to, bpm
+            ))));
+        }
+        self.tempo_ramps.push((from, to, bpm));
+        Ok(self)
+    }
+
+    // swing each pair of notes that together last `pair` ticks, the first
+    // taking up `ratio` of their time
+    pub fn swing(mut self, ratio: f64, pair: f64) -> Result<Self, MusicError> {
+        match Swing::new(ratio, pair) {
+            Some(swing) => {
+                self.swing = Some(swing);
+                Ok(self)
+            }
+            None => Err(MusicError::BuildError(SyntaxErrorType::BadSwing(format!(
+                "{} {}",
+                ratio, pair
+            )))),
+        }
+    }
+
+    // name the given tick, to be written into the output as a cue point
+    pub fn marker(mut self, name: &str, tick: f64) -> Result<Self, MusicError> {
+        if !tick.is_finite() || tick < 0.0 {
+            return Err(MusicError::BuildError(SyntaxErrorType::BadMark(format!(
+                "{} @ {}",
+                name, tick
+            ))));
+        }
+        self.markers.push((name.to_string(), tick));
+        Ok(self)
+    }
+
+    pub fn loop_points(mut self, start: f64, end: f64) -> Result<Self, MusicError> {
+        if !start.is_finite() || !end.is_finite() || start < 0.0 || start >= end {
+            return Err(MusicError::BuildError(SyntaxErrorType::BadLoop(format!(
+                "{} {}",
+                start, end
+            ))));
+        }
+        self.loops.push((start, end));
+        Ok(self)
+    }
+
+    pub fn amplitude(mut self, ampl: u16) -> Self {
+        self.ampl = ampl;
+        self
+    }
+
+    pub fn click_guard(mut self, ms: f64) -> Self {
+        self.click_guard = ms;
+        self
+    }
+
+    pub fn max_duration(mut self, seconds: f64) -> Self {
+        self.max_duration = seconds;
+        self
+    }
+
+    pub fn band_limited(mut self, band_limited: bool) -> Self {
+        self.band_limited = band_limited;
+        self
+    }
+
+    pub fn sample_rate(mut self, rate: u32) -> Result<Self, MusicError> {
+        if rate == 0 {
+            return Err(MusicError::BuildError(SyntaxErrorType::BadSampleRate(
+                rate.to_string(),
+            )));
+        }
+        self.sample_rate = rate;
+        Ok(self)
+    }
+
+    pub fn channels(mut self, channels: u16) -> Result<Self, MusicError> {
+        if layout(channels).is_none() {
+            return Err(MusicError::BuildError(SyntaxErrorType::BadChannels(
+                channels.to_string(),
+            )));
+        }
+        self.channels = channels;
+        Ok(self)
+    }
+
+    // add a single cycle of a custom waveform, returning the builder along
+    // with the waveform that plays it
+    pub fn wavetable(mut self, samples: Vec<f64>) -> Result<(Self, WaveType), MusicError> {
+        let len = samples.len();
+        match self.timbres.wavetables.add(samples) {
+            Some(index) => Ok((self, WaveType::Wavetable(index))),
+            None => Err(MusicError::BuildError(SyntaxErrorType::BadWavetable(
+                format!("table of {} samples", len),
+            ))),
+        }
+    }
+
+    // add an additive timbre with the given harmonic amplitudes, returning
+    // the builder along with the waveform that plays it
+    pub fn harmonics(mut self, amplitudes: Vec<f64>) -> Result<(Self, WaveType), MusicError> {
+        let text = format!("{:?}", amplitudes);
+        match self.timbres.harmonics.add(amplitudes) {
+            Some(index) => Ok((self, WaveType::Harmonics(index))),
+            None => Err(MusicError::BuildError(SyntaxErrorType::BadHarmonics(text))),
+        }
+    }
+
+    // add an effect to run over the whole piece, after any added before it
+    pub fn effect(mut self, effect: Effect) -> Self {
+        self.effects.push(effect);
+        self
+    }
+
+    // put the notes added from now on in the named track (adding it if need
+    // be), and give that track the given chain of effects
+    pub fn track(mut self, name: &str, effects: Vec<Effect>) -> Self {
+        self.track = find_track(&mut self.tracks, name);
+        self.tracks[self.track].effects = effects;
+        self
+    }
+
+    pub fn fade_in(mut self, length: FadeLength) -> Self {
+        self.fade_in = Some(length);
+        self
+    }
+
+    pub fn fade_out(mut self, length: FadeLength) -> Self {
+        self.fade_out = Some(length);
+        self
+    }
+
+    pub fn tuning(mut self, tuning: Tuning) -> Self {
+        self.tuning = tuning;
+        self
+    }
+
+    // add a note, or a frequency given as a `Pitch`
+    pub fn add_note(
+        mut self,
+        start: NoteStart,
+        duration: NoteDuration,
+        pitch: impl Into<Pitch>,
+        ampl: f64,
+        wavetype: WaveType,
+    ) -> Result<Self, MusicError> {
+        if let NoteStart::Ticks(ticks) = start {
+            if !ticks.is_finite() || ticks < 0.0 {
+                return Err(MusicError::BuildError(SyntaxErrorType::BadStartTime(
+                    ticks.to_string(),
+                )));
+            }
+        }
+        if !ampl.is_finite() {
+            return Err(MusicError::BuildError(SyntaxErrorType::BadAmplitude(
+                ampl.to_string(),
+            )));
+        }
+        self.signals.push(NoteSignal {
+            start,
+            duration,
+            gate: 1.0,
+            pitch: pitch.into(),
+            bend: None,
+            ornament: None,
+            humanize: Humanize::default(),
+            ampl,
+            wavetype,
+            pan: 0.0,
+            speakers: Speakers::default(),
+            tremolo: None,
+            envelope: None,
+            vibrato: None,
+            unison: None,
+            filter: None,
+            filter_envelope: None,
+            track: self.track,
+        });
+        Ok(self)
+    }
+
+    pub fn build(self) -> MusicalPiece {
+        let mut piece = MusicalPiece::from_signals(vec![], self.bpm, self.ampl);
+        for (tick, bpm) in self.tempo_changes {
+            piece.tempo.set(tick, bpm);
+        }
+        let mut ramps = self.tempo_ramps;
+        ramps.sort_by(|a, b| a.0.total_cmp(&b.0));
+        for (from, to, bpm) in ramps {
+            piece.tempo.ramp(from, to, bpm);
+        }
+        if let Some(swing) = self.swing {
+            piece.tempo.swing(swing);
+        }
+        piece.click_guard = self.click_guard;
+        piece.max_duration = self.max_duration;
+        piece.channels = self.channels;
+        piece.sample_rate = self.sample_rate;
+        piece.band_limited = self.band_limited;
+        piece.timbres = self.timbres;
+        piece.tracks = self.tracks;
+        piece.effects = self.effects;
+        piece.fade_in = self.fade_in;
+        piece.fade_out = self.fade_out;
+        piece.markers = self.markers;
+        piece.loops = self.loops;
+        piece.tuning = self.tuning;
+        let mut ends = HashMap::new();
+        let mut signals = self.signals;
+        for signal in signals.iter_mut() {
+            piece.place(signal, &mut ends);
+        }
+        piece.signals = signals;
+        piece
+    }
+}
+
+impl Default for PieceOptions {
+    fn default() -> Self {
+        Self {
+            bpm: None,
+            ampl: None,
+            velocity_curve: VelocityCurve::default(),
+            strict: false,
+            click_guard: DEFAULT_CLICK_GUARD,
+            max_duration: DEFAULT_MAX_DURATION,
+            channels: 1,
+            sample_rate: None,
+            band_limited: false,
+            reverb: false,
+            fade_in: None,
+            fade_out: None,
+            compressor: None,
+            tuning: None,
+            a4: None,
+            seed: 0,
+            metadata: Metadata::default(),
+        }
+    }
+}
+
+impl Default for PieceBuilder {
+    fn default() -> Self {
+        Self::new()
     }
 }
@@ -0,0 +1,112 @@
+//! This module renders a plain-text reference of every token the parser
+//! understands. It reads the same tables that the parser matches against, so
+//! the two can't drift apart.
+
+use crate::chord::QUALITIES;
+use crate::instrument::PRESETS;
+use crate::note::ACCIDENTALS;
+use crate::piece::DIRECTIVES;
+use crate::signal::{ARTICULATIONS, DEFAULTS, DURATIONS, DYNAMICS, MODIFIERS, WAVEFORMS};
+use crate::speakers::{LAYOUTS, SPEAKERS};
+
+// how wide the column of usages is, in the sections that spell them out
+const USAGE_WIDTH: usize = 32;
+
+// a line of the reference giving a usage and what it means, lined up with
+// the others; a usage too long for its column gets a line to itself, with
+// what it means lined up on the next
+fn row(usage: &str, meaning: &str) -> String {
+    if usage.chars().count() < USAGE_WIDTH {
+        format!("  {:<width$}{}\n", usage, meaning, width = USAGE_WIDTH)
+    } else {
+        format!(
+            "  {}\n  {:<width$}{}\n",
+            usage,
+            "",
+            meaning,
+            width = USAGE_WIDTH
+        )
+    }
+}
+
+pub fn token_reference() -> String {
+    let mut output = String::from("Durations:\n");
+    for (abbr, name, ticks) in DURATIONS {
+        output += &format!("  {:<12}{:<16}{} ticks\n", abbr, name, ticks);
+    }
+    output += &format!("  {:<12}{}\n", "<n>/<d>", "fraction of a whole note");
+    output += &format!("  {:<12}{}\n", "<n>", "number of ticks");
+    output += "\nArticulations (after a duration, e.g. Q.):\n";
+    for (suffix, name, gate) in ARTICULATIONS {
+        output += &format!("  {:<12}{:<16}sounds for {}%\n", suffix, name, gate * 100.0);
+    }
+
+    output += "\nWaveforms:\n";
+    for (abbr, _, name) in WAVEFORMS {
+        output += &format!("  {:<12}{}\n", abbr, name);
+    }
+    output += &format!(
+        "  {:<12}{}\n",
+        "I:<name>", "instrument from INSTRUMENT <name>"
+    );
+    output += &format!("  {:<12}{}\n", "<name>", "waveform from WAVEDEF <name>");
+    output += "\nPreset instruments (played by name, or as I:<name>):\n ";
+    for (name, _, _, _) in PRESETS {
+        output += &format!(" {}", name);
+    }
+    output += "\n";
+
+    output += "\nPitch classes (followed by an octave, e.g. C#4):\n  C D E F G A B\n";
+    output += "  a bare letter takes its accidental from the key, unless followed by one of:\n";
+    for (accidental, _, name) in ACCIDENTALS {
+        output += &format!("  {:<12}{}\n", accidental, name);
+    }
+    output += "  two notes joined by `>` (e.g. C4>E4) bend from the first to the second\n";
+    output += "  R in place of a note is a rest\n";
+
+    output += "\nDynamics (in place of an amplitude, or in CRESC and DIM):\n";
+    for (name, ampl) in DYNAMICS {
+        output += &format!("  {:<12}{:.3}\n", name, ampl);
+    }
+
+    output += "\nChord qualities (after a root, e.g. Cmaj7, Am:3 or G7/B):\n ";
+    for (name, _) in QUALITIES {
+        output += &format!(" {}", if name.is_empty() { "(major)" } else { name });
+    }
+    output += "\n";
+
+    output += "\nDirectives:\n";
+    for (keyword, _, args, meaning) in DIRECTIVES {
+        output += &row(&format!("{} {}", keyword, args), meaning);
+    }
+
+    output += "\nTrack defaults (after TRACK <name>, as e.g. OCTAVE:3, or in DEFAULT):\n";
+    for (name, value, meaning) in DEFAULTS {
+        output += &row(&format!("{}:{}", name, value), meaning);
+    }
+
+    output += "\nNote modifiers (after the fifth column):\n";
+    for (name, _, args, meaning) in MODIFIERS {
+        let usage = match args {
+            "" => name.to_string(),
+            args if args.starts_with('[') => format!("{}[:{}", name, &args[1..]),
+            args => format!("{}:{}", name, args),
+        };
+        output += &row(&usage, meaning);
+    }
+
+    output += "\nSpeakers (for OUT):\n";
+    for (name, _, meaning) in SPEAKERS {
+        output += &format!("  {:<12}{}\n", name, meaning);
+    }
+    output += "\nChannel counts (for --channels):\n";
+    for (channels, layout, name) in LAYOUTS {
+        let speakers = layout
+            .iter()
+            .filter_map(|bit| SPEAKERS.iter().find(|(_, b, _)| b == bit))
+            .map(|(name, _, _)| *name)
+            .collect::<Vec<_>>();
+        output += &format!("  {:<12}{:<16}{}\n", channels, name, speakers.join(" "));
+    }
+    output
+}
@@ -0,0 +1,72 @@
+//! This module defines sampled sounds: recordings loaded from WAV files with
+//! the `SAMPLE` directive and played back by name. A sample is repitched by
+//! resampling it, so that a note at its root pitch plays it at its own speed,
+//! and a note an octave up plays it twice as fast.
+
+use crate::error::SyntaxErrorType;
+use crate::note::Note;
+use crate::wavetable::read_wav;
+
+use std::collections::HashMap;
+
+// a recording, scaled so that its peak is at full amplitude, along with the
+// rate it was recorded at and the frequency of the note it sounds at
+struct Clip {
+    samples: Vec<f64>,
+    rate: u32,
+    root: f64,
+}
+
+// every sample loaded so far, so that a waveform can refer to its sample by
+// index; each file is only loaded once for a given root note
+#[derive(Default)]
+pub struct Sampler {
+    files: HashMap<(String, String), usize>,
+    clips: Vec<Clip>,
+}
+
+impl Sampler {
+    // load the sample in the given file (if it hasn't been already), returning
+    // its index
+    pub fn load(&mut self, file: &str, root: &str) -> Result<usize, SyntaxErrorType> {
+        let key = (file.to_string(), root.to_string());
+        if let Some(&index) = self.files.get(&key) {
+            return Ok(index);
+        }
+        let bad = || SyntaxErrorType::BadSample(file.into());
+        let root = Note::new(root)?.equal_tempered();
+        let bytes = std::fs::read(file).map_err(|_| bad())?;
+        let (mut samples, rate) = read_wav(&bytes).ok_or_else(bad)?;
+        let peak = samples.iter().fold(0.0f64, |peak, s| peak.max(s.abs()));
+        if peak == 0.0 || !peak.is_finite() || rate == 0 {
+            return Err(bad());
+        }
+        samples.iter_mut().for_each(|s| *s /= peak);
+        self.clips.push(Clip {
+            samples,
+            rate,
+            root,
+        });
+        self.files.insert(key, self.clips.len() - 1);
+        Ok(self.clips.len() - 1)
+    }
+
+    // the value of a sample at the given (fractional) position in its own
+    // samples, interpolating linearly; it falls silent once it has run out
+    pub fn sample(&self, index: usize, position: f64) -> f64 {
+        let samples = &self.clips[index].samples;
+        let i = position.floor() as usize;
+        match (samples.get(i), samples.get(i + 1)) {
+            (Some(a), Some(b)) => a + (b - a) * position.fract(),
+            (Some(a), None) => *a,
+            _ => 0.0,
+        }
+    }
+
+    // how far through a sample to move on each output sample, to play it at
+    // `freq` Hz at the given output rate
+    pub fn step(&self, index: usize, freq: f64, rate: u32) -> f64 {
+        let clip = &self.clips[index];
+        freq / clip.root * clip.rate as f64 / rate as f64
+    }
+}
@@ -1,105 +1,877 @@
 //! This module defines NoteSignal, a struct corresponding to a single row in
-//! an input file---effectively, a single note on a music score. It also defines
+//! an input file---effectively, a single note on a music score (a row naming a
+//! chord is several signals, one per note). It also defines
 //! a couple of auxiliary types that are wrappers for parsing the duration and
 //! wave type of such a signal.
 
+use crate::chord::{chord_notes, Voicing};
+use crate::envelope::Envelope;
 use crate::error::SyntaxErrorType;
-use crate::note::Note;
+use crate::filter::{Filter, FilterEnvelope};
+use crate::humanize::Humanize;
+use crate::instrument::PRESETS;
+use crate::mastering::db_to_gain;
+use crate::meter::{parse_ticks, scale_ticks, Meter};
+use crate::note::{KeySignature, Notation, Note, Pitch, PitchClass};
+use crate::ornament::Ornament;
+use crate::speakers::Speakers;
+use crate::timbre::Timbres;
 
-#[derive(Clone, Copy)]
+use std::f64::consts::PI;
+
+#[derive(Clone, Copy, PartialEq)]
 pub enum WaveType {
     Sine,
     Square,
     Triangle,
     Sawtooth,
+    WhiteNoise,
+    PinkNoise,
+    BrownNoise,
+    Fm { ratio: f64, index: f64 },
+    RingMod { ratio: f64 },
+    Wavetable(usize),
+    Harmonics(usize),
+    Pluck,
+    Kick,
+    Snare,
+    HiHat,
+    // a sample loaded by a `SAMPLE` directive, which is only ever played by
+    // name, so has no abbreviation of its own
+    Sample(usize),
+}
+
+// every waveform abbreviation, with the name of the waveform it stands for
+pub const WAVEFORMS: [(&str, WaveType, &str); 15] = [
+    ("S", WaveType::Sine, "sine"),
+    ("Q", WaveType::Square, "square"),
+    ("T", WaveType::Triangle, "triangle"),
+    ("A", WaveType::Sawtooth, "sawtooth"),
+    ("N", WaveType::WhiteNoise, "white noise (ignores pitch)"),
+    ("PN", WaveType::PinkNoise, "pink noise (ignores pitch)"),
+    ("BN", WaveType::BrownNoise, "brown noise (ignores pitch)"),
+    ("P", WaveType::Pluck, "plucked string (Karplus-Strong)"),
+    (
+        "BD",
+        WaveType::Kick,
+        "kick drum, a sine swept down onto the pitch",
+    ),
+    (
+        "SD",
+        WaveType::Snare,
+        "snare drum, a tone at the pitch plus noise",
+    ),
+    (
+        "HH",
+        WaveType::HiHat,
+        "hi-hat, high-passed noise (ignores pitch)",
+    ),
+    (
+        "FM",
+        WaveType::Fm {
+            ratio: 1.0,
+            index: 1.0,
+        },
+        "two-operator FM; FM:<ratio>,<index> sets the modulator",
+    ),
+    (
+        "RM",
+        WaveType::RingMod { ratio: 1.5 },
+        "ring modulation of two sines; RM:<ratio> sets the modulator",
+    ),
+    (
+        "W",
+        WaveType::Wavetable(0),
+        "wavetable; W:<file> plays the single cycle in <file>",
+    ),
+    (
+        "H",
+        WaveType::Harmonics(0),
+        "additive; H:<name> plays the series from HARMONICS <name>",
+    ),
+];
+
+// how a note's amplitude column is mapped onto the amplitude actually used;
+// linear leaves it alone, while the other two bend it toward perceived loudness
+#[derive(Clone, Copy, Default, clap::ValueEnum)]
+pub enum VelocityCurve {
+    #[default]
+    Linear,
+    Exp,
+    Sqrt,
+}
+
+// a signal's start is either a tick count, which may be fractional (and may be
+// written as `measure:beat:tick`), an exact sample offset (written with a
+// leading `@`), or `+` to start just as the
+// signal before it in the same track ends; a piece replaces each `+` with the
+// tick that this works out to as it resolves its signals
+#[derive(Clone, Copy)]
+pub enum NoteStart {
+    Ticks(f64),
+    Sample(u32),
+    Follow,
 }
 
 // duration is stored in "ticks", with 1 tick having the same duration as a
-// 64th note, assuming a quarter note is 1 beat
+// 64th note, assuming a quarter note is 1 beat; like a start, it may be
+// fractional, so that tuplets (a quarter-note triplet is 32/3 ticks) come out
+// exactly
 #[derive(Clone, Copy)]
 pub struct NoteDuration {
-    pub ticks: u32,
+    pub ticks: f64,
+}
+
+// every duration abbreviation, with its name and its length in ticks
+pub const DURATIONS: [(&str, &str, u32); 11] = [
+    ("TS", "32nd", 2),
+    ("DTS", "dotted 32nd", 3),
+    ("S", "16th", 4),
+    ("DS", "dotted 16th", 6),
+    ("E", "8th", 8),
+    ("DE", "dotted 8th", 12),
+    ("Q", "quarter", 16),
+    ("DQ", "dotted quarter", 24),
+    ("H", "half", 32),
+    ("DH", "dotted half", 48),
+    ("W", "whole", 64),
+];
+
+// every articulation that can follow a note's duration (e.g. `Q.`), with its
+// name and the fraction of the duration that the note then sounds for; the
+// rhythm is left alone, so a note played staccato still ends where it would
+// have for anything that follows on from it
+pub const ARTICULATIONS: [(char, &str, f64); 2] = [('.', "staccato", 0.5), ('_', "legato", 1.05)];
+
+// every dynamic marking that can be written in place of a note's amplitude,
+// with the amplitude that it stands for (the velocity that sequencers tend to
+// give it, out of 127)
+pub const DYNAMICS: [(&str, f64); 8] = [
+    ("ppp", 16.0 / 127.0),
+    ("pp", 33.0 / 127.0),
+    ("p", 49.0 / 127.0),
+    ("mp", 64.0 / 127.0),
+    ("mf", 80.0 / 127.0),
+    ("f", 96.0 / 127.0),
+    ("ff", 112.0 / 127.0),
+    ("fff", 1.0),
+];
+
+// parse an amplitude, written either as a number, as a dynamic marking, or as
+// a level in decibels (e.g. `-6dB`), relative to 1.0
+pub fn parse_amplitude(input: &str) -> Option<f64> {
+    if let Some(db) = input.strip_suffix("dB") {
+        return db
+            .parse::<f64>()
+            .ok()
+            .filter(|db| db.is_finite())
+            .map(db_to_gain);
+    }
+    match DYNAMICS.iter().find(|(name, _)| *name == input) {
+        Some(&(_, ampl)) => Some(ampl),
+        None => input.parse().ok(),
+    }
+}
+
+// parse the base amplitude of a piece, written either as a number of the
+// 32767 steps of a 16-bit sample, or as a level in decibels relative to full
+// scale (e.g. `-6dB`), which can't be above 0
+pub fn parse_base_amplitude(input: &str) -> Option<u16> {
+    match input.strip_suffix("dB") {
+        Some(db) => {
+            let db = db.parse::<f64>().ok().filter(|db| *db <= 0.0)?;
+            Some((i16::MAX as f64 * db_to_gain(db)).round() as u16)
+        }
+        None => input.parse().ok(),
+    }
+}
+
+// what a note line leaves out is filled in from its track: a missing waveform
+// or amplitude column (which can only be left off the end of the line), or a
+// pan or speakers, or the octave of a note written as a bare pitch class
+// (e.g. `F#`)
+#[derive(Clone, Default)]
+pub struct Defaults {
+    pub wave: Option<String>,
+    pub ampl: Option<f64>,
+    pub octave: Option<i32>,
+    pub pan: Option<f64>,
+    pub out: Option<Speakers>,
+}
+
+// every setting that a track's defaults can be given, with its value and what
+// it fills in
+pub const DEFAULTS: [(&str, &str, &str); 5] = [
+    ("WAVE", "<waveform>", "waveform of notes without one"),
+    ("AMPL", "<ampl|dynamic>", "amplitude of notes without one"),
+    ("OCTAVE", "<octave>", "octave of notes written without one"),
+    ("PAN", "<pan>", "pan of notes without one"),
+    (
+        "OUT",
+        "<speaker>[,<speaker>...]",
+        "speakers of notes without any",
+    ),
+];
+
+impl Defaults {
+    // set one of the defaults by name, as in `AMPL` and `0.8`
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), SyntaxErrorType> {
+        let bad = || SyntaxErrorType::BadDefault(format!("{} {}", name, value));
+        match name {
+            "WAVE" => self.wave = Some(value.to_string()),
+            "AMPL" => self.ampl = Some(parse_amplitude(value).ok_or_else(bad)?),
+            "OCTAVE" => self.octave = Some(value.parse::<i8>().map_err(|_| bad())? as i32),
+            "PAN" => match value.parse::<f64>() {
+                Ok(pan) if (-1.0..=1.0).contains(&pan) => self.pan = Some(pan),
+                _ => return Err(bad()),
+            },
+            "OUT" => self.out = Some(Speakers::new(value).map_err(|_| bad())?),
+            _ => return Err(bad()),
+        }
+        Ok(())
+    }
+
+    // a pitch, in which a note written as a bare pitch class (as in `F#`, but
+    // not `F#4`) is in the default octave, if there is one
+    fn pitch(
+        &self,
+        input: &str,
+        key: &KeySignature,
+        notation: Notation,
+    ) -> Result<Pitch, SyntaxErrorType> {
+        Pitch::new(input, self.octave, key, notation)
+    }
+}
+
+// the optional NAME:ARGS modifiers that can follow the five columns of a note
+#[derive(Clone, Copy)]
+pub enum Modifier {
+    Tremolo,
+    Envelope,
+    Vibrato,
+    Unison,
+    Filter,
+    Trill,
+    Mordent,
+    Turn,
+    Out,
+}
+
+// every modifier name, with the arguments it takes and what it does
+pub const MODIFIERS: [(&str, Modifier, &str, &str); 9] = [
+    (
+        "TREM",
+        Modifier::Tremolo,
+        "<rate>,<depth>",
+        "tremolo at <rate> Hz, dipping to 1 - <depth> of the amplitude",
+    ),
+    (
+        "ENV",
+        Modifier::Envelope,
+        "<a>,<d>,<s>,<r>",
+        "ADSR envelope for this note, overriding any ENV directive",
+    ),
+    (
+        "VIB",
+        Modifier::Vibrato,
+        "<rate>,<depth>",
+        "vibrato at <rate> Hz, <depth> cents either way, overriding any VIB directive",
+    ),
+    (
+        "UNISON",
+        Modifier::Unison,
+        "<voices>,<detune>",
+        "play <voices> copies spread over <detune> cents either way",
+    ),
+    (
+        "FILTER",
+        Modifier::Filter,
+        "<LP|HP>,<cutoff>,<q>",
+        "low- or high-pass filter, overriding any FILTER directive",
+    ),
+    (
+        "TR",
+        Modifier::Trill,
+        "",
+        "trill with the note a step of the key above, in 32nds",
+    ),
+    (
+        "MORD",
+        Modifier::Mordent,
+        "[lower]",
+        "mordent to the note a step above (or below) and back",
+    ),
+    (
+        "TURN",
+        Modifier::Turn,
+        "",
+        "turn: the notes above, on, below and on the note, in equal parts",
+    ),
+    (
+        "OUT",
+        Modifier::Out,
+        "<speaker>[,<speaker>...]",
+        "play the note on just these speakers, ignoring its pan",
+    ),
+];
+
+// a tremolo wobbles a signal's amplitude at `rate` Hz, dipping as far down as
+// `1 - depth` times its usual amplitude
+#[derive(Clone, Copy)]
+pub struct Tremolo {
+    pub rate: f64,
+    pub depth: f64,
 }
 
-// a "note signal" is a pitch, a start, a duration, an amplitude, and a waveform
+// a vibrato wobbles a signal's pitch at `rate` Hz, as far as `depth` cents
+// above and below the note
+#[derive(Clone, Copy)]
+pub struct Vibrato {
+    pub rate: f64,
+    pub depth: f64,
+}
+
+// a unison plays a signal as several voices at once (up to MAX_UNISON), with
+// their pitches spread evenly from `detune` cents below the note to `detune`
+// cents above it
+#[derive(Clone, Copy)]
+pub struct Unison {
+    pub voices: u32,
+    pub detune: f64,
+}
+
+pub const MAX_UNISON: u32 = 16;
+
+// a "note signal" is a pitch, a start, a duration, an amplitude, and a waveform,
+// plus an optional stereo position (-1.0 for hard left up to 1.0 for hard
+// right) and any optional modifiers given after those columns; a pitch written
+// as e.g. `C4>E4` bends from the first note to the second (`bend`) over the
+// course of the signal; `track` is the index of the track it is mixed in;
+// `gate` is how much of its duration it actually sounds for, which is all of
+// it unless an articulation says otherwise; `ornament` is any grace note,
+// trill, mordent or turn that the note is played with, and `humanize` how far
+// its start and amplitude may be nudged at random; `speakers` are the ones
+// that it's sent to when rendering for more than one, if any
 #[derive(Clone, Copy)]
 pub struct NoteSignal {
-    pub start: u32,
+    pub start: NoteStart,
     pub duration: NoteDuration,
-    pub note: Note,
+    pub gate: f64,
+    pub pitch: Pitch,
+    pub bend: Option<Pitch>,
+    pub ornament: Option<Ornament>,
+    pub humanize: Humanize,
     pub ampl: f64,
     pub wavetype: WaveType,
+    pub pan: f64,
+    pub speakers: Speakers,
+    pub tremolo: Option<Tremolo>,
+    pub envelope: Option<Envelope>,
+    pub vibrato: Option<Vibrato>,
+    pub unison: Option<Unison>,
+    pub filter: Option<Filter>,
+    pub filter_envelope: Option<FilterEnvelope>,
+    pub track: usize,
 }
 
 impl WaveType {
-    pub fn new(input: &str) -> Result<Self, SyntaxErrorType> {
-        match input {
-            "S" => Ok(Self::Sine),
-            "Q" => Ok(Self::Square),
-            "T" => Ok(Self::Triangle),
-            "A" => Ok(Self::Sawtooth),
-            _ => Err(SyntaxErrorType::BadWaveform(input.to_string())),
+    // a waveform is an abbreviation, which for FM may be followed by
+    // `:<ratio>,<index>` to set the modulator's frequency ratio to the carrier
+    // and its modulation index, for ring modulation may be followed by
+    // `:<ratio>` to set the same ratio, for a wavetable must be followed by `:<file>`
+    // to name the file that the table is loaded from, and for an additive
+    // timbre must be followed by `:<name>` to name a `HARMONICS` directive;
+    // a waveform set up with `WAVEDEF` is written as its name alone
+    pub fn new(input: &str, timbres: &mut Timbres) -> Result<Self, SyntaxErrorType> {
+        let bad = || SyntaxErrorType::BadWaveform(input.to_string());
+        let (abbr, args) = match input.split_once(':') {
+            Some((abbr, args)) => (abbr, Some(args)),
+            None => (input, None),
+        };
+        let wavetype = match WAVEFORMS.iter().find(|(a, _, _)| *a == abbr) {
+            Some(&(_, wavetype, _)) => wavetype,
+            None => return timbres.waves.get(input).copied().ok_or_else(bad),
+        };
+        match (wavetype, args) {
+            (Self::Wavetable(_), Some(file)) => Ok(Self::Wavetable(timbres.wavetables.load(file)?)),
+            (Self::Harmonics(_), Some(name)) => match timbres.harmonics.find(name) {
+                Some(index) => Ok(Self::Harmonics(index)),
+                None => Err(bad()),
+            },
+            (Self::Wavetable(_) | Self::Harmonics(_), None) => Err(bad()),
+            (_, None) => Ok(wavetype),
+            (Self::Fm { .. }, Some(args)) => {
+                let (ratio, index) = args.split_once(',').ok_or_else(bad)?;
+                let ratio = ratio.parse::<f64>().map_err(|_| bad())?;
+                let index = index.parse::<f64>().map_err(|_| bad())?;
+                if !ratio.is_finite() || ratio <= 0.0 || !index.is_finite() || index < 0.0 {
+                    return Err(bad());
+                }
+                Ok(Self::Fm { ratio, index })
+            }
+            (Self::RingMod { .. }, Some(ratio)) => match ratio.parse::<f64>() {
+                Ok(ratio) if ratio.is_finite() && ratio > 0.0 => Ok(Self::RingMod { ratio }),
+                _ => Err(bad()),
+            },
+            _ => Err(bad()),
+        }
+    }
+
+    // the waveform as it would be written in a note's waveform column, which
+    // can't be done for the timbres that a piece defines for itself
+    pub fn abbreviation(&self) -> Option<String> {
+        match self {
+            Self::Fm { ratio, index } => Some(format!("FM:{},{}", ratio, index)),
+            Self::RingMod { ratio } => Some(format!("RM:{}", ratio)),
+            Self::Wavetable(_) | Self::Harmonics(_) | Self::Sample(_) => None,
+            _ => WAVEFORMS
+                .iter()
+                .find(|(_, wavetype, _)| {
+                    std::mem::discriminant(wavetype) == std::mem::discriminant(self)
+                })
+                .map(|(abbr, _, _)| abbr.to_string()),
+        }
+    }
+}
+
+impl VelocityCurve {
+    pub fn apply(&self, ampl: f64) -> f64 {
+        match self {
+            Self::Linear => ampl,
+            Self::Exp => ampl.signum() * ampl * ampl,
+            Self::Sqrt => ampl.signum() * ampl.abs().sqrt(),
+        }
+    }
+}
+
+impl NoteStart {
+    pub fn new(input: &str, meter: &Meter) -> Result<Self, SyntaxErrorType> {
+        if input == "+" {
+            return Ok(Self::Follow);
+        }
+        let bad = || SyntaxErrorType::BadStartTime(input.to_string());
+        if let [measure, beat, tick] = input.split(':').collect::<Vec<_>>()[..] {
+            let measure = measure.parse().map_err(|_| bad())?;
+            let beat = beat.parse().map_err(|_| bad())?;
+            let tick = parse_ticks(tick).ok_or_else(bad)?;
+            return meter
+                .tick(measure, beat, tick)
+                .map(Self::Ticks)
+                .ok_or_else(bad);
+        }
+        match input.strip_prefix('@') {
+            Some(sample) => match sample.parse() {
+                Ok(n) => Ok(Self::Sample(n)),
+                Err(_) => Err(SyntaxErrorType::BadStartTime(input.to_string())),
+            },
+            None => match parse_ticks(input) {
+                Some(n) => Ok(Self::Ticks(meter.ticks(n))),
+                None => Err(SyntaxErrorType::BadStartTime(input.to_string())),
+            },
         }
     }
 }
 
 impl NoteDuration {
-    pub fn new(input: &str) -> Result<Self, SyntaxErrorType> {
-        match input {
-            "TS" => Ok(Self { ticks: 2 }),
-            "DTS" => Ok(Self { ticks: 3 }),
-            "S" => Ok(Self { ticks: 4 }),
-            "DS" => Ok(Self { ticks: 6 }),
-            "E" => Ok(Self { ticks: 8 }),
-            "DE" => Ok(Self { ticks: 12 }),
-            "Q" => Ok(Self { ticks: 16 }),
-            "DQ" => Ok(Self { ticks: 24 }),
-            "H" => Ok(Self { ticks: 32 }),
-            "DH" => Ok(Self { ticks: 48 }),
-            "W" => Ok(Self { ticks: 64 }),
-            _ => match input.parse() {
-                Ok(ticks) => Ok(Self { ticks }),
-                Err(_) => Err(SyntaxErrorType::BadDuration(input.to_owned())),
+    // parse a duration, reading a bare number as that many ticks at the given
+    // resolution (in ticks per quarter note); durations joined by `+` or `~`
+    // (e.g. `H+E`, or `Q~Q`) are tied into one, as long as all of them
+    pub fn new(input: &str, ppq: u32) -> Result<Self, SyntaxErrorType> {
+        let bad = || SyntaxErrorType::BadDuration(input.to_owned());
+        if input.contains(['+', '~']) {
+            let mut ticks = 0.0;
+            for part in input.split(['+', '~']) {
+                ticks += Self::new(part, ppq).map_err(|_| bad())?.ticks;
+            }
+            return Ok(Self { ticks });
+        }
+        match DURATIONS.iter().find(|(abbr, _, _)| *abbr == input) {
+            Some(&(_, _, ticks)) => Ok(Self {
+                ticks: ticks as f64,
+            }),
+            None => match input.split_once('/') {
+                Some((abbr, tuplet)) if abbr.starts_with(char::is_alphabetic) => {
+                    Self::from_tuplet(abbr, tuplet).ok_or_else(bad)
+                }
+                Some((num, den)) => Self::from_fraction(num, den).ok_or_else(bad),
+                None => match input.parse::<f64>() {
+                    Ok(ticks) if ticks.is_finite() && ticks >= 0.0 => Ok(Self {
+                        ticks: scale_ticks(ticks, ppq),
+                    }),
+                    _ => Err(bad()),
+                },
             },
         }
     }
+
+    // the same duration, scaled by `ratio`
+    pub fn scaled(&self, ratio: f64) -> Self {
+        Self {
+            ticks: self.ticks * ratio,
+        }
+    }
+
+    // a fraction of a whole note (64 ticks), kept exact like a tuplet rather
+    // than rounded to whole ticks, so `1/256` is a quarter of a tick; only a
+    // fraction of nothing at all is refused
+    fn from_fraction(num: &str, den: &str) -> Option<Self> {
+        let num = num.parse::<u32>().ok()? as f64;
+        let den = den.parse::<u32>().ok()? as f64;
+        if den == 0.0 || num == 0.0 {
+            return None;
+        }
+        Some(Self {
+            ticks: 64.0 * num / den,
+        })
+    }
+
+    // one of `n` notes of the given value played in the time of `m`, written
+    // `<abbr>/<n>:<m>`, or just `<abbr>/<n>` to play them in the time of the
+    // largest power of two below `n` (so `Q/3` is a quarter-note triplet)
+    fn from_tuplet(abbr: &str, tuplet: &str) -> Option<Self> {
+        let &(_, _, ticks) = DURATIONS.iter().find(|(a, _, _)| *a == abbr)?;
+        let (n, m) = parse_tuplet(tuplet)?;
+        Some(Self {
+            ticks: ticks as f64 * m as f64 / n as f64,
+        })
+    }
 }
 
-impl NoteSignal {
+// parse the `n:m` of a tuplet (n notes in the time of m), where `m` can be left
+// out to mean the largest power of two below `n`
+pub fn parse_tuplet(input: &str) -> Option<(u32, u32)> {
+    let (n, m) = match input.split_once(':') {
+        Some((n, m)) => (n.parse::<u32>().ok()?, m.parse::<u32>().ok()?),
+        None => {
+            let n = input.parse::<u32>().ok()?;
+            (n, 1 << (n.max(2) - 1).ilog2())
+        }
+    };
+    if n < 2 || m == 0 {
+        return None;
+    }
+    Some((n, m))
+}
+
+impl Tremolo {
+    // parse the arguments of a `TREM:<rate>,<depth>` modifier, or those of a
+    // `TREM` directive, joined up with a comma
     pub fn new(input: &str) -> Result<Self, SyntaxErrorType> {
+        let bad = || SyntaxErrorType::BadModifier(format!("TREM:{}", input));
+        let (rate, depth) = input.split_once(',').ok_or_else(bad)?;
+        let rate = rate.parse::<f64>().map_err(|_| bad())?;
+        let depth = depth.parse::<f64>().map_err(|_| bad())?;
+        if !rate.is_finite() || rate < 0.0 || !(0.0..=1.0).contains(&depth) {
+            return Err(bad());
+        }
+        Ok(Self { rate, depth })
+    }
+
+    // the factor to scale a signal's amplitude by, `t` seconds after its onset
+    pub fn gain(&self, t: f64) -> f64 {
+        1.0 - self.depth / 2.0 + (self.depth / 2.0) * f64::sin(2.0 * PI * self.rate * t)
+    }
+}
+
+impl Vibrato {
+    // parse the arguments of a `VIB:<rate>,<depth>` modifier, or those of a
+    // `VIB` directive, joined up with a comma
+    pub fn new(input: &str) -> Result<Self, SyntaxErrorType> {
+        let bad = || SyntaxErrorType::BadModifier(format!("VIB:{}", input));
+        let (rate, depth) = input.split_once(',').ok_or_else(bad)?;
+        let rate = rate.parse::<f64>().map_err(|_| bad())?;
+        let depth = depth.parse::<f64>().map_err(|_| bad())?;
+        if !rate.is_finite() || rate < 0.0 || !depth.is_finite() || depth < 0.0 {
+            return Err(bad());
+        }
+        Ok(Self { rate, depth })
+    }
+
+    // the factor to scale a signal's frequency by, `t` seconds after its onset
+    pub fn factor(&self, t: f64) -> f64 {
+        2.0f64.powf(self.depth * f64::sin(2.0 * PI * self.rate * t) / 1200.0)
+    }
+}
+
+impl Unison {
+    // parse the arguments of a `UNISON:<voices>,<detune>` modifier, or those
+    // of a `UNISON` directive, joined up with a comma
+    pub fn new(input: &str) -> Result<Self, SyntaxErrorType> {
+        let bad = || SyntaxErrorType::BadModifier(format!("UNISON:{}", input));
+        let (voices, detune) = input.split_once(',').ok_or_else(bad)?;
+        let voices = voices.parse::<u32>().map_err(|_| bad())?;
+        let detune = detune.parse::<f64>().map_err(|_| bad())?;
+        if !(1..=MAX_UNISON).contains(&voices) || !detune.is_finite() || detune < 0.0 {
+            return Err(bad());
+        }
+        Ok(Self { voices, detune })
+    }
+
+    // the factor to scale the frequency of the given voice by
+    pub fn factor(&self, voice: u32) -> f64 {
+        if self.voices < 2 {
+            return 1.0;
+        }
+        let spread = 2.0 * voice as f64 / (self.voices - 1) as f64 - 1.0;
+        2.0f64.powf(spread * self.detune / 1200.0)
+    }
+}
+
+impl NoteSignal {
+    // a silent signal, which still counts towards the length of the piece and
+    // of any section that a `GOTO` repeats
+    pub fn rest(start: NoteStart, duration: NoteDuration) -> Self {
+        Self {
+            start,
+            duration,
+            gate: 1.0,
+            pitch: Pitch::Note(Note {
+                pitch_class: PitchClass::A,
+                octave: 4,
+                cents: 0.0,
+            }),
+            bend: None,
+            ornament: None,
+            humanize: Humanize::default(),
+            ampl: 0.0,
+            wavetype: WaveType::Sine,
+            pan: 0.0,
+            speakers: Speakers::default(),
+            tremolo: None,
+            envelope: None,
+            vibrato: None,
+            unison: None,
+            filter: None,
+            filter_envelope: None,
+            track: 0,
+        }
+    }
+
+    // how many ticks the signal sounds for, as opposed to how many it takes up
+    // in the rhythm
+    pub fn sounding_ticks(&self) -> f64 {
+        self.duration.ticks * self.gate
+    }
+
+    // the same signal with its pitch (at both ends of any bend, and that of
+    // any ornament) moved by the given number of semitones, unless that would
+    // take it below the lowest octave
+    pub fn transposed(&self, semitones: i32) -> Option<Self> {
+        let pitch = self.pitch.transpose(semitones)?;
+        let bend = match self.bend {
+            Some(bend) => Some(bend.transpose(semitones)?),
+            None => None,
+        };
+        let ornament = match self.ornament {
+            Some(ornament) => Some(ornament.transpose(semitones)?),
+            None => None,
+        };
+        Some(Self {
+            pitch,
+            bend,
+            ornament,
+            ..*self
+        })
+    }
+
+    // parse a line of the score into its signals: just the one for a note or a
+    // rest, but one per note for a chord symbol, all sharing the same columns;
+    // the defaults fill in whatever the line leaves out
+    pub fn parse(
+        input: &str,
+        key: &KeySignature,
+        notation: Notation,
+        voicing: &Voicing,
+        meter: &Meter,
+        defaults: &Defaults,
+        timbres: &mut Timbres,
+    ) -> Result<Vec<Self>, SyntaxErrorType> {
         let parts = input.split_whitespace().collect::<Vec<_>>();
-        let start = match parts.get(0) {
-            Some(s) => match s.parse() {
-                Ok(n) => n,
-                Err(_) => return Err(SyntaxErrorType::BadStartTime(s.to_string())),
-            },
+        let start = match parts.first() {
+            Some(s) => NoteStart::new(s, meter)?,
             None => return Err(SyntaxErrorType::MissingEntry),
         };
-        let duration = match parts.get(1) {
-            Some(s) => NoteDuration::new(s)?,
+        // an articulation comes straight after the duration
+        let (duration, gate) = match parts.get(1) {
+            Some(s) => match ARTICULATIONS.iter().find(|(c, _, _)| s.ends_with(*c)) {
+                Some(&(c, _, gate)) => (NoteDuration::new(s.trim_end_matches(c), meter.ppq)?, gate),
+                None => (NoteDuration::new(s, meter.ppq)?, 1.0),
+            },
             None => return Err(SyntaxErrorType::MissingEntry),
         };
-        let note = match parts.get(2) {
-            Some(s) => Note::new(s)?,
-            None => return Err(SyntaxErrorType::MissingEntry),
+        // a rest takes up time without sounding, so it needs nothing more
+        if parts.get(2) == Some(&"R") {
+            return Ok(vec![Self::rest(start, duration)]);
+        }
+        // a grace note is written before the note that it leads into, joined
+        // to it by a comma (e.g. `gD4,C4`)
+        let (grace, column) = match parts.get(2).map(|s| (s.strip_prefix('g'), s)) {
+            Some((Some(rest), s)) => match rest.split_once(',') {
+                Some((grace, main)) => (Some(defaults.pitch(grace, key, notation)?), Some(main)),
+                None => return Err(SyntaxErrorType::BadOrnament(s.to_string())),
+            },
+            Some((None, s)) => (None, Some(*s)),
+            None => (None, None),
         };
-        let ampl = match parts.get(3) {
-            Some(s) => match s.parse() {
-                Ok(n) => n,
-                Err(_) => return Err(SyntaxErrorType::BadAmplitude(s.to_string())),
+        // anything that isn't a note might still be a chord symbol, but if it
+        // isn't one either, the note's error is the more helpful of the two
+        let (pitches, bend) = match column.map(|s| (s, s.split_once('>'))) {
+            Some((_, Some((from, to)))) => (
+                vec![defaults.pitch(from, key, notation)?],
+                Some(defaults.pitch(to, key, notation)?),
+            ),
+            Some((s, None)) => match defaults.pitch(s, key, notation) {
+                Ok(pitch) => (vec![pitch], None),
+                Err(e) => {
+                    let notes = chord_notes(s, voicing).map_err(|_| e)?;
+                    (notes.into_iter().map(Pitch::Note).collect(), None)
+                }
             },
             None => return Err(SyntaxErrorType::MissingEntry),
         };
-        let wavetype = match parts.get(4) {
-            Some(s) => WaveType::new(s)?,
+        let ampl = match (parts.get(3), defaults.ampl) {
+            (Some(s), _) => match parse_amplitude(s) {
+                Some(n) => n,
+                None => return Err(SyntaxErrorType::BadAmplitude(s.to_string())),
+            },
+            (None, Some(ampl)) => ampl,
+            (None, None) => return Err(SyntaxErrorType::MissingEntry),
+        };
+        // an instrument brings its own waveform, and a patch to fill in
+        // whatever the note doesn't set for itself; a preset instrument can
+        // also be written by name alone, if no waveform has that name
+        let wave = parts.get(4).copied().or(defaults.wave.as_deref());
+        let (wavetype, patch) = match wave.map(|s| (s, s.strip_prefix("I:"))) {
+            Some((_, Some(name))) => match timbres.instrument(name) {
+                Some(instrument) => (instrument.wavetype, Some(instrument.patch)),
+                None => return Err(SyntaxErrorType::BadInstrument(name.to_string())),
+            },
+            Some((s, None)) => match WaveType::new(s, timbres) {
+                Ok(wavetype) => (wavetype, None),
+                Err(e) if PRESETS.iter().any(|(name, _, _, _)| *name == s) => {
+                    let instrument = timbres.instrument(s).ok_or(e)?;
+                    (instrument.wavetype, Some(instrument.patch))
+                }
+                Err(e) => return Err(e),
+            },
             None => return Err(SyntaxErrorType::MissingEntry),
         };
-        Ok(Self {
+        // a bare number in the sixth column is the pan
+        let pan = match parts.get(5).map(|s| s.parse::<f64>()) {
+            Some(Ok(pan)) if (-1.0..=1.0).contains(&pan) => Some(pan),
+            Some(Ok(_)) => return Err(SyntaxErrorType::BadPan(parts[5].to_string())),
+            _ => None,
+        };
+        // anything after that is a modifier of the form NAME:ARGS
+        let mut tremolo = None;
+        let mut envelope = None;
+        let mut vibrato = None;
+        let mut unison = None;
+        let mut filter = None;
+        let mut ornament = None;
+        let mut speakers = None;
+        for part in parts.iter().skip(5 + pan.is_some() as usize) {
+            let (name, args) = part.split_once(':').unwrap_or((part, ""));
+            match MODIFIERS
+                .iter()
+                .find(|(n, _, _, _)| n.eq_ignore_ascii_case(name))
+            {
+                Some((_, Modifier::Tremolo, _, _)) => tremolo = Some(Tremolo::new(args)?),
+                Some((_, Modifier::Envelope, _, _)) => {
+                    envelope = Some(Envelope::new(&args.split(',').collect::<Vec<_>>())?)
+                }
+                Some((_, Modifier::Vibrato, _, _)) => vibrato = Some(Vibrato::new(args)?),
+                Some((_, Modifier::Unison, _, _)) => unison = Some(Unison::new(args)?),
+                Some((_, Modifier::Filter, _, _)) => {
+                    filter = Some(Filter::new(&args.split(',').collect::<Vec<_>>())?)
+                }
+                // an ornament is only worked out once the note's pitch is
+                // known, since a chord's notes each have their own
+                Some(&(
+                    _,
+                    modifier @ (Modifier::Trill | Modifier::Mordent | Modifier::Turn),
+                    _,
+                    _,
+                )) => ornament = Some((modifier, args)),
+                Some((_, Modifier::Out, _, _)) => speakers = Some(Speakers::new(args)?),
+                None => return Err(SyntaxErrorType::BadModifier(part.to_string())),
+            }
+        }
+        let mut signal = Self {
             start,
             duration,
-            note,
+            gate,
+            pitch: pitches[0],
+            bend,
+            ornament: None,
+            humanize: Humanize::default(),
             ampl,
             wavetype,
-        })
+            pan: pan.or(defaults.pan).unwrap_or(0.0),
+            speakers: speakers.or(defaults.out).unwrap_or_default(),
+            tremolo,
+            envelope,
+            vibrato,
+            unison,
+            filter,
+            filter_envelope: None,
+            track: 0,
+        };
+        if let Some(patch) = patch {
+            patch.apply(&mut signal);
+        }
+        // a note can have a grace note or another ornament, but not both, and
+        // a chord has no single note for a grace note to lead into
+        let ornament = |pitch| match (grace, ornament) {
+            (Some(_), Some(_)) => Err(SyntaxErrorType::BadOrnament(parts[2].to_string())),
+            (Some(_), None) if pitches.len() > 1 => {
+                Err(SyntaxErrorType::BadOrnament(parts[2].to_string()))
+            }
+            (Some(grace), None) => Ok(Some(Ornament::Grace(grace))),
+            (None, Some((modifier, args))) => Ornament::new(modifier, args, &pitch, key).map(Some),
+            (None, None) => Ok(None),
+        };
+        pitches
+            .iter()
+            .map(|&pitch| {
+                Ok(Self {
+                    pitch,
+                    ornament: ornament(pitch)?,
+                    ..signal
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::meter::PPQ;
+
+    fn ticks(input: &str) -> f64 {
+        NoteDuration::new(input, PPQ)
+            .map(|d| d.ticks)
+            .unwrap_or(f64::NAN)
+    }
+
+    #[test]
+    fn fractions_match_abbreviations() {
+        assert_eq!(ticks("1/4"), 16.0);
+        assert_eq!(ticks("1/4"), ticks("Q"));
+        assert_eq!(ticks("3/16"), 12.0);
+        assert_eq!(ticks("3/16"), ticks("DE"));
+    }
+
+    #[test]
+    fn fractions_are_not_rounded() {
+        assert_eq!(ticks("1/256"), 0.25);
+        assert_eq!(ticks("1/3"), 64.0 / 3.0);
+    }
+
+    #[test]
+    fn empty_fractions_are_refused() {
+        assert!(NoteDuration::new("0/4", PPQ).is_err());
+        assert!(NoteDuration::new("1/0", PPQ).is_err());
     }
 }
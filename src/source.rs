@@ -0,0 +1,149 @@
+//! This module reads scores from disk, inflating them first if they are
+//! gzipped, and splices the files named by any `INCLUDE` directives into the
+//! score that includes them. Every line keeps track of the file and line it
+//! came from, so that an error can point at the right place.
+
+use crate::error::{MusicError, SyntaxErrorType};
+
+use flate2::read::GzDecoder;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+// a line of a score, with the file it was included from (or `None`, for the
+// main score) and its line number within that file
+pub struct SourceLine {
+    pub file: Option<String>,
+    pub number: usize,
+    pub text: String,
+}
+
+// read the score into a string, transparently inflating it first if it is
+// gzipped
+pub fn read_score(file: &str) -> Result<String, MusicError> {
+    match String::from_utf8(read_bytes(file)?) {
+        Ok(s) => Ok(s),
+        Err(_) => Err(MusicError::FileReadError(file.to_string())),
+    }
+}
+
+// read the bytes of a file, such as a MIDI file, transparently inflating them
+// first if it is gzipped (judging by either the extension or the gzip magic
+// bytes)
+pub fn read_bytes(file: &str) -> Result<Vec<u8>, MusicError> {
+    let bytes = match std::fs::read(file) {
+        Ok(b) => b,
+        Err(_) => return Err(MusicError::FileReadError(file.to_string())),
+    };
+
+    if file.ends_with(".gz") || bytes.starts_with(&[0x1f, 0x8b]) {
+        let mut input = vec![];
+        match GzDecoder::new(&bytes[..]).read_to_end(&mut input) {
+            Ok(_) => Ok(input),
+            Err(_) => Err(MusicError::DecompressError(file.to_string())),
+        }
+    } else {
+        Ok(bytes)
+    }
+}
+
+// the most lines that a score may add up to once its includes are read in,
+// counting the `INCLUDE` lines themselves, and the most times that files may
+// be included in all; a file that includes the next one twice over, and so on,
+// would otherwise double the score at every step
+pub const MAX_SOURCE_LINES: usize = 1_000_000;
+pub const MAX_INCLUDES: usize = 10_000;
+
+// the lines of the score at `path` (whose text is `input`), with every
+// `INCLUDE <file>` line replaced by the lines of that file, found relative to
+// the directory of the file that includes it
+pub fn expand_includes(input: &str, path: &Path) -> Result<Vec<SourceLine>, MusicError> {
+    let mut lines = vec![];
+    let mut open = path.canonicalize().into_iter().collect();
+    expand(input, path, None, &mut open, &mut (0, 0), &mut lines)?;
+    Ok(lines)
+}
+
+// the error for something wrong on the given line of the given file
+pub fn syntax_error(file: Option<&str>, line: usize, e: SyntaxErrorType) -> MusicError {
+    match file {
+        Some(file) => MusicError::IncludedSyntaxError(file.to_string(), line, e),
+        None => MusicError::SyntaxError(line, e),
+    }
+}
+
+// cut a line off at the start of any comment, which is a `#` or `//` at the
+// start of the line or after whitespace (so that the `#` of a sharp, as in
+// `C#4`, doesn't count)
+pub fn strip_comment(line: &str) -> &str {
+    let start = line.char_indices().find(|&(i, _)| {
+        let rest = &line[i..];
+        let starts_word = line[..i]
+            .chars()
+            .next_back()
+            .is_none_or(char::is_whitespace);
+        starts_word && (rest.starts_with('#') || rest.starts_with("//"))
+    });
+    match start {
+        Some((i, _)) => &line[..i],
+        None => line,
+    }
+}
+
+// `open` holds every file that is being included at the moment, so that one
+// that ends up including itself can be caught rather than recursing forever,
+// and `read` counts the lines and the included files read so far
+fn expand(
+    input: &str,
+    path: &Path,
+    file: Option<&str>,
+    open: &mut Vec<PathBuf>,
+    read: &mut (usize, usize),
+    lines: &mut Vec<SourceLine>,
+) -> Result<(), MusicError> {
+    for (n, text) in input.lines().enumerate() {
+        read.0 += 1;
+        if read.0 > MAX_SOURCE_LINES {
+            let shown = path.display().to_string();
+            return Err(syntax_error(
+                file,
+                n + 1,
+                SyntaxErrorType::IncludeTooLong(shown),
+            ));
+        }
+        let mut words = strip_comment(text).split_whitespace();
+        if words.next() != Some("INCLUDE") {
+            lines.push(SourceLine {
+                file: file.map(str::to_string),
+                number: n + 1,
+                text: text.to_string(),
+            });
+            continue;
+        }
+        let error = |e| syntax_error(file, n + 1, e);
+        let name = match words.collect::<Vec<_>>()[..] {
+            [name] => name,
+            ref args => return Err(error(SyntaxErrorType::BadInclude(args.join(" ")))),
+        };
+        let included = path.parent().unwrap_or(Path::new("")).join(name);
+        let shown = included.display().to_string();
+        let canonical = match included.canonicalize() {
+            Ok(canonical) => canonical,
+            Err(_) => return Err(error(SyntaxErrorType::BadInclude(shown))),
+        };
+        if open.contains(&canonical) {
+            return Err(error(SyntaxErrorType::IncludeCycle(shown)));
+        }
+        read.1 += 1;
+        if read.1 > MAX_INCLUDES {
+            return Err(error(SyntaxErrorType::IncludeTooLong(shown)));
+        }
+        let input = match read_score(&shown) {
+            Ok(input) => input,
+            Err(_) => return Err(error(SyntaxErrorType::BadInclude(shown))),
+        };
+        open.push(canonical);
+        expand(&input, &included, Some(&shown), open, read, lines)?;
+        open.pop();
+    }
+    Ok(())
+}
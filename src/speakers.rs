@@ -0,0 +1,91 @@
+//! This module defines the speaker layouts that a piece can be rendered for:
+//! mono, stereo, quad, 5.1 and 7.1, each channel of the output feeding one
+//! speaker. A note (or every note of a track) can be sent to particular
+//! speakers by name with `OUT`; a note that isn't sent to any of the speakers
+//! of the layout is panned between the front left and right ones, just as it
+//! is in stereo.
+
+use crate::error::SyntaxErrorType;
+
+// every speaker that a note can be sent to, by its name, with the bit that
+// stands for it in the channel mask of a WAV file
+pub const SPEAKERS: [(&str, u32, &str); 8] = [
+    ("FL", 0x1, "front left"),
+    ("FR", 0x2, "front right"),
+    ("FC", 0x4, "front centre"),
+    ("LFE", 0x8, "low-frequency effects"),
+    ("BL", 0x10, "back left"),
+    ("BR", 0x20, "back right"),
+    ("SL", 0x200, "side left"),
+    ("SR", 0x400, "side right"),
+];
+
+// the speaker that each channel of the output feeds, in the order that WAV
+// files interleave them, for every number of channels that can be rendered
+pub const LAYOUTS: [(u16, &[u32], &str); 5] = [
+    (1, &[0x4], "mono"),
+    (2, &[0x1, 0x2], "stereo"),
+    (4, &[0x1, 0x2, 0x10, 0x20], "quad"),
+    (6, &[0x1, 0x2, 0x4, 0x8, 0x10, 0x20], "5.1"),
+    (8, &[0x1, 0x2, 0x4, 0x8, 0x10, 0x20, 0x200, 0x400], "7.1"),
+];
+
+// the speakers that a note is sent to, as a channel mask; none at all (the
+// default) leaves it to its pan
+#[derive(Clone, Copy, Default, PartialEq)]
+pub struct Speakers(u32);
+
+impl Speakers {
+    // parse a list of speaker names separated by commas, e.g. `BL,BR`
+    pub fn new(input: &str) -> Result<Self, SyntaxErrorType> {
+        let bad = || SyntaxErrorType::BadSpeakers(input.to_string());
+        let mut mask = 0;
+        for name in input.split(',') {
+            let &(_, bit, _) = SPEAKERS
+                .iter()
+                .find(|(n, _, _)| *n == name)
+                .ok_or_else(bad)?;
+            mask |= bit;
+        }
+        Ok(Self(mask))
+    }
+
+    // how loud a note at the given pan is on each channel of the given
+    // layout: in full on every speaker that it's sent to, or otherwise turned
+    // down on the front left or right as it's panned away from that side, so
+    // that a centred note is as loud as in mono; a mono output plays it all
+    pub fn gains(&self, pan: f64, layout: &[u32]) -> Vec<f64> {
+        if layout.len() == 1 {
+            return vec![1.0];
+        }
+        if layout.iter().any(|bit| self.0 & bit != 0) {
+            return layout
+                .iter()
+                .map(|bit| if self.0 & bit != 0 { 1.0 } else { 0.0 })
+                .collect();
+        }
+        layout
+            .iter()
+            .map(|&bit| match bit {
+                0x1 => (1.0 - pan).min(1.0),
+                0x2 => (1.0 + pan).min(1.0),
+                _ => 0.0,
+            })
+            .collect()
+    }
+}
+
+// the speakers fed by the channels of an output with the given number of
+// channels, if it can be rendered
+pub fn layout(channels: u16) -> Option<&'static [u32]> {
+    LAYOUTS
+        .iter()
+        .find(|(count, _, _)| *count == channels)
+        .map(|&(_, layout, _)| layout)
+}
+
+// the channel mask of an output with the given number of channels, which says
+// which speaker each of its channels feeds
+pub fn channel_mask(channels: u16) -> u32 {
+    layout(channels).map_or(0, |layout| layout.iter().sum())
+}
@@ -0,0 +1,229 @@
+//! This module reads scores written as JSON or TOML rather than as text, which
+//! is handier for other programs to generate. A structured score holds the
+//! piece's settings, any directives, and its notes, either on their own or
+//! split into tracks; it is turned into the lines of an ordinary score, so
+//! that everything that a text score can do means the same thing here, and an
+//! error in it is reported against the setting or note that it came from.
+
+use crate::error::MusicError;
+use crate::formats::abc::parse_abc;
+use crate::formats::csv::parse_csv;
+use crate::formats::midi::parse_midi;
+use crate::formats::mml::parse_mml;
+use crate::formats::musicxml::parse_musicxml;
+use crate::piece::{MusicalPiece, PieceOptions};
+
+use serde::Deserialize;
+use std::fmt;
+use std::path::Path;
+
+// the formats that a score can be written in
+#[derive(Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum Format {
+    Text,
+    Json,
+    Toml,
+    Abc,
+    Mml,
+    Midi,
+    #[value(name = "musicxml")]
+    MusicXml,
+    Csv,
+}
+
+// a column that can be written either as a number or as a string, such as a
+// start (`0` or `"2:1:0"`) or an amplitude (`0.8` or `"mf"`)
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Value {
+    Number(f64),
+    Text(String),
+}
+
+// the whole score: the settings are just the directives of the same names,
+// and the notes outside of `tracks` go in the first track
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Score {
+    bpm: Option<f64>,
+    ampl: Option<u16>,
+    rate: Option<u32>,
+    key: Option<String>,
+    time: Option<String>,
+    ppq: Option<u32>,
+    swing: Option<Value>,
+    #[serde(default)]
+    directives: Vec<String>,
+    #[serde(default)]
+    notes: Vec<Note>,
+    #[serde(default)]
+    tracks: Vec<Track>,
+}
+
+// a track, with the defaults and effects that a `TRACK` line and an `FX` line
+// would give it, and any directives to apply before its notes
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Track {
+    name: String,
+    wave: Option<String>,
+    ampl: Option<Value>,
+    octave: Option<i32>,
+    pan: Option<f64>,
+    out: Option<String>,
+    fx: Option<String>,
+    #[serde(default)]
+    directives: Vec<String>,
+    #[serde(default)]
+    notes: Vec<Note>,
+}
+
+// a note, with the columns of a note line; as there, the amplitude and the
+// waveform can only be left out if the track has defaults for them, and the
+// pan and modifiers need the waveform before them
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Note {
+    start: Value,
+    duration: Value,
+    note: String,
+    ampl: Option<Value>,
+    wave: Option<String>,
+    pan: Option<f64>,
+    #[serde(default)]
+    modifiers: Vec<String>,
+}
+
+impl Format {
+    // the format of the score in the given file, judging by its extension
+    // (ignoring any `.gz` after it)
+    pub fn detect(file: &str) -> Self {
+        let file = file.strip_suffix(".gz").unwrap_or(file);
+        match Path::new(file).extension().and_then(|e| e.to_str()) {
+            Some("json") => Self::Json,
+            Some("toml") => Self::Toml,
+            Some("abc") => Self::Abc,
+            Some("mml") => Self::Mml,
+            Some("mid" | "midi") => Self::Midi,
+            Some("musicxml" | "xml") => Self::MusicXml,
+            Some("csv") => Self::Csv,
+            _ => Self::Text,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Number(n) => write!(f, "{}", n),
+            Self::Text(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+impl Note {
+    fn line(&self) -> String {
+        let mut columns = vec![
+            self.start.to_string(),
+            self.duration.to_string(),
+            self.note.clone(),
+        ];
+        columns.extend(self.ampl.as_ref().map(Value::to_string));
+        columns.extend(self.wave.clone());
+        columns.extend(self.pan.map(|pan| pan.to_string()));
+        columns.extend(self.modifiers.iter().cloned());
+        columns.join(" ")
+    }
+}
+
+impl Track {
+    fn line(&self) -> String {
+        let mut words = vec![format!("TRACK {}", self.name)];
+        words.extend(self.wave.as_ref().map(|wave| format!("WAVE:{}", wave)));
+        words.extend(self.ampl.as_ref().map(|ampl| format!("AMPL:{}", ampl)));
+        words.extend(self.octave.map(|octave| format!("OCTAVE:{}", octave)));
+        words.extend(self.pan.map(|pan| format!("PAN:{}", pan)));
+        words.extend(self.out.as_ref().map(|out| format!("OUT:{}", out)));
+        words.join(" ")
+    }
+}
+
+impl Score {
+    // the lines of the equivalent text score, each with a description of
+    // where in the structured score it came from
+    fn lines(&self) -> Vec<(String, String)> {
+        let mut lines = vec![];
+        let settings = [
+            ("bpm", "BPM", self.bpm.map(|bpm| bpm.to_string())),
+            ("ampl", "AMPL", self.ampl.map(|ampl| ampl.to_string())),
+            ("rate", "RATE", self.rate.map(|rate| rate.to_string())),
+            ("key", "KEY", self.key.clone()),
+            ("time", "TIME", self.time.clone()),
+            ("ppq", "PPQ", self.ppq.map(|ppq| ppq.to_string())),
+            ("swing", "SWING", self.swing.as_ref().map(Value::to_string)),
+        ];
+        for (name, keyword, value) in settings {
+            if let Some(value) = value {
+                lines.push((format!("{} {}", keyword, value), name.to_string()));
+            }
+        }
+        for (i, directive) in self.directives.iter().enumerate() {
+            lines.push((directive.clone(), format!("directives[{}]", i)));
+        }
+        for (i, note) in self.notes.iter().enumerate() {
+            lines.push((note.line(), format!("notes[{}]", i)));
+        }
+        for (i, track) in self.tracks.iter().enumerate() {
+            let at = format!("tracks[{}]", i);
+            lines.push((track.line(), at.clone()));
+            if let Some(fx) = &track.fx {
+                lines.push((format!("FX {}", fx), format!("{}.fx", at)));
+            }
+            for (j, directive) in track.directives.iter().enumerate() {
+                lines.push((directive.clone(), format!("{}.directives[{}]", at, j)));
+            }
+            for (j, note) in track.notes.iter().enumerate() {
+                lines.push((note.line(), format!("{}.notes[{}]", at, j)));
+            }
+        }
+        lines
+    }
+}
+
+// parse a score in the given format, which is in the file at `path` (where
+// any files that it includes are looked for, relative to); a text score goes
+// straight to `MusicalPiece::new()`, and other notations to their readers (but
+// as a MIDI file is binary, it is best read with `parse_midi()` from its bytes)
+pub fn parse_score(
+    input: &str,
+    format: Format,
+    path: &Path,
+    options: &PieceOptions,
+) -> Result<MusicalPiece, MusicError> {
+    let file = path.display().to_string();
+    let score: Score = match format {
+        Format::Json => serde_json::from_str(input)
+            .map_err(|e| MusicError::StructureError(file.clone(), e.to_string()))?,
+        Format::Toml => toml::from_str(input)
+            .map_err(|e| MusicError::StructureError(file.clone(), e.to_string()))?,
+        Format::Text => return MusicalPiece::new(input, path, options),
+        Format::Abc => return parse_abc(input, path, options),
+        Format::Mml => return parse_mml(input, path, options),
+        Format::Midi => return parse_midi(input.as_bytes(), path, options, &[]),
+        Format::MusicXml => return parse_musicxml(input, path, options),
+        Format::Csv => return parse_csv(input, path, options),
+    };
+    let lines = score.lines();
+    let text = lines
+        .iter()
+        .map(|(line, _)| line.as_str())
+        .collect::<Vec<_>>()
+        .join("\n");
+    MusicalPiece::new(&text, path, options).map_err(|e| match e {
+        MusicError::SyntaxError(n, e) => match lines.get(n.wrapping_sub(1)) {
+            Some((_, at)) => MusicError::StructuredSyntaxError(at.clone(), e),
+            None => MusicError::SyntaxError(n, e),
+        },
+        e => e,
+    })
+}
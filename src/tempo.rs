@@ -0,0 +1,241 @@
+//! This module defines the tempo map of a piece: the tempo that it starts at,
+//! and any changes of tempo at later ticks, which between them decide the
+//! sample that each tick falls on. A change can either hold until the next one
+//! or ramp smoothly towards it, for an accelerando or a ritardando. The map can
+//! also swing the piece, stretching the first of each pair of eighths (or
+//! sixteenths) at the expense of the second. All the conversions between ticks
+//! and samples go through here, so that nothing else has to assume that the
+//! tempo holds steady, or that the ticks are evenly spaced.
+
+use crate::error::SyntaxErrorType;
+
+// each change of tempo as the tick it takes effect on, the new tempo in beats
+// per minute, and whether the tempo then moves linearly (tick by tick) towards
+// that of the next change, rather than holding; they are kept in order of
+// tick, and the first is always at tick 0
+#[derive(Clone)]
+pub struct TempoMap {
+    changes: Vec<(f64, f64, bool)>,
+    swing: Option<Swing>,
+}
+
+// a swung feel, where each pair of notes of `pair / 2` ticks is played long
+// then short, the first taking up `ratio` of the time that the two share; so a
+// ratio of 0.5 is straight, and 2/3 is the lilt of a triplet shuffle
+#[derive(Clone, Copy)]
+pub struct Swing {
+    ratio: f64,
+    pair: f64,
+}
+
+impl Swing {
+    // a ratio has to leave some time for both notes of the pair
+    pub fn new(ratio: f64, pair: f64) -> Option<Self> {
+        if !(ratio > 0.0 && ratio < 1.0 && pair.is_finite() && pair > 0.0) {
+            return None;
+        }
+        Some(Self { ratio, pair })
+    }
+
+    // parse `<ratio> [E|S]`, swinging eighths unless told to swing sixteenths
+    pub fn parse(args: &[&str]) -> Result<Self, SyntaxErrorType> {
+        let bad = || SyntaxErrorType::BadSwing(args.join(" "));
+        let (ratio, pair) = match args[..] {
+            [ratio] | [ratio, "E"] => (ratio, 16.0),
+            [ratio, "S"] => (ratio, 8.0),
+            _ => return Err(bad()),
+        };
+        let ratio = ratio.parse().map_err(|_| bad())?;
+        Self::new(ratio, pair).ok_or_else(bad)
+    }
+
+    // the tick that the given one is played on, once swung; the start of each
+    // pair stays put, and the ticks within it are stretched over the first
+    // half and squeezed over the second
+    fn apply(&self, tick: f64) -> f64 {
+        let start = (tick / self.pair).floor() * self.pair;
+        let offset = (tick - start) / self.pair;
+        start
+            + self.pair
+                * match offset < 0.5 {
+                    true => offset * 2.0 * self.ratio,
+                    false => self.ratio + (offset - 0.5) * 2.0 * (1.0 - self.ratio),
+                }
+    }
+
+    // the tick that is played on the given one once swung, which undoes
+    // `apply()`
+    fn undo(&self, tick: f64) -> f64 {
+        let start = (tick / self.pair).floor() * self.pair;
+        let offset = (tick - start) / self.pair;
+        start
+            + self.pair
+                * match offset < self.ratio {
+                    true => offset / (2.0 * self.ratio),
+                    false => 0.5 + (offset - self.ratio) / (2.0 * (1.0 - self.ratio)),
+                }
+    }
+}
+
+impl TempoMap {
+    pub fn new(bpm: f64) -> Self {
+        Self {
+            changes: vec![(0.0, bpm, false)],
+            swing: None,
+        }
+    }
+
+    // swing every pair of notes through the whole piece
+    pub fn swing(&mut self, swing: Swing) {
+        self.swing = Some(swing);
+    }
+
+    // change the tempo from the given tick on, in place of any change already
+    // made at that very tick
+    pub fn set(&mut self, tick: f64, bpm: f64) {
+        match self.changes.iter().position(|&(t, _, _)| t >= tick) {
+            Some(i) if self.changes[i].0 == tick => self.changes[i] = (tick, bpm, false),
+            Some(i) => self.changes.insert(i, (tick, bpm, false)),
+            None => self.changes.push((tick, bpm, false)),
+        }
+    }
+
+    // move the tempo linearly from whatever it is at the tick `from` to `bpm`
+    // at the tick `to`, and hold it there; any changes in between are lost
+    pub fn ramp(&mut self, from: f64, to: f64, bpm: f64) {
+        let start = self.bpm(from);
+        self.changes.retain(|&(t, _, _)| t <= from || t >= to);
+        self.set(from, start);
+        self.set(to, bpm);
+        if let Some(change) = self.changes.iter_mut().find(|(t, _, _)| *t == from) {
+            change.2 = true;
+        }
+    }
+
+    // each change of tempo, as the tick it takes effect on, the tempo, and
+    // whether it ramps towards the next change
+    pub fn changes(&self) -> &[(f64, f64, bool)] {
+        &self.changes
+    }
+
+    // the tick that the given tick is played on once any swing is applied, in
+    // the evenly spaced ticks that the changes of tempo fall on
+    pub fn swung(&self, tick: f64) -> f64 {
+        self.swing.map_or(tick, |swing| swing.apply(tick))
+    }
+
+    // the tempo in force at the given tick
+    pub fn bpm(&self, tick: f64) -> f64 {
+        let i = self.segment(tick);
+        let (start, bpm, ramp) = self.changes[i];
+        match self.changes.get(i + 1) {
+            Some(&(next, target, _)) if ramp => {
+                bpm + (target - bpm) * (tick - start) / (next - start)
+            }
+            _ => bpm,
+        }
+    }
+
+    pub fn samples_per_tick(&self, tick: f64, rate: u32) -> f64 {
+        60.0 * rate as f64 / (16.0 * self.bpm(tick))
+    }
+
+    // the (possibly fractional) sample that the given tick falls on
+    pub fn position(&self, tick: f64, rate: u32) -> f64 {
+        match self.swing {
+            Some(swing) => self.straight_position(swing.apply(tick), rate),
+            None => self.straight_position(tick, rate),
+        }
+    }
+
+    // the (possibly fractional) tick that the given sample falls on, which
+    // undoes `position()`
+    pub fn tick(&self, position: f64, rate: u32) -> f64 {
+        match self.swing {
+            Some(swing) => swing.undo(self.straight_tick(position, rate)),
+            None => self.straight_tick(position, rate),
+        }
+    }
+
+    // how many (possibly fractional) samples the given number of ticks lasts
+    // for, starting from the tick `from`
+    pub fn length(&self, from: f64, ticks: f64, rate: u32) -> f64 {
+        let ramping = self.changes[self.segment(from)].2;
+        if ramping
+            || self.swing.is_some()
+            || self
+                .changes
+                .iter()
+                .any(|&(t, _, _)| from < t && t < from + ticks)
+        {
+            return self.position(from + ticks, rate) - self.position(from, rate);
+        }
+        ticks * 60.0 * rate as f64 / (16.0 * self.bpm(from))
+    }
+
+    // the sample that the given tick falls on, leaving aside any swing
+    fn straight_position(&self, tick: f64, rate: u32) -> f64 {
+        let mut position = 0.0;
+        for i in 0..self.changes.len() {
+            match self.changes.get(i + 1) {
+                Some(&(next, _, _)) if tick > next => position += self.advance(i, next, rate),
+                _ => return position + self.advance(i, tick, rate),
+            }
+        }
+        position
+    }
+
+    // the tick that the given sample falls on, leaving aside any swing, which
+    // undoes `straight_position()`
+    fn straight_tick(&self, position: f64, rate: u32) -> f64 {
+        let mut reached = 0.0;
+        for i in 0..self.changes.len() {
+            if let Some(&(next, _, _)) = self.changes.get(i + 1) {
+                let length = self.advance(i, next, rate);
+                if position > reached + length {
+                    reached += length;
+                    continue;
+                }
+            }
+            return self.retreat(i, position - reached, rate);
+        }
+        0.0
+    }
+
+    // the index of the change in force at the given tick
+    fn segment(&self, tick: f64) -> usize {
+        self.changes
+            .iter()
+            .rposition(|&(t, _, _)| t <= tick)
+            .unwrap_or(0)
+    }
+
+    // how many samples it takes to get from the `i`th change to the given
+    // tick, which is no later than the next change; over a ramp, the time per
+    // tick is the reciprocal of a linear function, which integrates to a log
+    fn advance(&self, i: usize, tick: f64, rate: u32) -> f64 {
+        let (start, bpm, ramp) = self.changes[i];
+        match self.changes.get(i + 1) {
+            Some(&(next, target, _)) if ramp && target != bpm => {
+                let slope = (target - bpm) / (next - start);
+                let reached = bpm + slope * (tick - start);
+                60.0 * rate as f64 / (16.0 * slope) * (reached / bpm).ln()
+            }
+            _ => (tick - start) * 60.0 * rate as f64 / (16.0 * bpm),
+        }
+    }
+
+    // the tick reached the given number of samples after the `i`th change,
+    // which undoes `advance()`
+    fn retreat(&self, i: usize, samples: f64, rate: u32) -> f64 {
+        let (start, bpm, ramp) = self.changes[i];
+        match self.changes.get(i + 1) {
+            Some(&(next, target, _)) if ramp && target != bpm => {
+                let slope = (target - bpm) / (next - start);
+                let reached = bpm * (samples * 16.0 * slope / (60.0 * rate as f64)).exp();
+                start + (reached - bpm) / slope
+            }
+            _ => start + samples * 16.0 * bpm / (60.0 * rate as f64),
+        }
+    }
+}
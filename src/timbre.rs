@@ -0,0 +1,72 @@
+//! This module gathers up the custom timbres that a piece can define for
+//! itself, which the waveforms that play them refer to by index, along with
+//! the instruments and waveforms it defines, which notes refer to by name.
+
+use crate::error::SyntaxErrorType;
+use crate::harmonics::Harmonics;
+use crate::instrument::Instrument;
+use crate::sampler::Sampler;
+use crate::signal::{WaveType, WAVEFORMS};
+use crate::wavetable::Wavetables;
+
+use std::collections::HashMap;
+
+#[derive(Default)]
+pub struct Timbres {
+    pub wavetables: Wavetables,
+    pub harmonics: Harmonics,
+    pub sampler: Sampler,
+    pub instruments: HashMap<String, Instrument>,
+    pub waves: HashMap<String, WaveType>,
+}
+
+impl Timbres {
+    // define the waveform given by the arguments of a `WAVEDEF` directive,
+    // which are a name followed by the amplitude of each sine partial in
+    // turn; it's an additive timbre that can be played by its name alone, so
+    // the name can't be one of the built-in abbreviations
+    pub fn define_wave(&mut self, args: &[&str]) -> Result<(), SyntaxErrorType> {
+        let bad = || SyntaxErrorType::BadHarmonics(args.join(" "));
+        let name = *args.first().filter(|name| is_free(name)).ok_or_else(bad)?;
+        self.harmonics.define(args)?;
+        let index = self.harmonics.find(name).ok_or_else(bad)?;
+        self.waves
+            .insert(name.to_string(), WaveType::Harmonics(index));
+        Ok(())
+    }
+
+    // the instrument with the given name: one the piece has defined, or else
+    // a preset, which is built the first time it's asked for
+    pub fn instrument(&mut self, name: &str) -> Option<Instrument> {
+        if let Some(&instrument) = self.instruments.get(name) {
+            return Some(instrument);
+        }
+        let preset = Instrument::preset(name, self)?;
+        self.instruments.insert(name.to_string(), preset);
+        Some(preset)
+    }
+
+    // load the sample given by the arguments of a `SAMPLE` directive, which
+    // are a name, a WAV file and (optionally) the note it sounds at, which is
+    // C4 unless given; it's played by its name alone, like a `WAVEDEF`
+    pub fn define_sample(&mut self, args: &[&str]) -> Result<(), SyntaxErrorType> {
+        let bad = || SyntaxErrorType::BadSample(args.join(" "));
+        let (name, file, root) = match args[..] {
+            [name, file] => (name, file, "C4"),
+            [name, file, root] => (name, file, root),
+            _ => return Err(bad()),
+        };
+        if !is_free(name) {
+            return Err(bad());
+        }
+        let index = self.sampler.load(file, root)?;
+        self.waves.insert(name.to_string(), WaveType::Sample(index));
+        Ok(())
+    }
+}
+
+// whether a name can be given to a waveform, which it can't if it would be
+// mistaken for a built-in one
+fn is_free(name: &str) -> bool {
+    !WAVEFORMS.iter().any(|(abbr, _, _)| *abbr == name) && !name.contains(':')
+}
@@ -0,0 +1,200 @@
+//! This module defines the tunings that notes can be played in. Equal
+//! temperament, which every piece uses unless it says otherwise, splits the
+//! octave into twelve equal semitones; the others tune each note by its step
+//! above a tonic: just intonation by small whole-number ratios, Pythagorean
+//! tuning by stacking pure fifths, and quarter-comma meantone by stacking
+//! fifths narrowed so that its major thirds are pure. The tonic itself keeps
+//! its equal-tempered pitch, so A4 is still 440 Hz in any tuning with A as
+//! its tonic. Any other scale can be read from a Scala (`.scl`) file, with as
+//! many degrees as it likes, each semitone of the notes being one degree.
+
+use crate::error::SyntaxErrorType;
+use crate::note::Note;
+
+#[derive(Clone, Copy, Default, PartialEq, clap::ValueEnum)]
+pub enum Temperament {
+    #[default]
+    Equal,
+    Just,
+    Pythagorean,
+    Meantone,
+}
+
+// every temperament, by the name it's written with
+pub const TEMPERAMENTS: [(&str, Temperament); 4] = [
+    ("equal", Temperament::Equal),
+    ("just", Temperament::Just),
+    ("pythagorean", Temperament::Pythagorean),
+    ("meantone", Temperament::Meantone),
+];
+
+// the ratio of each step above the tonic to the tonic, in five-limit just
+// intonation
+const JUST_RATIOS: [(f64, f64); 12] = [
+    (1.0, 1.0),
+    (16.0, 15.0),
+    (9.0, 8.0),
+    (6.0, 5.0),
+    (5.0, 4.0),
+    (4.0, 3.0),
+    (45.0, 32.0),
+    (3.0, 2.0),
+    (8.0, 5.0),
+    (5.0, 3.0),
+    (9.0, 5.0),
+    (15.0, 8.0),
+];
+
+// the usual reference pitch, that A4 is tuned to
+pub const DEFAULT_A4: f64 = 440.0;
+
+// a scale that notes are tuned to, built on a tonic (in semitones above C):
+// `steps` holds the cents of each degree above the tonic, and `period` those
+// of the interval that the scale repeats at, which is an octave for all but
+// some Scala scales; the scale is anchored to the tonic in octave 4, which
+// keeps its equal-tempered pitch, and each semitone above that is a degree
+// further up the scale; a scale with twelve degrees to the octave thus tunes
+// each note by its step above the tonic below it; `reference` is the
+// frequency of A4 in equal temperament, which everything else is tuned from
+#[derive(Clone, PartialEq)]
+pub struct Tuning {
+    temperament: Option<Temperament>,
+    steps: Vec<f64>,
+    period: f64,
+    pub tonic: i32,
+    pub reference: f64,
+}
+
+impl Default for Tuning {
+    fn default() -> Self {
+        Self::temperament(Temperament::Equal, 0)
+    }
+}
+
+impl Temperament {
+    // how many cents the given step (0 to 11 semitones) above the tonic is
+    fn cents(&self, step: i32) -> f64 {
+        match self {
+            Self::Equal => 100.0 * step as f64,
+            Self::Just => {
+                let (num, den) = JUST_RATIOS[step as usize];
+                1200.0 * (num / den).log2()
+            }
+            // the fifths run from the tonic's Db up to its F#, and in
+            // meantone from its Eb up to its G#, where the wolf fifth falls
+            Self::Pythagorean => stacked_fifths(step, 1200.0 * 1.5f64.log2(), -5),
+            Self::Meantone => stacked_fifths(step, 300.0 * 5.0f64.log2(), -3),
+        }
+    }
+}
+
+// the cents above the tonic of the step that a chain of twelve fifths of the
+// given size reaches, the chain starting `lowest` fifths below the tonic;
+// each fifth is brought back down into the octave above the tonic
+fn stacked_fifths(step: i32, fifth: f64, lowest: i32) -> f64 {
+    let fifths = (lowest..lowest + 12)
+        .find(|fifths| (7 * fifths).rem_euclid(12) == step)
+        .unwrap_or_default();
+    (fifths as f64 * fifth).rem_euclid(1200.0)
+}
+
+impl Tuning {
+    // one of the temperaments, built on the given tonic
+    pub fn temperament(temperament: Temperament, tonic: i32) -> Self {
+        Self {
+            temperament: Some(temperament),
+            steps: (0..12).map(|step| temperament.cents(step)).collect(),
+            period: 1200.0,
+            tonic,
+            reference: DEFAULT_A4,
+        }
+    }
+
+    // parse the arguments of a `TEMPERAMENT <name> [<tonic>]` directive; the
+    // tonic is a letter name with any accidental, and is C if left out
+    pub fn new(args: &[&str]) -> Result<Self, SyntaxErrorType> {
+        let bad = || SyntaxErrorType::BadTemperament(args.join(" "));
+        let (name, tonic) = match args[..] {
+            [name] => (name, "C"),
+            [name, tonic] => (name, tonic),
+            _ => return Err(bad()),
+        };
+        let temperament = match TEMPERAMENTS.iter().find(|(n, _)| *n == name) {
+            Some(&(_, temperament)) => temperament,
+            None => return Err(bad()),
+        };
+        let tonic = parse_tonic(tonic).ok_or_else(bad)?;
+        Ok(Self::temperament(temperament, tonic))
+    }
+
+    // parse a scale in the Scala (`.scl`) format, built on the given tonic:
+    // after any lines starting with `!`, which are comments, come a line
+    // describing the scale, the number of degrees that follow, and then each
+    // degree above the first, the last being the period; a degree is either
+    // in cents, if it has a `.` in it, or a ratio such as `5/4` or `2`
+    pub fn from_scala(input: &str, tonic: i32) -> Result<Self, String> {
+        let mut lines = input.lines().filter(|line| !line.starts_with('!'));
+        lines.next().ok_or("the scale has no description")?;
+        let count = lines
+            .next()
+            .and_then(|line| line.trim().parse::<usize>().ok())
+            .ok_or("the scale has no number of degrees")?;
+        let mut degrees = vec![0.0];
+        for line in lines.take(count) {
+            let value = line.split_whitespace().next().unwrap_or_default();
+            let bad = || format!("invalid degree: \"{}\"", value);
+            let cents = match value.contains('.') {
+                true => value.parse::<f64>().map_err(|_| bad())?,
+                false => {
+                    let (num, den) = value.split_once('/').unwrap_or((value, "1"));
+                    let num = num.parse::<u64>().map_err(|_| bad())?;
+                    let den = den.parse::<u64>().map_err(|_| bad())?;
+                    match num > 0 && den > 0 {
+                        true => 1200.0 * (num as f64 / den as f64).log2(),
+                        false => return Err(bad()),
+                    }
+                }
+            };
+            if !cents.is_finite() {
+                return Err(bad());
+            }
+            degrees.push(cents);
+        }
+        if degrees.len() != count + 1 {
+            return Err(format!("the scale has fewer than {} degrees", count));
+        }
+        let period = degrees.pop().unwrap_or_default();
+        if count == 0 || period <= 0.0 {
+            return Err("the scale doesn't repeat at an interval above its tonic".to_string());
+        }
+        Ok(Self {
+            temperament: None,
+            steps: degrees,
+            period,
+            tonic,
+            reference: DEFAULT_A4,
+        })
+    }
+
+    // the frequency of a note in this tuning, on top of any offset in cents
+    // that it has of its own
+    pub fn frequency(&self, note: &Note) -> f64 {
+        if self.temperament == Some(Temperament::Equal) {
+            return note.equal_tempered_at(self.reference);
+        }
+        let anchor = Note::from_semitones(48 + self.tonic).unwrap_or(*note);
+        let degree = note.semitones() - anchor.semitones();
+        let size = self.steps.len() as i32;
+        let cents = degree.div_euclid(size) as f64 * self.period
+            + self.steps[degree.rem_euclid(size) as usize]
+            + note.cents;
+        anchor.equal_tempered_at(self.reference) * 2.0f64.powf(cents / 1200.0)
+    }
+}
+
+// the semitones above C of a tonic written as a letter name with any
+// accidental (e.g. `Eb`)
+pub fn parse_tonic(tonic: &str) -> Option<i32> {
+    let note = Note::from_name(tonic, 4, &Default::default()).ok()?;
+    Some(note.semitones().rem_euclid(12))
+}
@@ -1,49 +1,308 @@
 //! This module contains the constants needed by wav-maker, such as header data
-//! and math constants, as well as helper functions for specific waveforms and
-//! for header generation.
+//! and default signal parameters, as well as helper functions for specific waveforms and
+//! for assembling the chunks of the output file.
+
+use crate::noise::Noise;
+use crate::speakers::channel_mask;
+
+use std::f64::consts::PI;
+use std::io::{self, Write};
 
 // the portions of the RIFF header corresponding to ASCII text
 pub const RIFF: [u8; 4] = [0x52, 0x49, 0x46, 0x46];
 pub const WAVE: [u8; 4] = [0x57, 0x41, 0x56, 0x45];
 pub const FMT: [u8; 4] = [0x66, 0x6D, 0x74, 0x20];
 pub const DATA: [u8; 4] = [0x64, 0x61, 0x74, 0x61];
+pub const FACT: [u8; 4] = [0x66, 0x61, 0x63, 0x74];
+pub const CUE: [u8; 4] = [0x63, 0x75, 0x65, 0x20];
+pub const LIST: [u8; 4] = [0x4C, 0x49, 0x53, 0x54];
+pub const ADTL: [u8; 4] = [0x61, 0x64, 0x74, 0x6C];
+pub const LABL: [u8; 4] = [0x6C, 0x61, 0x62, 0x6C];
+pub const INFO: [u8; 4] = [0x49, 0x4E, 0x46, 0x4F];
+pub const INAM: [u8; 4] = [0x49, 0x4E, 0x41, 0x4D];
+pub const IART: [u8; 4] = [0x49, 0x41, 0x52, 0x54];
+pub const ICMT: [u8; 4] = [0x49, 0x43, 0x4D, 0x54];
+pub const SMPL: [u8; 4] = [0x73, 0x6D, 0x70, 0x6C];
+
+// the MIDI note that a sampler plays a looped render back at its own pitch on
+pub const UNITY_NOTE: u32 = 60;
 
-// basic format constants (PCM, mono, 44.1kHz, 16-bit samples)
-pub const CHUNK_SIZE: u32 = 16;
+// basic format constants (PCM, 16-bit samples, 44.1kHz unless told otherwise)
 pub const FORMAT: u16 = 1;
-pub const CHANNELS: u16 = 1;
-pub const SAMPLE_RATE: u32 = 44100;
-pub const BITS_PER_SAMPLE: u16 = 16;
+pub const FLOAT_FORMAT: u16 = 3;
+pub const EXTENSIBLE_FORMAT: u16 = 0xFFFE;
+// the rest of the GUID of an extensible format's subformat, after its first
+// two bytes, which are the tag of the format that the samples are in
+pub const SUBFORMAT_GUID: [u8; 14] = [
+    0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xAA, 0x00, 0x38, 0x9B, 0x71,
+];
+pub const DEFAULT_SAMPLE_RATE: u32 = 44100;
+pub const DEFAULT_BIT_DEPTH: u16 = 16;
+pub const BIT_DEPTHS: [u16; 4] = [8, 16, 24, 32];
 
-// signal parameters and mathematical constants
+// signal parameters
 pub const DEFAULT_BPM: f64 = 100.0;
 pub const DEFAULT_AMPL: u16 = 2048;
-pub const PI: f64 = 3.141592653589793;
+pub const DEFAULT_CLICK_GUARD: f64 = 3.0;
+pub const DEFAULT_MAX_DURATION: f64 = 600.0;
+
+// the most notes that replaying sections of a piece may leave it with, which
+// keeps a huge repeat count from running out of memory before the length of
+// the piece is ever checked
+pub const MAX_SIGNALS: usize = 1_000_000;
+
+// the parts of the output format that can vary from piece to piece
+#[derive(Clone, Copy)]
+pub struct WavFormat {
+    pub channels: u16,
+    pub sample_rate: u32,
+    pub bits_per_sample: u16,
+    pub float: bool,
+}
+
+// the text that a render is tagged with, which players show alongside it
+#[derive(Clone, Default, PartialEq)]
+pub struct Metadata {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub comment: Option<String>,
+}
+
+impl Metadata {
+    // each piece of text that is given, with the id of its chunk
+    fn fields(&self) -> Vec<([u8; 4], &str)> {
+        [
+            (INAM, &self.title),
+            (IART, &self.artist),
+            (ICMT, &self.comment),
+        ]
+        .into_iter()
+        .filter_map(|(id, text)| text.as_deref().map(|text| (id, text)))
+        .collect()
+    }
+}
+
+// the dither added to integer samples as they're quantized: plain TPDF noise
+// (one LSB either way, triangularly distributed), or the same with the error
+// of each sample fed back into the next, pushing the noise up towards the top
+// of the spectrum where it's harder to hear
+#[derive(Clone, Copy, clap::ValueEnum)]
+pub enum Dither {
+    Tpdf,
+    Shaped,
+}
+
+// the waveforms are given as functions of their phase, in cycles, so that
+// their frequency is free to vary from one sample to the next
+pub fn sine_wave(phase: f64, ampl: f64) -> f64 {
+    ampl * f64::sin(2.0 * PI * phase)
+}
+
+pub fn square_wave(phase: f64, ampl: f64) -> f64 {
+    ampl * f64::signum(f64::sin(2.0 * PI * phase))
+}
+
+pub fn triangle_wave(phase: f64, ampl: f64) -> f64 {
+    ampl * (4.0 * (phase - (0.5 + phase).floor()).abs() - 1.0)
+}
+
+pub fn sawtooth_wave(phase: f64, ampl: f64) -> f64 {
+    2.0 * ampl * (phase - (0.5 + phase).floor())
+}
 
-pub fn sine_wave(n: u32, ampl: f64, freq: f64) -> f64 {
-    ampl * f64::sin(2.0 * PI * freq * n as f64 / SAMPLE_RATE as f64)
+// turn samples (where 1.0 is full scale) into little-endian data in the given
+// format; float samples are written as they are, with no clamping at all,
+// while integer ones are (optionally) dithered and clamped to the bit depth;
+// 8-bit PCM is unsigned, with silence at 128, while every other depth is signed
+pub fn quantize(samples: &[f64], format: &WavFormat, dither: Option<Dither>) -> Vec<u8> {
+    let bits_per_sample = format.bits_per_sample;
+    let full_scale = (1i64 << (bits_per_sample - 1)) as f64;
+    let bytes = bits_per_sample as usize / 8;
+    let channels = format.channels as usize;
+    let mut noise = Noise::new(0);
+    // the quantization error of the last sample on each channel
+    let mut errors = vec![0.0; channels];
+    let mut data = Vec::with_capacity(samples.len() * bytes);
+    for (n, &sample) in samples.iter().enumerate() {
+        if format.float {
+            data.extend_from_slice(&(sample as f32).to_le_bytes());
+            continue;
+        }
+        let mut exact = sample * full_scale;
+        let height = match dither {
+            None => exact.round(),
+            Some(dither) => {
+                let error = &mut errors[n % channels];
+                if let Dither::Shaped = dither {
+                    exact -= *error;
+                }
+                let height = (exact + (noise.white() + noise.white()) / 2.0).round();
+                *error = height - exact;
+                height
+            }
+        };
+        let height = height.clamp(-full_scale, full_scale - 1.0) as i64;
+        if bits_per_sample == 8 {
+            data.push((height + 128) as u8);
+        } else {
+            data.extend_from_slice(&height.to_le_bytes()[..bytes]);
+        }
+    }
+    data
 }
 
-pub fn square_wave(n: u32, ampl: f64, freq: f64) -> f64 {
-    ampl * f64::signum(f64::sin(2.0 * PI * freq * n as f64 / SAMPLE_RATE as f64))
+// the band-limited versions of the waveforms below smooth out each jump (or
+// kink, for the triangle) over the samples either side of it with a polynomial
+// (polyBLEP/polyBLAMP), which suppresses most of the aliasing of the naive
+// ones; `dt` is how far the phase advances from one sample to the next
+pub fn band_limited_square_wave(phase: f64, dt: f64, ampl: f64) -> f64 {
+    let t = phase.fract();
+    let naive = if t < 0.5 { 1.0 } else { -1.0 };
+    ampl * (naive + poly_blep(t, dt) - poly_blep((t + 0.5).fract(), dt))
 }
 
-pub fn triangle_wave(n: u32, ampl: f64, freq: f64) -> f64 {
-    ampl * (4.0
-        * ((n as f64 * freq) / SAMPLE_RATE as f64
-            - (0.5 + (n as f64 * freq) / SAMPLE_RATE as f64).floor())
-        .abs()
-        - 1.0)
+pub fn band_limited_triangle_wave(phase: f64, dt: f64, ampl: f64) -> f64 {
+    let t = phase.fract();
+    triangle_wave(phase, ampl)
+        + ampl * 4.0 * dt * (poly_blamp(t, dt) - poly_blamp((t + 0.5).fract(), dt))
 }
 
-pub fn sawtooth_wave(n: u32, ampl: f64, freq: f64) -> f64 {
-    2.0 * ampl
-        * ((n as f64 * freq) / SAMPLE_RATE as f64
-            - (0.5 + (n as f64 * freq) / SAMPLE_RATE as f64).floor())
+pub fn band_limited_sawtooth_wave(phase: f64, dt: f64, ampl: f64) -> f64 {
+    let t = (phase + 0.5).fract();
+    ampl * (2.0 * t - 1.0 - poly_blep(t, dt))
 }
 
-pub fn header(data_size: u32) -> Vec<u8> {
-    // header layout:
+// the correction for a jump at phase 0 of a waveform advancing `dt` per sample
+fn poly_blep(t: f64, dt: f64) -> f64 {
+    if t < dt {
+        let x = t / dt;
+        2.0 * x - x * x - 1.0
+    } else if t > 1.0 - dt {
+        let x = (t - 1.0) / dt;
+        x * x + 2.0 * x + 1.0
+    } else {
+        0.0
+    }
+}
+
+// the correction for a kink at phase 0, i.e. the integral of `poly_blep()`
+fn poly_blamp(t: f64, dt: f64) -> f64 {
+    if t < dt {
+        let x = t / dt - 1.0;
+        -x * x * x / 3.0
+    } else if t > 1.0 - dt {
+        let x = (t - 1.0) / dt + 1.0;
+        x * x * x / 3.0
+    } else {
+        0.0
+    }
+}
+
+// wrap a chunk body with its four-character id and its little-endian size,
+// padding the body to an even length as RIFF requires
+pub fn chunk(id: &[u8; 4], body: &[u8]) -> Vec<u8> {
+    let mut output = id.to_vec();
+    output.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    output.extend_from_slice(body);
+    if body.len() % 2 == 1 {
+        output.push(0);
+    }
+    output
+}
+
+// the "fact" chunk just holds the number of sample frames in the data chunk
+pub fn fact_chunk(data_size: u32, format: &WavFormat) -> Vec<u8> {
+    let block_align = (format.channels * format.bits_per_sample / 8) as u32;
+    chunk(&FACT, &(data_size / block_align).to_le_bytes())
+}
+
+// the "cue " chunk is a count followed by one 24-byte record per cue point:
+// - 4-byte cue point id
+// - 4-byte play order position
+// - "data" (the chunk the cue point lives in)
+// - 4-byte chunk start and 4-byte block start (both 0 for plain PCM)
+// - 4-byte sample offset of the cue point
+pub fn cue_chunk(cues: &[(u32, Option<String>)]) -> Vec<u8> {
+    let mut body = (cues.len() as u32).to_le_bytes().to_vec();
+    for (id, &(offset, _)) in cues.iter().enumerate() {
+        body.extend_from_slice(&(id as u32 + 1).to_le_bytes());
+        body.extend_from_slice(&offset.to_le_bytes());
+        body.extend_from_slice(&DATA);
+        body.extend_from_slice(&0u32.to_le_bytes());
+        body.extend_from_slice(&0u32.to_le_bytes());
+        body.extend_from_slice(&offset.to_le_bytes());
+    }
+    chunk(&CUE, &body)
+}
+
+// the "LIST" chunk of type "adtl" holds a "labl" chunk for each cue point that
+// has a name, made up of the cue point's id and the name, null-terminated
+pub fn label_chunk(cues: &[(u32, Option<String>)]) -> Vec<u8> {
+    let mut body = ADTL.to_vec();
+    for (id, (_, name)) in cues.iter().enumerate() {
+        if let Some(name) = name {
+            let mut labl = (id as u32 + 1).to_le_bytes().to_vec();
+            labl.extend_from_slice(name.as_bytes());
+            labl.push(0);
+            body.extend_from_slice(&chunk(&LABL, &labl));
+        }
+    }
+    chunk(&LIST, &body)
+}
+
+// the "smpl" chunk tells a sampler how to play the file as an instrument:
+// - 4-byte manufacturer and 4-byte product (both 0, for none in particular)
+// - 4-byte length of a sample frame in nanoseconds
+// - 4-byte MIDI unity note and 4-byte fraction of a semitone above it
+// - 4-byte SMPTE format and 4-byte SMPTE offset (both 0, for none)
+// - 4-byte loop count and 4-byte size of the sampler data after the loops (0)
+// followed by one 24-byte record per loop:
+// - 4-byte cue point id (0) and 4-byte loop type (0, for looping forwards)
+// - 4-byte start and 4-byte end sample frames, the end being the last played
+// - 4-byte fraction of a frame (0) and 4-byte play count (0, for forever)
+pub fn sampler_chunk(loops: &[(u32, u32)], format: &WavFormat) -> Vec<u8> {
+    let period = 1_000_000_000 / format.sample_rate;
+    let mut body = [0, 0, period, UNITY_NOTE, 0, 0, 0, loops.len() as u32, 0]
+        .iter()
+        .flat_map(|field| field.to_le_bytes())
+        .collect::<Vec<_>>();
+    for &(start, end) in loops {
+        for field in [0, 0, start, end, 0, 0] {
+            body.extend_from_slice(&field.to_le_bytes());
+        }
+    }
+    chunk(&SMPL, &body)
+}
+
+// the "LIST" chunk of type "INFO" holds a chunk for each piece of text that
+// the render is tagged with, each null-terminated
+pub fn info_chunk(metadata: &Metadata) -> Vec<u8> {
+    let mut body = INFO.to_vec();
+    for (id, text) in metadata.fields() {
+        let mut text = text.as_bytes().to_vec();
+        text.push(0);
+        body.extend_from_slice(&chunk(&id, &text));
+    }
+    chunk(&LIST, &body)
+}
+
+// write the whole output file around the sample data, optionally with a
+// "fact" chunk after "fmt " and any metadata before "data", and a "cue "
+// chunk after "data", followed by the names of any cue points that have them
+// and a "smpl" chunk if there are any loops; float data always gets a "fact"
+// chunk, as the format requires one for anything but plain PCM; the sample
+// data is written straight through rather than copied into the file first,
+// so the output can go anywhere, a pipe included
+pub fn write_wav<W: Write>(
+    output: &mut W,
+    data: &[u8],
+    format: &WavFormat,
+    fact: bool,
+    cues: &[(u32, Option<String>)],
+    metadata: &Metadata,
+    loops: &[(u32, u32)],
+) -> io::Result<()> {
+    // file layout:
     // - "RIFF"
     // - 4-byte size of the entire file below this point
     // - "WAVE"
@@ -55,22 +314,72 @@ pub fn header(data_size: u32) -> Vec<u8> {
     // - 4-byte bytes per second (derived from other header info)
     // - 2-byte block alignment
     // - 2-byte bit count per sample
-    // - "data"
-    let mut output = RIFF.to_vec();
-    let block_align = CHANNELS * BITS_PER_SAMPLE / 8;
-    let bytes_per_sec = SAMPLE_RATE * (block_align as u32);
-    let file_size = 36u32 + data_size;
-    output.extend_from_slice(&file_size.to_le_bytes());
-    output.extend_from_slice(&WAVE);
-    output.extend_from_slice(&FMT);
-    output.extend_from_slice(&CHUNK_SIZE.to_le_bytes());
-    output.extend_from_slice(&FORMAT.to_le_bytes());
-    output.extend_from_slice(&CHANNELS.to_le_bytes());
-    output.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
-    output.extend_from_slice(&bytes_per_sec.to_le_bytes());
-    output.extend_from_slice(&block_align.to_le_bytes());
-    output.extend_from_slice(&BITS_PER_SAMPLE.to_le_bytes());
-    output.extend_from_slice(&DATA);
-    output.extend_from_slice(&data_size.to_le_bytes());
-    output
+    // - 2-byte size of the format extension (0 for float data, 22 for more
+    //   than two channels, and left out otherwise)
+    // - for more than two channels, the extension: 2-byte valid bits per
+    //   sample, 4-byte channel mask, and the 16-byte GUID of the subformat,
+    //   which starts with the tag that would otherwise be the format tag
+    // - "fact" chunk, if requested
+    // - "LIST" chunk of type "INFO", if there is any metadata
+    // - "data" chunk
+    // - "cue " chunk, if there are any cue points
+    // - "LIST" chunk of type "adtl", if any of them have names
+    // - "smpl" chunk, if there are any loop points
+    let block_align = format.channels * format.bits_per_sample / 8;
+    let bytes_per_sec = format.sample_rate * (block_align as u32);
+    let tag = if format.float { FLOAT_FORMAT } else { FORMAT };
+    // players only know which speaker each channel feeds beyond stereo if
+    // they're told, which takes the extensible format
+    let extensible = format.channels > 2;
+    let mut fmt = match extensible {
+        true => EXTENSIBLE_FORMAT,
+        false => tag,
+    }
+    .to_le_bytes()
+    .to_vec();
+    fmt.extend_from_slice(&format.channels.to_le_bytes());
+    fmt.extend_from_slice(&format.sample_rate.to_le_bytes());
+    fmt.extend_from_slice(&bytes_per_sec.to_le_bytes());
+    fmt.extend_from_slice(&block_align.to_le_bytes());
+    fmt.extend_from_slice(&format.bits_per_sample.to_le_bytes());
+    if extensible {
+        fmt.extend_from_slice(&22u16.to_le_bytes());
+        fmt.extend_from_slice(&format.bits_per_sample.to_le_bytes());
+        fmt.extend_from_slice(&channel_mask(format.channels).to_le_bytes());
+        fmt.extend_from_slice(&tag.to_le_bytes());
+        fmt.extend_from_slice(&SUBFORMAT_GUID);
+    } else if format.float {
+        fmt.extend_from_slice(&0u16.to_le_bytes());
+    }
+
+    let mut head = WAVE.to_vec();
+    head.extend_from_slice(&chunk(&FMT, &fmt));
+    if fact || format.float {
+        head.extend_from_slice(&fact_chunk(data.len() as u32, format));
+    }
+    if !metadata.fields().is_empty() {
+        head.extend_from_slice(&info_chunk(metadata));
+    }
+    let mut tail = vec![];
+    if !cues.is_empty() {
+        tail.extend_from_slice(&cue_chunk(cues));
+    }
+    if cues.iter().any(|(_, name)| name.is_some()) {
+        tail.extend_from_slice(&label_chunk(cues));
+    }
+    if !loops.is_empty() {
+        tail.extend_from_slice(&sampler_chunk(loops, format));
+    }
+
+    // the "data" chunk is laid out by hand, the same way `chunk` would
+    let padding = &[0][..data.len() % 2];
+    let size = head.len() + 8 + data.len() + padding.len() + tail.len();
+    output.write_all(&RIFF)?;
+    output.write_all(&(size as u32).to_le_bytes())?;
+    output.write_all(&head)?;
+    output.write_all(&DATA)?;
+    output.write_all(&(data.len() as u32).to_le_bytes())?;
+    output.write_all(data)?;
+    output.write_all(padding)?;
+    output.write_all(&tail)
 }
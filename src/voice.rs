@@ -0,0 +1,269 @@
+//! This module defines Voice, the state that a single signal carries through
+//! synthesis: the span of samples it sounds over, plus whatever its waveform
+//! has to remember from one sample to the next, such as oscillator phases, a
+//! noise generator, the delay line of a plucked string, how far through a
+//! sample it has played or a filter's history. A voice that carries straight
+//! on from the one before it, at the same pitch on the same waveform, picks
+//! up that voice's phase rather than starting afresh, so that tied and
+//! adjacent notes join without a click.
+
+use crate::filter::{Biquad, Filter, FilterKind};
+use crate::noise::Noise;
+use crate::note::LOWEST_FREQUENCY;
+use crate::signal::{NoteSignal, WaveType};
+use crate::timbre::Timbres;
+use crate::tuning::Tuning;
+use crate::utils::*;
+
+use std::f64::consts::PI;
+
+pub struct Voice {
+    pub signal: NoteSignal,
+    // the samples that the voice starts on, is let go on, and has fallen
+    // silent by, which is later than when it's let go only while the release
+    // of its envelope rings on
+    pub start: u32,
+    pub held: u32,
+    pub end: u32,
+    // the voice's place among all those of the piece; whether it carries on
+    // from another voice, and the place of the one that carries on from it
+    pub id: usize,
+    pub carried: bool,
+    pub carries_to: Option<usize>,
+    // how loud the voice is on each channel of the output
+    pub gains: Vec<f64>,
+    noise: Noise,
+    // the frequency that the signal starts at, as tuned, and that of the end
+    // of any bend
+    freq: f64,
+    bend: Option<f64>,
+    // the factor that the voice's place in a unison detunes it by, and the
+    // share of the signal's amplitude that it gets
+    detune: f64,
+    share: f64,
+    // the phases (in cycles) of the carrier (or the fundamental of a custom
+    // timbre) and of the FM modulator
+    phase: f64,
+    mod_phase: f64,
+    // the delay line of a plucked string, and where in it we've got to
+    delay: Vec<f64>,
+    position: usize,
+    // how far (in its own samples) we've got through a sampled sound
+    cursor: f64,
+    biquad: Biquad,
+    // the filter that shapes the noise of a drum
+    drum: Biquad,
+}
+
+// how quickly (in seconds) a kick drum's pitch sweeps down, and the cutoffs of
+// the high-pass filters that the noise of a snare drum and a hi-hat go through
+const KICK_SWEEP: f64 = 0.03;
+const SNARE_CUTOFF: f64 = 1000.0;
+const HAT_CUTOFF: f64 = 7000.0;
+
+impl Voice {
+    // `index` is the voice's place in the signal's unison, if it has one, and
+    // the seed keeps each voice's noise distinct but reproducible
+    pub fn new(
+        signal: NoteSignal,
+        (start, held, end): (u32, u32, u32),
+        gains: Vec<f64>,
+        index: u32,
+        seed: u64,
+        tuning: &Tuning,
+    ) -> Self {
+        let voices = signal.unison.map_or(1, |unison| unison.voices);
+        Self {
+            signal,
+            start,
+            held,
+            end,
+            id: 0,
+            carried: false,
+            carries_to: None,
+            gains,
+            noise: Noise::new(seed),
+            freq: signal.pitch.frequency(tuning),
+            bend: signal.bend.map(|bend| bend.frequency(tuning)),
+            detune: signal.unison.map_or(1.0, |unison| unison.factor(index)),
+            share: 1.0 / voices as f64,
+            // stagger the voices of a unison so that they don't all start in
+            // phase with one another
+            phase: index as f64 / voices as f64,
+            mod_phase: 0.0,
+            delay: vec![],
+            position: 0,
+            cursor: 0.0,
+            biquad: Biquad::default(),
+            drum: Biquad::default(),
+        }
+    }
+
+    // the value of the (filtered) waveform at the given sample, at the given
+    // amplitude; this should be called once for each sample that the voice
+    // sounds over, as each call moves the oscillators on by a sample
+    pub fn oscillate(
+        &mut self,
+        sample: u32,
+        ampl: f64,
+        rate: u32,
+        band_limited: bool,
+        timbres: &Timbres,
+    ) -> f64 {
+        let mut freq = self.freq * self.detune;
+        // a bend glides evenly in pitch, so exponentially in frequency, from
+        // the note at the start of the signal to the target at its end, where
+        // it stays through any release
+        if let Some(bend) = self.bend {
+            let progress = (sample - self.start) as f64 / (self.held - self.start) as f64;
+            freq *= (bend / self.freq).powf(progress.min(1.0));
+        }
+        if let Some(vibrato) = self.signal.vibrato {
+            freq *= vibrato.factor((sample - self.start) as f64 / rate as f64);
+        }
+        let t = (sample - self.start) as f64 / rate as f64;
+        // a kick drum starts a couple of octaves above its pitch and drops
+        // quickly onto it
+        if let WaveType::Kick = self.signal.wavetype {
+            freq *= 1.0 + 3.0 * f64::exp(-t / KICK_SWEEP);
+        }
+        let phase = self.phase;
+        let dt = freq / rate as f64;
+        let mut value = match self.signal.wavetype {
+            WaveType::Sine => sine_wave(phase, 1.0),
+            WaveType::Square if band_limited => band_limited_square_wave(phase, dt, 1.0),
+            WaveType::Triangle if band_limited => band_limited_triangle_wave(phase, dt, 1.0),
+            WaveType::Sawtooth if band_limited => band_limited_sawtooth_wave(phase, dt, 1.0),
+            WaveType::Square => square_wave(phase, 1.0),
+            WaveType::Triangle => triangle_wave(phase, 1.0),
+            WaveType::Sawtooth => sawtooth_wave(phase, 1.0),
+            WaveType::WhiteNoise => self.noise.white(),
+            WaveType::PinkNoise => self.noise.pink(),
+            WaveType::BrownNoise => self.noise.brown(),
+            WaveType::Fm { ratio, index } => {
+                let value =
+                    f64::sin(2.0 * PI * phase + index * f64::sin(2.0 * PI * self.mod_phase));
+                self.mod_phase = (self.mod_phase + ratio * dt).fract();
+                value
+            }
+            // the product of the two sines, which sounds at their sum and
+            // difference frequencies rather than at either one
+            WaveType::RingMod { ratio } => {
+                let value = sine_wave(phase, 1.0) * sine_wave(self.mod_phase, 1.0);
+                self.mod_phase = (self.mod_phase + ratio * dt).fract();
+                value
+            }
+            WaveType::Wavetable(index) => timbres.wavetables.sample(index, phase),
+            WaveType::Harmonics(index) => timbres.harmonics.sample(index, phase, freq, rate),
+            WaveType::Pluck => {
+                // Karplus-Strong: fill a delay line one period long with a
+                // burst of noise (less its average, to avoid a DC offset), then
+                // keep playing it back, averaging neighbouring samples each time
+                // round, which damps the higher harmonics first; a bend or
+                // vibrato can take the pitch lower than any written one, so
+                // the line is never longer than one period of the lowest
+                if self.delay.is_empty() {
+                    let longest = (rate as f64 / LOWEST_FREQUENCY) as usize;
+                    let len = ((rate as f64 / freq).round() as usize).clamp(2, longest.max(2));
+                    self.delay = (0..len).map(|_| self.noise.white()).collect();
+                    let mean = self.delay.iter().sum::<f64>() / len as f64;
+                    self.delay.iter_mut().for_each(|s| *s -= mean);
+                }
+                let len = self.delay.len();
+                let value = self.delay[self.position];
+                self.delay[self.position] = (value + self.delay[(self.position + 1) % len]) / 2.0;
+                self.position = (self.position + 1) % len;
+                value
+            }
+            // the drums each die away on their own, however long the note
+            WaveType::Kick => sine_wave(phase, f64::exp(-t / 0.3)),
+            WaveType::Snare => {
+                let noise = self.drum_noise(sample, SNARE_CUTOFF, rate);
+                0.4 * sine_wave(phase, f64::exp(-t / 0.05)) + 0.6 * noise * f64::exp(-t / 0.15)
+            }
+            WaveType::HiHat => self.drum_noise(sample, HAT_CUTOFF, rate) * f64::exp(-t / 0.05),
+            WaveType::Sample(index) => {
+                let value = timbres.sampler.sample(index, self.cursor);
+                self.cursor += timbres.sampler.step(index, freq, rate);
+                value
+            }
+        };
+        self.phase = (self.phase + dt).fract();
+        // filter the bare waveform, before any change in its amplitude
+        // a filter envelope means retuning the filter on every sample, up to
+        // the end of the note, where it stays through any release
+        if let Some(filter) = self.signal.filter {
+            if let Some(sweep) = self.signal.filter_envelope {
+                let length = (self.held - self.start) as f64 / rate as f64;
+                let t = ((sample - self.start) as f64 / rate as f64).min(length);
+                self.biquad.tune(&sweep.sweep(&filter, t, length), rate);
+            } else if sample == self.start {
+                self.biquad.tune(&filter, rate);
+            }
+            value = self.biquad.process(value);
+        }
+        ampl * self.share * value
+    }
+
+    // whether this voice can carry straight on from `other`, which ends just
+    // as it starts: it needs an oscillator that runs on a phase, the same
+    // waveform, and the frequency that `other` ends on, in the same place in
+    // a unison of the same size; it also has to sound just as loud, on the
+    // same channels, for as much of its duration, with nothing (an envelope,
+    // a tremolo or a filter envelope) that would start its level or tone over
+    // again, since a joined voice gets no fade to hide a jump
+    pub fn continues(&self, other: &Voice) -> bool {
+        let restarts = |voice: &Voice| {
+            voice.signal.envelope.is_some()
+                || voice.signal.tremolo.is_some()
+                || voice.signal.filter_envelope.is_some()
+        };
+        let phased = matches!(
+            self.signal.wavetype,
+            WaveType::Sine
+                | WaveType::Square
+                | WaveType::Triangle
+                | WaveType::Sawtooth
+                | WaveType::Fm { .. }
+                | WaveType::RingMod { .. }
+                | WaveType::Wavetable(_)
+                | WaveType::Harmonics(_)
+        );
+        phased
+            && self.signal.wavetype == other.signal.wavetype
+            && self.freq == other.bend.unwrap_or(other.freq)
+            && self.detune == other.detune
+            && self.share == other.share
+            && self.signal.ampl == other.signal.ampl
+            && self.signal.gate == other.signal.gate
+            && self.gains == other.gains
+            && !restarts(self)
+            && !restarts(other)
+    }
+
+    // the oscillator state that passes from a voice to the one that carries
+    // on from it: its phases and its filter's history
+    pub fn handoff(&self) -> (f64, f64, Biquad) {
+        (self.phase, self.mod_phase, self.biquad)
+    }
+
+    pub fn take_over(&mut self, (phase, mod_phase, biquad): (f64, f64, Biquad)) {
+        self.phase = phase;
+        self.mod_phase = mod_phase;
+        self.biquad = biquad;
+    }
+
+    // white noise through the drum's high-pass filter
+    fn drum_noise(&mut self, sample: u32, cutoff: f64, rate: u32) -> f64 {
+        if sample == self.start {
+            let filter = Filter {
+                kind: FilterKind::HighPass,
+                cutoff,
+                resonance: std::f64::consts::FRAC_1_SQRT_2,
+            };
+            self.drum.tune(&filter, rate);
+        }
+        let noise = self.noise.white();
+        self.drum.process(noise)
+    }
+}
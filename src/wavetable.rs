@@ -0,0 +1,120 @@
+//! This module defines wavetables: single cycles of a custom waveform, loaded
+//! from a file and played back at the pitch of each note that uses them. A
+//! table file is either a plain list of numbers or a short WAV file, of which
+//! only the first channel is used.
+
+use crate::error::SyntaxErrorType;
+use crate::utils::{DATA, EXTENSIBLE_FORMAT, FLOAT_FORMAT, FMT, FORMAT, RIFF, WAVE};
+
+use std::collections::HashMap;
+
+// every table loaded so far, in the order they were loaded, so that a
+// waveform can refer to its table by index; each file is only loaded once
+#[derive(Default)]
+pub struct Wavetables {
+    files: HashMap<String, usize>,
+    tables: Vec<Vec<f64>>,
+}
+
+impl Wavetables {
+    // load the table in the given file (if it hasn't been already), returning
+    // its index
+    pub fn load(&mut self, file: &str) -> Result<usize, SyntaxErrorType> {
+        if let Some(&index) = self.files.get(file) {
+            return Ok(index);
+        }
+        let bytes = std::fs::read(file).map_err(|_| SyntaxErrorType::BadWavetable(file.into()))?;
+        let samples = if bytes.starts_with(&RIFF) {
+            read_wav(&bytes).map(|(samples, _)| samples)
+        } else {
+            String::from_utf8(bytes).ok().and_then(|text| {
+                text.split_whitespace()
+                    .map(|s| s.parse().ok())
+                    .collect::<Option<Vec<f64>>>()
+            })
+        };
+        let index = self
+            .add(samples.unwrap_or_default())
+            .ok_or_else(|| SyntaxErrorType::BadWavetable(file.into()))?;
+        self.files.insert(file.to_string(), index);
+        Ok(index)
+    }
+
+    // add a table of samples, scaled so that its peak is at full amplitude,
+    // returning its index; a table has to have some nonzero, finite sample
+    pub fn add(&mut self, mut samples: Vec<f64>) -> Option<usize> {
+        let peak = samples.iter().fold(0.0f64, |peak, s| peak.max(s.abs()));
+        if peak == 0.0 || !peak.is_finite() {
+            return None;
+        }
+        samples.iter_mut().for_each(|s| *s /= peak);
+        self.tables.push(samples);
+        Some(self.tables.len() - 1)
+    }
+
+    // the value of a table at the given phase (in cycles, from 0 to 1),
+    // interpolating linearly between its samples
+    pub fn sample(&self, index: usize, phase: f64) -> f64 {
+        let table = &self.tables[index];
+        let position = phase * table.len() as f64;
+        let i = position.floor() as usize % table.len();
+        let next = table[(i + 1) % table.len()];
+        table[i] + (next - table[i]) * position.fract()
+    }
+}
+
+// pull the first channel out of a WAV file's "data" chunk, along with its
+// sample rate, as long as its samples are 8/16/24/32-bit PCM or 32-bit float;
+// an extensible file (as renders of more than two channels are) says which of
+// those it holds in the tag at the start of its subformat
+pub fn read_wav(bytes: &[u8]) -> Option<(Vec<f64>, u32)> {
+    if bytes.get(8..12)? != WAVE {
+        return None;
+    }
+    let mut format = None;
+    let mut position = 12;
+    while position + 8 <= bytes.len() {
+        let id = &bytes[position..position + 4];
+        let size = u32::from_le_bytes(bytes[position + 4..position + 8].try_into().ok()?) as usize;
+        let body = bytes.get(position + 8..position + 8 + size)?;
+        if id == FMT {
+            let tag = match u16::from_le_bytes(body.get(0..2)?.try_into().ok()?) {
+                EXTENSIBLE_FORMAT => u16::from_le_bytes(body.get(24..26)?.try_into().ok()?),
+                tag => tag,
+            };
+            let channels = u16::from_le_bytes(body.get(2..4)?.try_into().ok()?) as usize;
+            let rate = u32::from_le_bytes(body.get(4..8)?.try_into().ok()?);
+            let bits = u16::from_le_bytes(body.get(14..16)?.try_into().ok()?);
+            format = Some((tag, channels, rate, bits));
+        } else if id == DATA {
+            let (tag, channels, rate, bits) = format?;
+            let width = bits as usize / 8;
+            let frame = channels * width;
+            if frame == 0 {
+                return None;
+            }
+            let samples = body
+                .chunks_exact(frame)
+                .map(|frame| decode(&frame[..width], tag, bits))
+                .collect::<Option<_>>()?;
+            return Some((samples, rate));
+        }
+        position += 8 + size + size % 2;
+    }
+    None
+}
+
+// a single sample of a WAV file as a value from -1.0 to 1.0
+fn decode(bytes: &[u8], tag: u16, bits: u16) -> Option<f64> {
+    match (tag, bits) {
+        (FLOAT_FORMAT, 32) => Some(f32::from_le_bytes(bytes.try_into().ok()?) as f64),
+        (FORMAT, 8) => Some((bytes[0] as f64 - 128.0) / 128.0),
+        (FORMAT, 16 | 24 | 32) => {
+            // sign-extend by filling the low bytes of an i32
+            let mut word = [0u8; 4];
+            word[4 - bytes.len()..].copy_from_slice(bytes);
+            Some(i32::from_le_bytes(word) as f64 / 2147483648.0)
+        }
+        _ => None,
+    }
+}